@@ -1,69 +1,212 @@
+use crate::cache::{CachedPage, ListingCache};
 use crate::canvas::ProcessOptions;
 use anyhow::{Error, Result};
+use chrono::{DateTime, Utc};
 use rand::Rng;
-use reqwest::{Response, Url, header};
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::{Response, ResponseBuilderExt, StatusCode, Url, header};
 use std::time::Duration;
 
-pub async fn get_pages(link: String, options: &ProcessOptions) -> Result<Vec<Response>> {
-    fn parse_next_page(resp: &Response) -> Option<String> {
-        // Parse LINK header
-        let links = resp.headers().get(header::LINK)?.to_str().ok()?; // ok to not have LINK header
-        let rels = parse_link_header::parse_with_rel(links).unwrap_or_else(|e| {
-            panic!(
-                "Error parsing header for next page, uri={}, err={e:?}",
+/// URL of the page after `resp` from its Link header, None on the last page.
+/// A malformed header ends the pagination with a warning, keeping the pages so far.
+pub fn next_page_link(resp: &Response) -> Option<String> {
+    // Parse LINK header
+    let links = resp.headers().get(header::LINK)?.to_str().ok()?; // ok to not have LINK header
+    let rels = match parse_link_header::parse_with_rel(links) {
+        Ok(rels) => rels,
+        Err(e) => {
+            tracing::warn!(
+                "Could not parse the Link header of {}, not following further pages: {e:?}",
                 resp.url()
-            )
-        });
-
-        // Is last page?
-        let nex = rels.get("next")?; // ok to not have "next"
-        let cur = rels
-            .get("current")
-            .unwrap_or_else(|| panic!("Could not find current page for {}", resp.url()));
-        let last = rels.get("last")?;
-        if cur == last {
+            );
             return None;
-        };
+        }
+    };
 
-        // Next page
-        Some(nex.raw_uri.clone())
-    }
+    // Is last page?
+    let nex = rels.get("next")?; // ok to not have "next"
+    let Some(cur) = rels.get("current") else {
+        tracing::warn!(
+            "No current page in the Link header of {}, not following further pages",
+            resp.url()
+        );
+        return None;
+    };
+    let last = rels.get("last")?;
+    if cur == last {
+        return None;
+    };
+
+    // Next page
+    Some(nex.raw_uri.clone())
+}
 
+pub async fn get_pages(link: String, options: &ProcessOptions) -> Result<Vec<Response>> {
+    get_pages_until(link, options, |_| false).await
+}
+
+/// Like get_pages, but stops paginating after a page whose body satisfies `last_page`,
+/// e.g. once a listing sorted newest first reaches items older than --since
+pub async fn get_pages_until(
+    link: String,
+    options: &ProcessOptions,
+    last_page: impl Fn(&str) -> bool,
+) -> Result<Vec<Response>> {
     let mut link = Some(link);
     let mut resps = Vec::new();
 
     while let Some(uri) = link {
         // GET request
-        let resp = get_canvas_api(uri, options).await?;
+        let mut resp = match options.listing_cache {
+            Some(ref cache) => get_cached_page(uri, cache, options).await?,
+            None => get_canvas_api(uri, options).await?,
+        };
 
         // Get next page before returning for json
-        link = parse_next_page(&resp);
+        link = next_page_link(&resp);
+        if link.is_some() && resp.status().is_success() {
+            // Reading the body consumes the response, so hand callers a copy
+            let status = resp.status();
+            let headers = resp.headers().clone();
+            let url = resp.url().clone();
+            let body = resp.text().await?;
+            if last_page(&body) {
+                link = None;
+            }
+            let mut builder = http::Response::builder().status(status).url(url);
+            if let Some(builder_headers) = builder.headers_mut() {
+                *builder_headers = headers;
+            }
+            resp = Response::from(builder.body(body)?);
+        }
         resps.push(resp);
     }
 
     Ok(resps)
 }
 
+/// Fetch a listing page, letting Canvas answer 304 Not Modified when it matches the cached one
+async fn get_cached_page(
+    url: String,
+    cache: &ListingCache,
+    options: &ProcessOptions,
+) -> Result<Response> {
+    let cached = cache.get(&url);
+    let mut headers = HeaderMap::new();
+    if let Some(cached) = cached {
+        if let Some(ref etag) = cached.etag
+            && let Ok(etag) = HeaderValue::from_str(etag)
+        {
+            headers.insert(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(ref last_modified) = cached.last_modified
+            && let Ok(last_modified) = HeaderValue::from_str(last_modified)
+        {
+            headers.insert(header::IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+    let resp = get_canvas_api_with_headers(url.clone(), headers, options).await?;
+
+    let page = match (resp.status(), cached) {
+        (StatusCode::NOT_MODIFIED, Some(cached)) => {
+            tracing::debug!("Listing unchanged since last run: {url}");
+            cached.clone()
+        }
+        (status, _) if status.is_success() => {
+            let header_string = |name| {
+                resp.headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string)
+            };
+            CachedPage {
+                etag: header_string(header::ETAG),
+                last_modified: header_string(header::LAST_MODIFIED),
+                link: header_string(header::LINK),
+                body: resp.text().await?,
+            }
+        }
+        _ => return Ok(resp),
+    };
+
+    // Hand callers a fresh response, whether the body came from Canvas or the cache
+    let mut builder = http::Response::builder()
+        .status(StatusCode::OK)
+        .url(Url::parse(&url)?);
+    if let Some(ref link) = page.link {
+        builder = builder.header(header::LINK, link);
+    }
+    let resp = Response::from(builder.body(page.body.clone())?);
+    if page.etag.is_some() || page.last_modified.is_some() {
+        cache.insert(&url, page);
+    }
+    Ok(resp)
+}
+
 pub async fn get_canvas_api(url: String, options: &ProcessOptions) -> Result<Response> {
+    get_canvas_api_with_headers(url, HeaderMap::new(), options).await
+}
+
+async fn get_canvas_api_with_headers(
+    url: String,
+    headers: HeaderMap,
+    options: &ProcessOptions,
+) -> Result<Response> {
     let mut query_pairs: Vec<(String, String)> = Vec::new();
     // insert into query_pairs from url.query_pairs();
     for (key, value) in Url::parse(&url)?.query_pairs() {
         query_pairs.push((key.to_string(), value.to_string()));
     }
-    for retry in 0..3 {
+    // Canvas defaults to 10 items per page, ask for more to save round trips
+    if !query_pairs.iter().any(|(key, _)| key == "per_page") {
+        query_pairs.push(("per_page".to_string(), options.per_page.to_string()));
+    }
+    let mut retry = 0;
+    let last_retry = options.retries.saturating_sub(1);
+    let mut retry_after_waited = Duration::ZERO;
+    while retry < options.retries {
         let resp = options
             .client
             .get(&url)
             .query(&query_pairs)
+            .headers(headers.clone())
             .bearer_auth(&options.canvas_token)
-            .timeout(Duration::from_secs(10))
+            .timeout(options.timeout)
             .send()
             .await;
 
-        match resp {
+        let status = match resp {
             Ok(resp) => {
-                if resp.status() == reqwest::StatusCode::FORBIDDEN {
-                    if retry == 2 {
+                let status = resp.status();
+                if status == StatusCode::TOO_MANY_REQUESTS {
+                    // Server told us how long to wait, which doesn't use up an attempt
+                    if let Some(wait_time) = parse_retry_after(&resp) {
+                        if retry_after_waited + wait_time <= MAX_RETRY_AFTER {
+                            tracing::debug!(
+                                "Rate limited (429) for {}, waiting {:?} as requested by Retry-After",
+                                url,
+                                wait_time
+                            );
+                            tokio::time::sleep(wait_time).await;
+                            retry_after_waited += wait_time;
+                            continue;
+                        }
+                        tracing::warn!(
+                            "Canvas keeps asking to wait before requesting {} again, already waited {:?}; backing off as usual",
+                            url,
+                            retry_after_waited
+                        );
+                    }
+                    if retry == last_retry {
+                        tracing::debug!(
+                            "Still rate limited (429) for {} after {} attempts",
+                            url,
+                            options.retries
+                        );
+                        return Ok(resp);
+                    }
+                } else if status == StatusCode::FORBIDDEN {
+                    if retry == last_retry {
                         // Log more specific error information on final retry
                         if url.contains("users") {
                             tracing::debug!(
@@ -84,26 +227,101 @@ pub async fn get_canvas_api(url: String, options: &ProcessOptions) -> Result<Res
                 } else {
                     return Ok(resp);
                 }
+                status
             }
             Err(e) => {
                 tracing::error!("Canvas request error uri: {} {}", url, e);
                 return Err(e.into());
             }
-        }
+        };
 
-        // Exponential backoff with jitter: base delay * 2^retry + random jitter
-        let base_delay = 500; // 500ms base delay
-        let exponential_delay = base_delay * 2_u64.pow(retry);
-        let jitter = rand::rng().random_range(0..=exponential_delay / 2);
-        let wait_time = Duration::from_millis(exponential_delay + jitter);
+        let wait_time = backoff_delay(retry);
 
         tracing::debug!(
-            "Rate limited (403) for {}, waiting {:?} before retry {}/3",
+            "Rate limited ({}) for {}, waiting {:?} before attempt {}/{}",
+            status.as_u16(),
             url,
             wait_time,
-            retry + 1
+            retry + 2,
+            options.retries
         );
         tokio::time::sleep(wait_time).await;
+        retry += 1;
     }
     Err(Error::msg("canvas request failed"))
 }
+
+/// Exponential backoff with jitter: base delay * 2^retry + random jitter, capped
+pub fn backoff_delay(retry: u32) -> Duration {
+    let base_delay: u64 = 500; // 500ms base delay
+    let max_delay: u64 = 60_000; // 60s max delay
+    let exponential_delay = base_delay
+        .saturating_mul(2_u64.saturating_pow(retry))
+        .min(max_delay);
+    let jitter = rand::rng().random_range(0..=exponential_delay / 2);
+    Duration::from_millis((exponential_delay + jitter).min(max_delay))
+}
+
+/// Longest a request waits in total as asked by `Retry-After` headers, so a server that keeps
+/// asking can't stall the sync; further 429s then count against --retries
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(30 * 60);
+
+/// Parse the `Retry-After` header, which is either a number of seconds or an HTTP-date
+fn parse_retry_after(resp: &Response) -> Option<Duration> {
+    let value = resp
+        .headers()
+        .get(header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    // A date in the past means we can retry right away
+    Some(
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response_with_link(link: &str) -> Response {
+        let resp = http::Response::builder()
+            .status(StatusCode::OK)
+            .url(Url::parse("https://canvas.example.com/api/v1/courses").expect("valid url"))
+            .header(header::LINK, link)
+            .body(String::new())
+            .expect("valid response");
+        Response::from(resp)
+    }
+
+    #[test]
+    fn follows_next_page() {
+        let resp = response_with_link(
+            r#"<https://canvas.example.com/api/v1/courses?page=1>; rel="current", <https://canvas.example.com/api/v1/courses?page=2>; rel="next", <https://canvas.example.com/api/v1/courses?page=3>; rel="last""#,
+        );
+        assert_eq!(
+            next_page_link(&resp).as_deref(),
+            Some("https://canvas.example.com/api/v1/courses?page=2")
+        );
+    }
+
+    #[test]
+    fn malformed_link_header_ends_pagination() {
+        let resp = response_with_link("not a link header; rel=");
+        assert_eq!(next_page_link(&resp), None);
+    }
+
+    #[test]
+    fn next_without_current_ends_pagination() {
+        let resp = response_with_link(
+            r#"<https://canvas.example.com/api/v1/courses?page=2>; rel="next", <https://canvas.example.com/api/v1/courses?page=3>; rel="last""#,
+        );
+        assert_eq!(next_page_link(&resp), None);
+    }
+}
@@ -0,0 +1,121 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Folder a run with --archive downloads into before packing it. It stays next to the
+/// archive until a run succeeds, so an interrupted or failed run resumes like a normal one.
+pub fn staging_folder(archive_path: &Path) -> PathBuf {
+    let file_name = archive_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    archive_path.with_file_name(format!(".{file_name}.parts"))
+}
+
+/// Pack the staging folder into the archive once files were downloaded, whether or not some
+/// items failed; the staging folder is only removed after a sync without errors. A run that
+/// downloaded nothing, e.g. when cancelled, leaves the archive as it was.
+pub fn finish_archive(
+    staging_folder: &Path,
+    archive_path: &Path,
+    synced: Result<()>,
+    downloaded: bool,
+) -> Result<()> {
+    if !downloaded || !staging_folder.exists() {
+        return synced;
+    }
+    let n_files = write_zip(staging_folder, archive_path)?;
+    if n_files > 0 {
+        status!(
+            "📦 {n_files} file{} archived into {}",
+            if n_files == 1 { "" } else { "s" },
+            archive_path.display()
+        );
+    }
+    if synced.is_ok() {
+        std::fs::remove_dir_all(staging_folder).with_context(|| {
+            format!(
+                "Could not remove staging folder {}",
+                staging_folder.display()
+            )
+        })?;
+    }
+    synced
+}
+
+/// Write every file below `folder` into a zip at `archive_path`, keeping their paths
+/// relative to `folder`. Hidden bookkeeping files and unfinished downloads are left out.
+/// Without any files, no archive is written.
+fn write_zip(folder: &Path, archive_path: &Path) -> Result<usize> {
+    let mut files = Vec::new();
+    collect_files(folder, &mut files)?;
+    if files.is_empty() {
+        return Ok(0);
+    }
+    files.sort();
+
+    // A crash halfway shouldn't replace a good archive from an earlier run
+    let tmp_path = archive_path.with_file_name(format!(
+        "{}.tmp",
+        archive_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+    ));
+    let archive = std::fs::File::create(&tmp_path)
+        .with_context(|| format!("Unable to create archive {:?}", tmp_path))?;
+    let mut zip = ZipWriter::new(archive);
+    for path in &files {
+        let Ok(relative) = path.strip_prefix(folder) else {
+            continue;
+        };
+        let name = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let metadata = std::fs::metadata(path)?;
+        let mut options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .large_file(metadata.len() >= u32::MAX as u64);
+        // Keep the modification times, which -n compares against Canvas
+        if let Ok(modified) = metadata.modified()
+            && let Ok(modified) =
+                zip::DateTime::try_from(DateTime::<Local>::from(modified).naive_local())
+        {
+            options = options.last_modified_time(modified);
+        }
+        zip.start_file(name, options)?;
+        let mut file =
+            std::fs::File::open(path).with_context(|| format!("Could not read file {:?}", path))?;
+        std::io::copy(&mut file, &mut zip)
+            .with_context(|| format!("Could not write {:?} into the archive", path))?;
+    }
+    zip.finish()?;
+    std::fs::rename(&tmp_path, archive_path)
+        .with_context(|| format!("Could not write to file {:?}", archive_path))?;
+    Ok(files.len())
+}
+
+fn collect_files(folder: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(folder)
+        .with_context(|| format!("Could not read directory {}", folder.display()))?
+    {
+        let entry = entry?;
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with('.') || name.ends_with(".tmp") {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
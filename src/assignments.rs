@@ -1,15 +1,21 @@
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
 
 use anyhow::{Context, Result};
 
 use crate::api::{get_canvas_api, get_pages};
+use crate::calendar::write_deadlines_ics;
 use crate::canvas::{Assignment, AssignmentResult, ProcessOptions, Submission};
 use crate::files::filter_files;
-use crate::html::process_html_links;
-use crate::utils::{create_folder_if_not_exist_or_ignored, get_raw_json_path, prettify_json};
+use crate::html::{process_html_links, save_content};
+#[cfg(feature = "pdf")]
+use crate::pdf::{CourseSection, add_to_course_pdf};
+use crate::utils::{
+    create_folder_if_not_exist_or_ignored, flatten_path, get_raw_json_path, html_escape, ignored,
+    prettify_json, sanitize_path_component, write_json_array,
+};
 
 pub async fn process_assignments(
     (url, path): (String, PathBuf),
@@ -23,6 +29,8 @@ pub async fn process_assignments(
 
     let mut has_assignments = false;
     let mut assignments_folder_path = None;
+    let mut deadlines = Vec::new();
+    let mut assignments_json = Vec::new(); // raw items of all pages for assignments.json
 
     for pg in pages {
         let uri = pg.url().to_string();
@@ -40,25 +48,16 @@ pub async fn process_assignments(
                     }
                     assignments_folder_path = Some(folder_path.clone());
                     has_assignments = true;
+                }
+                if has_assignments
+                    && options.save_json
+                    && let Ok(items) = serde_json::from_str::<Vec<serde_json::Value>>(&page_body)
+                {
+                    assignments_json.extend(items);
+                }
 
-                    // Create assignments.json file
-                    if let Some(assignments_json_path) = get_raw_json_path(
-                        &path,
-                        "assignments.json",
-                        &options.base_path,
-                        options.save_json,
-                    )? {
-                        let mut assignments_json_file =
-                            std::fs::File::create(assignments_json_path.clone()).with_context(
-                                || format!("Unable to create file for {:?}", assignments_json_path),
-                            )?;
-                        let pretty_json = prettify_json(&page_body).unwrap_or(page_body.clone());
-                        assignments_json_file
-                            .write_all(pretty_json.as_bytes())
-                            .with_context(|| {
-                                format!("Unable to write to file for {:?}", assignments_json_path)
-                            })?;
-                    }
+                if options.export_ics {
+                    deadlines.extend(assignments.iter().filter(|a| a.due_at.is_some()).cloned());
                 }
 
                 for assignment in assignments {
@@ -84,18 +83,57 @@ pub async fn process_assignments(
                 }
             }
             Ok(AssignmentResult::Err { status }) => {
-                tracing::error!(
-                    "Failed to access assignments at link:{uri}, path:{path:?}, status:{status}",
-                );
+                options.record_error(anyhow::anyhow!(
+                    "Failed to access assignments at link:{uri}, path:{path:?}, status:{status}"
+                ));
             }
             Err(e) => {
-                tracing::error!(
-                    "Error when getting assignments at link:{uri}, path:{path:?}\n{e:?}",
-                );
+                options.record_error(anyhow::anyhow!(
+                    "Error when getting assignments at link:{uri}, path:{path:?}\n{e:?}"
+                ));
             }
         }
     }
 
+    // Create assignments.json file
+    if has_assignments
+        && let Some(assignments_json_path) = get_raw_json_path(
+            &path,
+            "assignments.json",
+            &options.base_path,
+            options.save_json,
+        )?
+    {
+        write_json_array(&assignments_json_path, &assignments_json)?;
+    }
+
+    if !deadlines.is_empty() {
+        let course_code = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let deadlines = deadlines
+            .into_iter()
+            .map(|assignment| (course_code.clone(), assignment))
+            .collect::<Vec<_>>();
+
+        let ics_path = path.join("deadlines.ics");
+        if !ignored(
+            &ics_path,
+            false,
+            &options.base_path,
+            options.ignore_matcher.as_deref(),
+        ) {
+            write_deadlines_ics(&ics_path, &deadlines)?;
+        }
+        options
+            .deadlines
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .extend(deadlines);
+    }
+
     if has_assignments {
         tracing::debug!(
             "📝 Assignments synced for {}",
@@ -107,6 +145,41 @@ pub async fn process_assignments(
     Ok(())
 }
 
+/// Download one assignment, e.g. for --url: its page, the user's submission and linked files
+pub async fn process_assignment(
+    (url, assignment_id, path): (String, u32, PathBuf),
+    options: Arc<ProcessOptions>,
+) -> Result<()> {
+    let assignment_url = format!("{url}assignments/{assignment_id}");
+    let assignment = get_canvas_api(assignment_url.clone(), &options)
+        .await?
+        .json::<Assignment>()
+        .await
+        .with_context(|| format!("Error when getting assignment at link:{assignment_url}"))?;
+
+    let folder_path = path.join("assignments");
+    if !create_folder_if_not_exist_or_ignored(&folder_path, &options)? {
+        return Ok(());
+    }
+    let submissions_url = format!("{}assignments/{}/submissions/", url, assignment.id);
+    if let Some(ref desc) = assignment.description {
+        fork!(
+            process_html_links,
+            (desc.clone(), folder_path.clone(), assignment.name.clone()),
+            (String, PathBuf, String),
+            options.clone()
+        );
+    }
+    fork!(
+        process_submissions,
+        (submissions_url, folder_path, assignment),
+        (String, PathBuf, Assignment),
+        options.clone()
+    );
+    options.n_assignments.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
 fn generate_assignment_html(assignment: &Assignment) -> String {
     let mut html = String::new();
 
@@ -190,27 +263,111 @@ fn generate_assignment_html(assignment: &Assignment) -> String {
     html
 }
 
-fn html_escape(s: &str) -> String {
-    s.replace('&', "&amp;")
-        .replace('<', "&lt;")
-        .replace('>', "&gt;")
-        .replace('"', "&quot;")
-        .replace('\'', "&#39;")
+/// Plain-text summary of score, rubric feedback and comments; None if not graded yet
+fn generate_grade_text(assignment: &Assignment, submission: &Submission) -> Option<String> {
+    if submission.score.is_none() && submission.grade.is_none() {
+        return None;
+    }
+
+    let mut text = String::new();
+    text.push_str(&format!("Assignment: {}\n", assignment.name));
+    if let Some(score) = submission.score {
+        match assignment.points_possible {
+            Some(points_possible) => {
+                text.push_str(&format!("Score: {score} / {points_possible}\n"))
+            }
+            None => text.push_str(&format!("Score: {score}\n")),
+        }
+    }
+    if let Some(ref grade) = submission.grade {
+        text.push_str(&format!("Grade: {grade}\n"));
+    }
+
+    // Rubric, in the order the criteria are defined on the assignment
+    if let Some(ref rubric_assessment) = submission.rubric_assessment
+        && let Some(ref rubric) = assignment.rubric
+        && !rubric_assessment.is_empty()
+    {
+        text.push_str("\nRubric:\n");
+        for criterion in rubric {
+            let Some(assessment) = rubric_assessment.get(&criterion.id) else {
+                continue;
+            };
+            let description = criterion.description.as_deref().unwrap_or(&criterion.id);
+            let points = match (assessment.points, criterion.points) {
+                (Some(points), Some(max)) => format!("{points} / {max}"),
+                (Some(points), None) => points.to_string(),
+                (None, _) => "-".to_string(),
+            };
+            text.push_str(&format!("  - {description}: {points}\n"));
+            if let Some(ref comments) = assessment.comments
+                && !comments.trim().is_empty()
+            {
+                text.push_str(&format!("    {}\n", comments.trim()));
+            }
+        }
+    }
+
+    if !submission.submission_comments.is_empty() {
+        text.push_str("\nComments:\n");
+        for comment in &submission.submission_comments {
+            text.push_str(&format!(
+                "  [{}] {}: {}\n",
+                comment.created_at.as_deref().unwrap_or("-"),
+                comment.author_name.as_deref().unwrap_or("Unknown"),
+                comment.comment.as_deref().unwrap_or("").trim()
+            ));
+        }
+    }
+
+    Some(text)
 }
 
 async fn process_submissions(
     (url, path, assignment): (String, PathBuf, Assignment),
     options: Arc<ProcessOptions>,
 ) -> Result<()> {
-    let submissions_url = format!("{}{}", url, options.user.id);
+    let assignment_name = sanitize_path_component(&assignment.name, options.filename_mode);
+
+    // A failing submission shouldn't cost the assignment its page
+    if let Err(e) = process_submission(&url, &path, &assignment, &assignment_name, &options).await {
+        options.record_error(e);
+    }
+
+    // Generate HTML file for the assignment
+    let html_content = generate_assignment_html(&assignment);
+    let html_path = flatten_path(&path.join(format!("{assignment_name}.html")), &options);
+    save_content(&html_path, &html_content, &options).await?;
+    #[cfg(feature = "pdf")]
+    add_to_course_pdf(
+        CourseSection::Assignments,
+        &html_path,
+        &html_content,
+        &options,
+    );
+
+    Ok(())
+}
+
+/// Save the grade, raw JSON and attachments of the user's submission to one assignment
+async fn process_submission(
+    url: &str,
+    path: &Path,
+    assignment: &Assignment,
+    assignment_name: &str,
+    options: &Arc<ProcessOptions>,
+) -> Result<()> {
+    let submissions_url = format!(
+        "{}{}?include[]=rubric_assessment&include[]=submission_comments",
+        url, options.user.id
+    );
 
-    let resp = get_canvas_api(submissions_url.clone(), &options).await?;
+    let resp = get_canvas_api(submissions_url.clone(), options).await?;
     let submissions_body = resp.text().await?;
 
-    let assignment_name = sanitize_filename::sanitize(&assignment.name);
-    let assignment_folder_path = path.join(assignment_name.clone());
+    let assignment_folder_path = path.join(assignment_name);
     if let Some(submissions_json) = get_raw_json_path(
-        &path,
+        path,
         &format!("{assignment_name}.json"),
         &options.base_path,
         options.save_json,
@@ -224,37 +381,44 @@ async fn process_submissions(
             .with_context(|| format!("Unable to write to file for {:?}", submissions_json))?;
     }
 
-    if !options.skip_submissions {
-        let submissions_result = serde_json::from_str::<Submission>(&submissions_body);
-        match submissions_result {
-            Result::Ok(submissions) => {
-                let mut filtered_files =
-                    filter_files(&options, &assignment_folder_path, submissions.attachments);
-
-                if !filtered_files.is_empty() {
-                    // create folder for assignment if there are files to download
-                    create_folder_if_not_exist_or_ignored(&assignment_folder_path, &options)?;
-
-                    let mut lock = options.files_to_download.lock().await;
-                    lock.append(&mut filtered_files);
-                }
-            }
-            Result::Err(e) => {
-                tracing::error!(
-                    "Error when getting submissions at link:{submissions_url}, path:{path:?}\n{e:?}",
-                );
-            }
-        }
+    let submission = serde_json::from_str::<Submission>(&submissions_body).with_context(|| {
+        format!("Error when getting submissions at link:{submissions_url}, path:{path:?}")
+    })?;
+    if let Some(grade_text) = generate_grade_text(assignment, &submission)
+        && create_folder_if_not_exist_or_ignored(&assignment_folder_path, options)?
+    {
+        let grade_path = flatten_path(&assignment_folder_path.join("grade.txt"), options);
+        std::fs::write(&grade_path, grade_text)
+            .with_context(|| format!("Could not write to file {:?}", grade_path))?;
     }
 
-    // Generate HTML file for the assignment
-    let html_content = generate_assignment_html(&assignment);
-    let html_path = path.join(format!("{assignment_name}.html"));
-    let mut html_file = std::fs::File::create(html_path.clone())
-        .with_context(|| format!("Unable to create file for {:?}", html_path))?;
-    html_file
-        .write_all(html_content.as_bytes())
-        .with_context(|| format!("Could not write to file {:?}", html_path))?;
+    if !options.skip_submissions {
+        let mut filtered_files =
+            filter_files(options, &assignment_folder_path, submission.attachments);
 
+        if !filtered_files.is_empty() {
+            // create folder for assignment if there are files to download
+            create_folder_if_not_exist_or_ignored(&assignment_folder_path, options)?;
+
+            let mut lock = options.files_to_download.lock().await;
+            lock.append(&mut filtered_files);
+        }
+
+        // Files attached to comments, such as annotated feedback from the grader
+        let feedback_path = assignment_folder_path.join("feedback");
+        let comment_attachments = submission
+            .submission_comments
+            .into_iter()
+            .flat_map(|comment| comment.attachments)
+            .collect();
+        let mut filtered_files = filter_files(options, &feedback_path, comment_attachments);
+
+        if !filtered_files.is_empty()
+            && create_folder_if_not_exist_or_ignored(&feedback_path, options)?
+        {
+            let mut lock = options.files_to_download.lock().await;
+            lock.append(&mut filtered_files);
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use lazy_regex::regex_captures;
+use serde::{Deserialize, Serialize};
+
+use crate::canvas::FilenameMode;
+use crate::utils::sanitize_path_component;
+
+/// A listing page as Canvas returned it, with the validators to ask whether it changed
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CachedPage {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub link: Option<String>, // pagination header, to keep following the next pages
+    pub body: String,
+}
+
+/// Listing pages of each course from the last run, stored as <cache-dir>/<host>/<course id>.json
+pub struct ListingCache {
+    folder: PathBuf,
+    previous: HashMap<String, HashMap<String, CachedPage>>, // course id -> page URL -> page
+    current: Mutex<HashMap<String, HashMap<String, CachedPage>>>,
+}
+
+impl ListingCache {
+    pub fn load(cache_dir: &Path, canvas_url: &str) -> Result<Self> {
+        let host = reqwest::Url::parse(canvas_url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_else(|| canvas_url.to_string());
+        let folder = cache_dir.join(sanitize_path_component(&host, FilenameMode::Safe));
+        std::fs::create_dir_all(&folder)
+            .with_context(|| format!("Failed to create cache directory: {}", folder.display()))?;
+
+        let mut previous = HashMap::new();
+        for entry in std::fs::read_dir(&folder)?.flatten() {
+            let path = entry.path();
+            let (Some(course_id), Some("json")) = (
+                path.file_stem().and_then(|stem| stem.to_str()),
+                path.extension().and_then(|ext| ext.to_str()),
+            ) else {
+                continue;
+            };
+            // A corrupt cache only costs a full crawl
+            match std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|content| Ok(serde_json::from_str(&content)?))
+            {
+                Ok(pages) => {
+                    previous.insert(course_id.to_string(), pages);
+                }
+                Err(e) => tracing::warn!("Ignoring unreadable cache file {:?}: {e}", path),
+            }
+        }
+
+        Ok(ListingCache {
+            folder,
+            previous,
+            current: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Page fetched for `url` on the last run, if it belongs to a course
+    pub fn get(&self, url: &str) -> Option<&CachedPage> {
+        self.previous.get(course_id(url)?)?.get(url)
+    }
+
+    /// Remember a page fetched on this run, to be written by save()
+    pub fn insert(&self, url: &str, page: CachedPage) {
+        let Some(course_id) = course_id(url) else {
+            return;
+        };
+        self.current
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(course_id.to_string())
+            .or_default()
+            .insert(url.to_string(), page);
+    }
+
+    /// Replace the cache of every course crawled on this run; others keep their old cache
+    pub fn save(&self) -> Result<()> {
+        let current = self
+            .current
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        for (course_id, pages) in current.iter() {
+            let path = self.folder.join(format!("{course_id}.json"));
+            std::fs::write(&path, serde_json::to_string(pages)?)
+                .with_context(|| format!("Could not write to file {:?}", path))?;
+        }
+        Ok(())
+    }
+}
+
+fn course_id(url: &str) -> Option<&str> {
+    regex_captures!(r"/api/v1/courses/(\d+)/", url).map(|(_, course_id)| course_id)
+}
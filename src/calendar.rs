@@ -0,0 +1,81 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+
+use crate::canvas::Assignment;
+
+/// Write an iCalendar file with one event per assignment due date.
+/// Each entry is (course code, assignment); assignments without due_at are skipped.
+pub fn write_deadlines_ics(ics_path: &Path, deadlines: &[(String, Assignment)]) -> Result<()> {
+    let now = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+
+    let mut ics = String::new();
+    ics.push_str("BEGIN:VCALENDAR\r\n");
+    ics.push_str("VERSION:2.0\r\n");
+    ics.push_str(&format!(
+        "PRODID:-//{}//{}//EN\r\n",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    ));
+    ics.push_str("CALSCALE:GREGORIAN\r\n");
+
+    for (course_code, assignment) in deadlines {
+        let Some(due_at) = assignment
+            .due_at
+            .as_deref()
+            .and_then(|due_at| DateTime::parse_from_rfc3339(due_at).ok())
+        else {
+            continue;
+        };
+        let due_at = due_at.with_timezone(&Utc).format("%Y%m%dT%H%M%SZ");
+
+        ics.push_str("BEGIN:VEVENT\r\n");
+        push_line(
+            &mut ics,
+            &format!(
+                "UID:assignment-{}@{}",
+                assignment.id,
+                env!("CARGO_PKG_NAME")
+            ),
+        );
+        push_line(&mut ics, &format!("DTSTAMP:{now}"));
+        push_line(&mut ics, &format!("DTSTART:{due_at}"));
+        push_line(&mut ics, &format!("DTEND:{due_at}"));
+        push_line(
+            &mut ics,
+            &format!(
+                "SUMMARY:{}",
+                escape_text(&format!("{course_code}: {}", assignment.name))
+            ),
+        );
+        if let Some(ref html_url) = assignment.html_url {
+            push_line(&mut ics, &format!("URL:{html_url}"));
+        }
+        ics.push_str("END:VEVENT\r\n");
+    }
+
+    ics.push_str("END:VCALENDAR\r\n");
+    std::fs::write(ics_path, ics).with_context(|| format!("Could not write to file {:?}", ics_path))
+}
+
+fn escape_text(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Content lines are folded at 75 octets, continuation lines start with a space
+fn push_line(ics: &mut String, line: &str) {
+    let mut line_len = 0;
+    for c in line.chars() {
+        if line_len + c.len_utf8() > 75 {
+            ics.push_str("\r\n ");
+            line_len = 1;
+        }
+        ics.push(c);
+        line_len += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
@@ -6,9 +6,14 @@ use tokio::sync::Mutex;
 #[derive(Clone, Deserialize, Serialize)]
 pub struct Credentials {
     pub canvas_url: String,
+    #[serde(default)]
     pub canvas_token: String,
     #[serde(default)]
     pub no_submissions: bool,
+    // Canvas developer key used by --oauth
+    pub oauth_client_id: Option<String>,
+    pub oauth_client_secret: Option<String>,
+    pub oauth_refresh_token: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -17,12 +22,18 @@ pub struct Course {
     pub name: String,
     pub course_code: String,
     pub enrollment_term_id: u32,
+    pub term: Option<Term>, // requested with include[]=term
+}
+
+#[derive(Deserialize)]
+pub struct Term {
+    pub name: String,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct User {
     pub id: u32,
-    // pub name: String,
+    pub name: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -34,10 +45,12 @@ pub(crate) enum FolderResult {
 
 #[derive(Deserialize)]
 pub struct Folder {
-    // pub id: u32,
+    pub id: u32,
     pub name: String,
     pub folders_url: String,
     pub files_url: String,
+    #[serde(default)]
+    pub folders_count: u32,
     // pub for_submissions: bool,
     // pub can_upload: bool,
     pub parent_folder_id: Option<u32>,
@@ -90,6 +103,16 @@ pub struct Assignment {
     pub created_at: Option<String>,
     pub due_at: Option<String>,
     pub submission_types: Option<Vec<String>>,
+    pub points_possible: Option<f64>,
+    pub rubric: Option<Vec<RubricCriterion>>,
+    pub html_url: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RubricCriterion {
+    pub id: String,
+    pub description: Option<String>,
+    pub points: Option<f64>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -98,6 +121,77 @@ pub struct Submission {
     // pub body: Option<String>,
     #[serde(default)]
     pub attachments: Vec<File>,
+    pub score: Option<f64>,
+    pub grade: Option<String>,
+    #[serde(default)]
+    pub submission_comments: Vec<SubmissionComment>,
+    pub rubric_assessment: Option<std::collections::HashMap<String, RubricAssessment>>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SubmissionComment {
+    // pub id: u32,
+    pub author_name: Option<String>,
+    pub comment: Option<String>,
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub attachments: Vec<File>, // e.g. annotated PDFs from the grader
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RubricAssessment {
+    pub points: Option<f64>,
+    pub comments: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum QuizResult {
+    Err { status: String },
+    Ok(Vec<Quiz>),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Quiz {
+    pub id: u32,
+    pub title: String,
+    pub description: Option<String>,
+    // pub quiz_type: String,
+    // pub due_at: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum QuizQuestionResult {
+    Err { status: String },
+    Ok(Vec<QuizQuestion>),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct QuizQuestion {
+    pub position: Option<u32>,
+    // pub question_name: Option<String>, // "Question" unless the teacher named it
+    pub question_type: String, // e.g. "multiple_choice_question", "short_answer_question"
+    #[serde(default)]
+    pub question_text: String, // HTML
+    pub points_possible: Option<f64>,
+    #[serde(default)]
+    pub answers: Vec<QuizAnswer>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct QuizAnswer {
+    pub text: Option<String>,
+    pub html: Option<String>, // rich answer text, preferred over text when not empty
+    pub weight: Option<f64>,  // 100 for a correct answer, only sent when visible to the user
+    // Numerical questions: "exact_answer", "range_answer" or "precision_answer"
+    pub numerical_answer_type: Option<String>,
+    pub exact: Option<f64>,
+    pub margin: Option<f64>,
+    pub start: Option<f64>,
+    pub end: Option<f64>,
+    pub approximate: Option<f64>,
+    pub precision: Option<u32>,
 }
 
 #[derive(Deserialize)]
@@ -145,6 +239,7 @@ pub struct Comments {
     pub created_at: Option<String>,
     pub attachment: Option<File>,
     pub attachments: Option<Vec<File>>,
+    pub replies: Option<Vec<Comments>>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -155,15 +250,27 @@ pub struct File {
     pub size: u64,
     pub url: String,
     pub updated_at: String,
+    #[serde(default)] // not sent for submission comment attachments
     pub locked_for_user: bool,
+    pub lock_explanation: Option<String>, // why a locked file is locked, may contain HTML
+    pub unlock_at: Option<String>,
+    #[serde(rename = "content-type", default)]
+    pub content_type: Option<String>, // MIME type, not sent for links
     #[serde(skip)]
     pub filepath: std::path::PathBuf,
+    #[serde(skip)]
+    pub segments: Vec<String>, // HLS segment URLs to concatenate, empty for regular files
+    #[serde(skip)]
+    pub remux_to_mp4: bool, // MPEG-TS video to turn into MP4 with ffmpeg once downloaded
+    #[serde(skip)]
+    pub client: Option<reqwest::Client>, // holds the cookies a non-Canvas download needs, which then gets no Canvas token
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Session {
     pub session_url: String,
-    // pub requires_terms_acceptance: bool,
+    #[serde(default)]
+    pub requires_terms_acceptance: bool, // the Canvas terms of use weren't accepted yet
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -197,6 +304,20 @@ pub struct PanoptoSubfolder {
 pub struct PanoptoDeliveryInfo {
     // pub SessionId: String,
     pub ViewerFileId: String,
+    pub Delivery: Option<PanoptoDelivery>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[allow(non_snake_case)]
+pub struct PanoptoDelivery {
+    #[serde(default)]
+    pub PodcastStreams: Vec<PanoptoStream>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[allow(non_snake_case)]
+pub struct PanoptoStream {
+    pub StreamUrl: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -235,7 +356,7 @@ pub struct ModuleItem {
     #[serde(rename = "type")]
     pub item_type: String, // "File", "Page", "Discussion", "Assignment", "Quiz", "SubHeader", "ExternalUrl", "ExternalTool"
     pub content_id: Option<u32>,
-    // pub html_url: Option<String>,
+    pub html_url: Option<String>,
     pub url: Option<String>,
     // pub page_url: Option<String>,
     pub external_url: Option<String>,
@@ -252,6 +373,180 @@ pub struct Syllabus {
     pub syllabus_body: Option<String>,
 }
 
+/// Which Panopto HLS variant to download
+#[derive(Clone, Copy, Debug)]
+pub enum VideoQuality {
+    Highest,
+    Lowest,
+    Height(u64), // closest resolution to this height
+}
+
+impl std::str::FromStr for VideoQuality {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().trim_end_matches('p') {
+            "highest" => Ok(VideoQuality::Highest),
+            "lowest" => Ok(VideoQuality::Lowest),
+            height => height.parse().map(VideoQuality::Height).map_err(|_| {
+                format!(
+                    "Invalid video quality {s:?}, expected highest, lowest or a height like 720"
+                )
+            }),
+        }
+    }
+}
+
+/// What HLS videos made of MPEG-TS segments are saved as
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VideoContainer {
+    Ts,  // the concatenated segments as they are
+    Mp4, // remuxed with ffmpeg
+}
+
+impl std::str::FromStr for VideoContainer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ts" => Ok(VideoContainer::Ts),
+            "mp4" => Ok(VideoContainer::Mp4),
+            _ => Err(format!("Invalid video container {s:?}, expected ts or mp4")),
+        }
+    }
+}
+
+/// Which progress bars are shown while downloading
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProgressMode {
+    Total,   // one bar for all files
+    PerFile, // one bar per file
+    Both,
+}
+
+impl std::str::FromStr for ProgressMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "total" => Ok(ProgressMode::Total),
+            "per-file" => Ok(ProgressMode::PerFile),
+            "both" => Ok(ProgressMode::Both),
+            _ => Err(format!(
+                "Invalid progress mode {s:?}, expected total, per-file or both"
+            )),
+        }
+    }
+}
+
+/// How Canvas names are turned into file and folder names
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilenameMode {
+    Safe,     // valid on every OS, including Windows
+    Preserve, // only path separators and control characters are removed
+    Ascii,    // transliterated to ASCII, then made safe
+}
+
+impl std::str::FromStr for FilenameMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "safe" => Ok(FilenameMode::Safe),
+            "preserve" => Ok(FilenameMode::Preserve),
+            "ascii" => Ok(FilenameMode::Ascii),
+            _ => Err(format!(
+                "Invalid filename mode {s:?}, expected safe, preserve or ascii"
+            )),
+        }
+    }
+}
+
+/// Which files are written for pages, syllabi, assignments, discussions and quizzes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContentFormat {
+    Html,
+    Markdown,
+    Both,
+}
+
+impl ContentFormat {
+    pub fn writes_html(self) -> bool {
+        self != ContentFormat::Markdown
+    }
+
+    pub fn writes_markdown(self) -> bool {
+        self != ContentFormat::Html
+    }
+}
+
+impl std::str::FromStr for ContentFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "html" => Ok(ContentFormat::Html),
+            "markdown" | "md" => Ok(ContentFormat::Markdown),
+            "both" => Ok(ContentFormat::Both),
+            _ => Err(format!(
+                "Invalid content format {s:?}, expected html, markdown or both"
+            )),
+        }
+    }
+}
+
+/// What to do with files that already exist locally, see --overwrite
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverwritePolicy {
+    Skip,        // keep them, unless --download-newer finds a newer version
+    Always,      // download them again
+    IfDifferent, // download them again if the size on Canvas differs
+}
+
+impl std::str::FromStr for OverwritePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "skip" => Ok(OverwritePolicy::Skip),
+            "always" => Ok(OverwritePolicy::Always),
+            "if-different" => Ok(OverwritePolicy::IfDifferent),
+            _ => Err(format!(
+                "Invalid overwrite policy {s:?}, expected skip, always or if-different"
+            )),
+        }
+    }
+}
+
+/// Where files attached to modules end up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ModuleLayout {
+    ByModule, // in the module's folder, by module position
+    ByFiles,  // only once, in the course's files/ tree
+}
+
+impl std::str::FromStr for ModuleLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "by-module" => Ok(ModuleLayout::ByModule),
+            "by-files" => Ok(ModuleLayout::ByFiles),
+            _ => Err(format!(
+                "Invalid module layout {s:?}, expected by-module or by-files"
+            )),
+        }
+    }
+}
+
+/// Download results for one course, keyed by course folder in ProcessOptions
+#[derive(Default)]
+pub struct CourseStats {
+    pub files_downloaded: usize,
+    pub bytes_downloaded: u64,
+    pub errors: usize,
+}
+
 pub struct ProcessOptions {
     pub canvas_token: String,
     pub canvas_url: String,
@@ -259,20 +554,102 @@ pub struct ProcessOptions {
     pub user: User,
     // Process
     pub download_newer: bool,
+    pub local_times_only: bool, // --newer-than-local-only
+    pub overwrite: OverwritePolicy,
     pub files_to_download: Mutex<Vec<File>>,
     pub ignore_matcher: Option<std::sync::Arc<ignore::gitignore::Gitignore>>,
+    pub include_ext: Option<Vec<String>>, // lowercase, without leading dot
+    pub exclude_ext: Vec<String>,         // lowercase, without leading dot
+    pub max_size: Option<u64>,
+    pub max_depth: Option<usize>, // folder levels below a course's files folder
+    pub since: Option<chrono::DateTime<chrono::Utc>>, // only files updated on Canvas after this
     pub base_path: std::path::PathBuf,
+    pub flatten: bool,                 // see utils::flatten_path
+    pub group_by_type: bool,           // see utils::group_by_type_path
+    pub course_layout: Option<String>, // see utils::course_folder
+    pub course_destinations: std::collections::HashMap<u32, std::path::PathBuf>, // from --courses-file
     // pub dry_run: bool,
     pub save_json: bool,
+    pub download_avatars: bool,
     pub skip_submissions: bool,
+    pub skip_files: bool,
+    pub skip_assignments: bool,
+    pub skip_discussions: bool,
+    pub skip_pages: bool,
+    pub skip_modules: bool,
+    pub skip_videos: bool,
+    pub video_quality: VideoQuality,
+    pub strict_videos: bool,
+    pub panopto_host: Option<String>, // tool to log in through, any Panopto tool if None
+    pub panopto_cdn_host: String,     // for sessions whose video URL has no host
+    pub video_index: bool,
+    pub ffmpeg: Option<std::path::PathBuf>, // None unless --video-container mp4 and ffmpeg was found
+    pub content_format: ContentFormat,
+    pub filename_mode: FilenameMode,
+    pub prefix_date: bool,
+    pub timeout: std::time::Duration, // for API and metadata requests, not file bodies
+    pub retries: u32, // max attempts per API request, segment or interrupted download
+    pub per_page: u32, // page size unless the URL sets per_page
+    pub proxy: Option<reqwest::Proxy>, // shared by every client we build
+    pub http1_only: bool,
+    pub keepalive: Option<std::time::Duration>, // TCP keep-alive, None if turned off
+    #[cfg(feature = "pdf")]
+    pub pdf_renderer: Option<crate::pdf::PdfRenderer>, // None unless --pdf or --combine-pdf and a renderer was found
+    #[cfg(feature = "pdf")]
+    pub pdf: bool,
+    #[cfg(feature = "pdf")]
+    pub combine_pdf: bool,
+    #[cfg(feature = "pdf")]
+    pub course_pdf_parts:
+        std::sync::Mutex<Vec<(crate::pdf::CourseSection, std::path::PathBuf, String)>>, // for --combine-pdf
+    pub track_etags: bool,
+    pub listing_cache: Option<crate::cache::ListingCache>, // see --cache-dir
+    pub manifest: Option<crate::manifest::Manifest>,       // see --manifest
+    pub rate_limiter: Option<crate::throttle::RateLimiter>,
+    pub export_ics: bool,
+    pub deadlines: std::sync::Mutex<Vec<(String, Assignment)>>, // (course code, assignment) for --ics
+    pub module_layout: ModuleLayout,
+    pub clean: bool,
+    pub checksums: bool,
+    pub on_download: Option<String>, // shell command run for each downloaded file
+    pub listed_files: std::sync::Mutex<
+        std::collections::HashMap<
+            std::path::PathBuf,
+            std::collections::HashSet<std::ffi::OsString>,
+        >,
+    >, // folder under files/ -> names Canvas lists there, for --clean
+    pub link_module_files: bool,
+    pub local_links: bool,
+    pub link_targets:
+        std::sync::Mutex<std::collections::HashMap<String, (String, std::path::PathBuf)>>, // link in a page -> url of its file and where it is saved, for --local-links
+    pub saved_content: std::sync::Mutex<Vec<std::path::PathBuf>>, // pages written by html::save_content, for --local-links
+    pub course_file_paths: std::sync::Mutex<std::collections::HashMap<u32, std::path::PathBuf>>, // file id -> path under files/
+    pub module_files: tokio::sync::Mutex<Vec<File>>, // module files held back for linking
     // Download
     pub progress_bars: indicatif::MultiProgress,
     pub progress_style: indicatif::ProgressStyle,
+    pub progress: ProgressMode,
+    pub total_progress: crate::progress::TotalProgress,
     // Synchronization
     pub n_active_requests: AtomicUsize, // main() waits for this to be 0
     pub sem_requests: tokio::sync::Semaphore, // Limit #active requests
+    pub sem_downloads: tokio::sync::Semaphore, // Limit #active file downloads
+    pub sem_hooks: tokio::sync::Semaphore, // Limit #running --on-download commands
+    pub course_concurrency: Option<usize>, // Limit #active requests per course, see course_semaphore()
+    pub course_semaphores:
+        std::sync::Mutex<std::collections::HashMap<u32, std::sync::Arc<tokio::sync::Semaphore>>>,
     pub notify_main: tokio::sync::Notify,
+    pub state_lock: std::sync::Mutex<()>, // Guards .canvas-state.json read-modify-write
+    pub claimed_paths: std::sync::Mutex<std::collections::HashSet<std::path::PathBuf>>, // download targets taken this run
+    pub visited_folders: std::sync::Mutex<std::collections::HashSet<u32>>, // folder ids crawled this run
     // Progress counters
+    pub course_stats: std::sync::Mutex<std::collections::HashMap<std::path::PathBuf, CourseStats>>,
+    pub downloaded_files: std::sync::Mutex<Vec<(std::path::PathBuf, u64)>>, // path and size, for --json
+    pub downloaded_ids: std::sync::Mutex<std::collections::HashSet<(u32, String)>>, // file id and url, for pruning the saved queue
+    pub file_checksums: std::sync::Mutex<Vec<(std::path::PathBuf, String)>>, // path and SHA-256, for --checksums
+    pub locked_files: std::sync::Mutex<Vec<File>>, // skipped as locked for the user, see files::write_locked_lists
+    pub partial_videos: std::sync::Mutex<Vec<(std::path::PathBuf, Vec<usize>)>>, // path and missing segments, for --json
+    pub video_sessions: std::sync::Mutex<Vec<(std::path::PathBuf, PanoptoResult)>>, // video folder and session, for --video-index
     pub n_syllabi: AtomicUsize,
     pub n_users: AtomicUsize,
     pub n_assignments: AtomicUsize,
@@ -280,5 +657,39 @@ pub struct ProcessOptions {
     pub n_discussions: AtomicUsize,
     pub n_announcements: AtomicUsize,
     pub n_modules: AtomicUsize,
+    pub n_quizzes: AtomicUsize,
     pub n_videos: AtomicUsize,
+    pub errors: std::sync::Mutex<Vec<String>>, // failed items, makes main() exit non-zero
+}
+
+impl ProcessOptions {
+    /// Semaphore limiting the active tasks of one course to --course-concurrency, if set
+    pub fn course_semaphore(
+        &self,
+        course_id: u32,
+    ) -> Option<std::sync::Arc<tokio::sync::Semaphore>> {
+        let course_concurrency = self.course_concurrency?;
+        let mut course_semaphores = self
+            .course_semaphores
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        Some(
+            course_semaphores
+                .entry(course_id)
+                .or_insert_with(|| {
+                    std::sync::Arc::new(tokio::sync::Semaphore::new(course_concurrency))
+                })
+                .clone(),
+        )
+    }
+
+    /// Log the failure of one item (a page, a submission, a request...) and remember it
+    /// for the summary; callers carry on with the other items
+    pub fn record_error(&self, error: anyhow::Error) {
+        tracing::error!("{error:?}");
+        self.errors
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(format!("{error:#}"));
+    }
 }
@@ -0,0 +1,160 @@
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::api::next_page_link;
+use crate::canvas::{Course, Credentials, File, User};
+use crate::utils::{format_bytes, print_all_courses_by_term};
+
+/// Largest file downloaded to measure the download speed
+const MAX_SAMPLE_SIZE: u64 = 20 * 1024 * 1024;
+
+#[derive(Deserialize)]
+struct AccessToken {
+    #[serde(default)]
+    scopes: Vec<String>,
+}
+
+/// Verify the token and connection before a long run: who the token belongs to, its scopes,
+/// the accessible courses and the download speed. Nothing is written to disk.
+pub async fn check_account(client: &reqwest::Client, cred: &Credentials) -> Result<()> {
+    let user = client
+        .get(format!("{}/api/v1/users/self", cred.canvas_url))
+        .bearer_auth(&cred.canvas_token)
+        .send()
+        .await
+        .with_context(|| format!("Could not reach {}", cred.canvas_url))?
+        .error_for_status()
+        .with_context(|| "Canvas rejected the token")?
+        .json::<User>()
+        .await
+        .with_context(|| "Failed to get user info")?;
+    status!(
+        "✅ Token works for {} (user {})",
+        user.name.as_deref().unwrap_or("unknown user"),
+        user.id
+    );
+
+    match token_scopes(client, cred).await {
+        Some(scopes) if scopes.is_empty() => status!("🔑 Token scopes: unrestricted"),
+        Some(scopes) => status!("🔑 Token scopes: {}", scopes.join(", ")),
+        None => status!("🔑 Token scopes: not available"),
+    }
+
+    let courses: Vec<Course> = get_all(
+        client,
+        cred,
+        format!(
+            "{}/api/v1/users/self/courses?include[]=term&per_page=100",
+            cred.canvas_url
+        ),
+    )
+    .await
+    .with_context(|| "Failed to list courses")?
+    .into_iter()
+    .filter(|json| json.get("enrollments").is_some())
+    .map(serde_json::from_value)
+    .collect::<Result<_, _>>()
+    .with_context(|| "Error when getting course json")?;
+    status!("📚 {} accessible courses:", courses.len());
+    print_all_courses_by_term(&courses);
+
+    let Some(sample) = find_sample_file(client, cred, &courses).await else {
+        status!("📶 No course file small enough to measure the download speed");
+        return Ok(());
+    };
+    let started = Instant::now();
+    let mut resp = client
+        .get(&sample.url)
+        .bearer_auth(&cred.canvas_token)
+        .send()
+        .await?
+        .error_for_status()
+        .with_context(|| format!("Failed to download {}", sample.display_name))?;
+    let mut bytes = 0;
+    while let Some(chunk) = resp.chunk().await? {
+        bytes += chunk.len() as u64;
+    }
+    let elapsed = started.elapsed().as_secs_f64().max(0.001);
+    status!(
+        "📶 Downloaded {} ({}) in {elapsed:.1}s, about {}/s",
+        sample.display_name,
+        format_bytes(bytes),
+        format_bytes((bytes as f64 / elapsed) as u64)
+    );
+    Ok(())
+}
+
+/// Scopes of the token, empty if it is unrestricted. Canvas looks tokens up by their
+/// first 5 characters (the token hint); None if it won't show this one.
+async fn token_scopes(client: &reqwest::Client, cred: &Credentials) -> Option<Vec<String>> {
+    let token_hint = cred.canvas_token.get(..5)?;
+    let token = client
+        .get(format!(
+            "{}/api/v1/users/self/tokens/{token_hint}",
+            cred.canvas_url
+        ))
+        .bearer_auth(&cred.canvas_token)
+        .send()
+        .await
+        .ok()?
+        .error_for_status()
+        .ok()?
+        .json::<AccessToken>()
+        .await
+        .ok()?;
+    Some(token.scopes)
+}
+
+/// The largest file up to MAX_SAMPLE_SIZE in the first course that lists its files
+async fn find_sample_file(
+    client: &reqwest::Client,
+    cred: &Credentials,
+    courses: &[Course],
+) -> Option<File> {
+    for course in courses {
+        let link = format!(
+            "{}/api/v1/courses/{}/files?sort=size&order=desc&per_page=100",
+            cred.canvas_url, course.id
+        );
+        // Courses with the files tab disabled reply with an error, try the next one
+        let Ok(resp) = client
+            .get(&link)
+            .bearer_auth(&cred.canvas_token)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+        else {
+            continue;
+        };
+        let Ok(files) = resp.json::<Vec<File>>().await else {
+            continue;
+        };
+        if let Some(file) = files
+            .into_iter()
+            .find(|file| file.size > 0 && file.size <= MAX_SAMPLE_SIZE && !file.locked_for_user)
+        {
+            return Some(file);
+        }
+    }
+    None
+}
+
+/// Every item of a paginated listing
+async fn get_all(client: &reqwest::Client, cred: &Credentials, link: String) -> Result<Vec<Value>> {
+    let mut items = Vec::new();
+    let mut link = Some(link);
+    while let Some(uri) = link {
+        let resp = client
+            .get(&uri)
+            .bearer_auth(&cred.canvas_token)
+            .send()
+            .await?
+            .error_for_status()?;
+        link = next_page_link(&resp);
+        items.extend(resp.json::<Vec<Value>>().await?);
+    }
+    Ok(items)
+}
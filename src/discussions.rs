@@ -1,16 +1,22 @@
 use std::collections::HashMap;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
 
 use anyhow::{Context, Result};
+use percent_encoding::utf8_percent_encode;
 
 use crate::api::{get_canvas_api, get_pages};
-use crate::canvas::{Discussion, DiscussionResult, DiscussionView, File, ProcessOptions};
+use crate::canvas::{
+    Comments, Discussion, DiscussionResult, DiscussionView, File, FilenameMode, ProcessOptions,
+};
 use crate::files::filter_files;
-use crate::html::process_html_links;
-use crate::utils::{create_folder_if_not_exist_or_ignored, get_raw_json_path, prettify_json};
+use crate::html::{process_html_links, save_content};
+use crate::utils::{
+    HREF_ESCAPES, create_folder_if_not_exist_or_ignored, flatten_path, get_raw_json_path,
+    html_escape, prettify_json, sanitize_path_component,
+};
 
 pub async fn process_discussions(
     (url, announcement, path): (String, bool, PathBuf),
@@ -29,6 +35,7 @@ pub async fn process_discussions(
 
     let mut has_discussions = false;
     let mut discussions_folder_path = None;
+    let mut announcements = Vec::new();
 
     for pg in pages {
         let uri = pg.url().to_string();
@@ -74,49 +81,10 @@ pub async fn process_discussions(
 
                 for discussion in discussions {
                     if let Some(ref folder_path) = discussions_folder_path {
-                        // download attachments (TODO: not sure if this is needed)
-                        let discussion_folder_path =
-                            folder_path.join(sanitize_filename::sanitize(&discussion.title));
-
-                        let files: Vec<File> = discussion
-                            .attachments
-                            .clone()
-                            .into_iter()
-                            .map(|mut f| {
-                                f.display_name = format!("{}_{}", f.id, &f.display_name);
-                                f
-                            })
-                            .collect();
-                        let mut filtered_files =
-                            filter_files(&options, &discussion_folder_path, files);
-                        if !filtered_files.is_empty() {
-                            // create folder for discussion if there are files to download
-                            create_folder_if_not_exist_or_ignored(
-                                &discussion_folder_path,
-                                &options,
-                            )?;
-                            // add files to download list
-                            let mut lock = options.files_to_download.lock().await;
-                            lock.append(&mut filtered_files);
+                        if announcement {
+                            announcements.push(discussion.clone());
                         }
-
-                        fork!(
-                            process_html_links,
-                            (
-                                discussion.message.clone(),
-                                folder_path.clone(),
-                                discussion.title.clone()
-                            ),
-                            (String, PathBuf, String),
-                            options.clone()
-                        );
-                        let view_url = format!("{}discussion_topics/{}/view", url, discussion.id);
-                        fork!(
-                            process_discussion_view,
-                            (view_url, folder_path.clone(), discussion),
-                            (String, PathBuf, Discussion),
-                            options.clone()
-                        )
+                        process_discussion(&url, folder_path, discussion, &options).await?;
                     }
                 }
             }
@@ -133,6 +101,15 @@ pub async fn process_discussions(
         }
     }
 
+    if let Some(ref folder_path) = discussions_folder_path
+        && !announcements.is_empty()
+    {
+        // Newest first, like the announcements page on Canvas
+        announcements.sort_by(|a, b| b.posted_at.cmp(&a.posted_at));
+        let index_html = generate_announcements_index_html(&announcements, options.filename_mode);
+        save_content(&folder_path.join("index.html"), &index_html, &options).await?;
+    }
+
     if has_discussions {
         let course = path.file_name().unwrap_or_default().to_string_lossy();
         if announcement {
@@ -147,10 +124,88 @@ pub async fn process_discussions(
     Ok(())
 }
 
-fn generate_discussion_html(
-    discussion: &Discussion,
-    comments: &[crate::canvas::Comments],
-) -> String {
+/// Download one discussion or announcement, e.g. for --url
+pub async fn process_discussion_topic(
+    (url, discussion_id, announcement, path): (String, u32, bool, PathBuf),
+    options: Arc<ProcessOptions>,
+) -> Result<()> {
+    let discussion_url = format!("{url}discussion_topics/{discussion_id}");
+    let discussion = get_canvas_api(discussion_url.clone(), &options)
+        .await?
+        .json::<Discussion>()
+        .await
+        .with_context(|| format!("Error when getting discussion at link:{discussion_url}"))?;
+
+    let folder_path = path.join(if announcement {
+        "announcements"
+    } else {
+        "discussions"
+    });
+    if !create_folder_if_not_exist_or_ignored(&folder_path, &options)? {
+        return Ok(());
+    }
+    process_discussion(&url, &folder_path, discussion, &options).await?;
+    if announcement {
+        options.n_announcements.fetch_add(1, Ordering::Relaxed);
+    } else {
+        options.n_discussions.fetch_add(1, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+/// Queue the attachments of a discussion in `folder_path` and fork the tasks saving its
+/// linked files and its replies
+async fn process_discussion(
+    url: &str,
+    folder_path: &Path,
+    discussion: Discussion,
+    options: &Arc<ProcessOptions>,
+) -> Result<()> {
+    // download attachments (TODO: not sure if this is needed)
+    let discussion_folder_path = folder_path.join(sanitize_path_component(
+        &discussion.title,
+        options.filename_mode,
+    ));
+
+    let files: Vec<File> = discussion
+        .attachments
+        .clone()
+        .into_iter()
+        .map(|mut f| {
+            f.display_name = format!("{}_{}", f.id, &f.display_name);
+            f
+        })
+        .collect();
+    let mut filtered_files = filter_files(options, &discussion_folder_path, files);
+    if !filtered_files.is_empty() {
+        // create folder for discussion if there are files to download
+        create_folder_if_not_exist_or_ignored(&discussion_folder_path, options)?;
+        // add files to download list
+        let mut lock = options.files_to_download.lock().await;
+        lock.append(&mut filtered_files);
+    }
+
+    fork!(
+        process_html_links,
+        (
+            discussion.message.clone(),
+            folder_path.to_path_buf(),
+            discussion.title.clone()
+        ),
+        (String, PathBuf, String),
+        options.clone()
+    );
+    let view_url = format!("{}discussion_topics/{}/view", url, discussion.id);
+    fork!(
+        process_discussion_view,
+        (view_url, folder_path.to_path_buf(), discussion),
+        (String, PathBuf, Discussion),
+        options.clone()
+    );
+    Ok(())
+}
+
+fn generate_discussion_html(discussion: &Discussion, comments: &[Comments]) -> String {
     let mut html = String::new();
 
     html.push_str("<!DOCTYPE html>\n<html>\n<head>\n");
@@ -180,6 +235,9 @@ fn generate_discussion_html(
 
         .comment { background: #fff; border: 1px solid #ddd; padding: 15px; margin-bottom: 15px; border-radius: 6px; }
         .comment-meta { color: #666; font-size: 0.875rem; margin-bottom: 10px; font-weight: 500; }
+
+        .replies { margin-top: 15px; margin-left: 24px; }
+        .replies .comment:last-child { margin-bottom: 0; }
     </style>
 "#);
     html.push_str("</head>\n<body>\n");
@@ -214,31 +272,74 @@ fn generate_discussion_html(
         html.push_str("    <div class=\"comments-section\">\n");
         html.push_str(&format!(
             "        <div class=\"comments-header\">Comments ({})</div>\n",
-            comments.len()
+            count_comments(comments)
         ));
 
         for comment in comments {
-            if let Some(ref message) = comment.message {
-                html.push_str("        <div class=\"comment\">\n");
-                html.push_str("            <div class=\"comment-meta\">\n");
+            push_comment_html(&mut html, comment, 2);
+        }
 
-                if let Some(ref user_name) = comment.user_name {
-                    html.push_str(&format!("                {}", html_escape(user_name)));
-                }
+        html.push_str("    </div>\n");
+    }
 
-                if let Some(ref created_at) = comment.created_at {
-                    html.push_str(&format!(" | {}", html_escape(created_at)));
-                }
+    html.push_str("</body>\n</html>");
+    html
+}
 
-                html.push_str("\n            </div>\n");
-                html.push_str(&format!(
-                    "            <div class=\"comment-message\">{}</div>\n",
-                    message
-                ));
-                html.push_str("        </div>\n");
-            }
+/// All announcements of a course on one page, each linking to its page with comments
+fn generate_announcements_index_html(
+    announcements: &[Discussion],
+    filename_mode: FilenameMode,
+) -> String {
+    let mut html = String::new();
+
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n");
+    html.push_str("    <meta charset=\"UTF-8\">\n");
+    html.push_str("    <title>Announcements</title>\n");
+    html.push_str(r#"    <style>
+        body { font-family: system-ui, -apple-system, "Segoe UI", Arial, sans-serif; font-size: 16px; line-height: 1.5; max-width: 900px; margin: 20px auto; padding: 0 20px; }
+
+        .discussion-post { background: #f9f9f9; border-left: 4px solid #4CAF50; padding: 20px; margin-bottom: 30px; }
+        .discussion-title { font-size: 1.5rem; font-weight: 600; margin-bottom: 10px; }
+        .discussion-title a { color: inherit; text-decoration: none; }
+        .discussion-meta { color: #666; font-size: 0.875rem; margin-bottom: 10px; font-weight: 500; }
+
+        .discussion-message { font-size: 0.95rem; }
+        .discussion-message p { margin: 0; }
+        .discussion-message p + p { margin-top: 0.75em; }
+    </style>
+"#);
+    html.push_str("</head>\n<body>\n");
+    html.push_str("    <h1>Announcements</h1>\n");
+
+    for announcement in announcements {
+        let page_name = format!(
+            "{}.html",
+            sanitize_path_component(&announcement.title, filename_mode)
+        );
+        html.push_str("    <div class=\"discussion-post\">\n");
+        html.push_str(&format!(
+            "        <div class=\"discussion-title\"><a href=\"{}\">{}</a></div>\n",
+            html_escape(&utf8_percent_encode(&page_name, HREF_ESCAPES).to_string()),
+            html_escape(&announcement.title)
+        ));
+        html.push_str("        <div class=\"discussion-meta\">\n");
+
+        if let Some(author) = &announcement.author
+            && let Some(display_name) = &author.display_name
+        {
+            html.push_str(&format!("            {}", html_escape(display_name)));
         }
 
+        if let Some(ref posted_at) = announcement.posted_at {
+            html.push_str(&format!(" | {}", html_escape(posted_at)));
+        }
+
+        html.push_str("\n        </div>\n");
+        html.push_str(&format!(
+            "        <div class=\"discussion-message\">{}</div>\n",
+            &announcement.message
+        ));
         html.push_str("    </div>\n");
     }
 
@@ -246,12 +347,44 @@ fn generate_discussion_html(
     html
 }
 
-fn html_escape(s: &str) -> String {
-    s.replace('&', "&amp;")
-        .replace('<', "&lt;")
-        .replace('>', "&gt;")
-        .replace('"', "&quot;")
-        .replace('\'', "&#39;")
+fn count_comments(comments: &[Comments]) -> usize {
+    comments
+        .iter()
+        .map(|c| 1 + count_comments(c.replies.as_deref().unwrap_or_default()))
+        .sum()
+}
+
+/// Render a comment and, nested inside it, its replies so they are indented under their parent
+fn push_comment_html(html: &mut String, comment: &Comments, indent: usize) {
+    let replies = comment.replies.as_deref().unwrap_or_default();
+    // Deleted comments have no message, keep them only as a parent for their replies
+    if comment.message.is_none() && replies.is_empty() {
+        return;
+    }
+    let pad = "    ".repeat(indent);
+
+    html.push_str(&format!("{pad}<div class=\"comment\">\n"));
+    html.push_str(&format!("{pad}    <div class=\"comment-meta\">\n"));
+    if let Some(ref user_name) = comment.user_name {
+        html.push_str(&format!("{pad}        {}", html_escape(user_name)));
+    }
+    if let Some(ref created_at) = comment.created_at {
+        html.push_str(&format!(" | {}", html_escape(created_at)));
+    }
+    html.push_str(&format!("\n{pad}    </div>\n"));
+    html.push_str(&format!(
+        "{pad}    <div class=\"comment-message\">{}</div>\n",
+        comment.message.as_deref().unwrap_or("<em>[deleted]</em>")
+    ));
+
+    if !replies.is_empty() {
+        html.push_str(&format!("{pad}    <div class=\"replies\">\n"));
+        for reply in replies {
+            push_comment_html(html, reply, indent + 2);
+        }
+        html.push_str(&format!("{pad}    </div>\n"));
+    }
+    html.push_str(&format!("{pad}</div>\n"));
 }
 
 async fn process_discussion_view(
@@ -261,7 +394,7 @@ async fn process_discussion_view(
     let resp = get_canvas_api(url.clone(), &options).await?;
     let discussion_view_body = resp.text().await?;
 
-    let discussion_name = sanitize_filename::sanitize(&discussion.title);
+    let discussion_name = sanitize_path_component(&discussion.title, options.filename_mode);
     if let Some(discussion_view_json) = get_raw_json_path(
         &path,
         &format!("{discussion_name}.json"),
@@ -280,10 +413,9 @@ async fn process_discussion_view(
 
     let discussion_view_result = serde_json::from_str::<DiscussionView>(&discussion_view_body);
     let mut attachments_all = Vec::new();
-    let mut comments = Vec::new();
 
     match discussion_view_result {
-        Result::Ok(discussion_view) => {
+        Result::Ok(mut discussion_view) => {
             // Create a mapping from user_id to display_name
             let user_map: HashMap<u32, String> = discussion_view
                 .participants
@@ -291,7 +423,9 @@ async fn process_discussion_view(
                 .map(|p| (p.id, p.display_name.clone()))
                 .collect();
 
-            for mut view in discussion_view.view {
+            // Walk the whole reply tree, not just the top-level entries
+            let mut stack: Vec<&mut Comments> = discussion_view.view.iter_mut().collect();
+            while let Some(view) = stack.pop() {
                 // Map user_id to display_name
                 if let Some(user_id) = view.user_id
                     && let Some(display_name) = user_map.get(&user_id)
@@ -299,37 +433,34 @@ async fn process_discussion_view(
                     view.user_name = Some(display_name.clone());
                 }
 
-                comments.push(view.clone());
-
-                if let Some(message) = view.message {
+                if let Some(ref message) = view.message {
                     fork!(
                         process_html_links,
-                        (message, path.clone(), discussion_name.clone()),
+                        (message.clone(), path.clone(), discussion_name.clone()),
                         (String, PathBuf, String),
                         options.clone()
                     )
                 }
-                if let Some(mut attachments) = view.attachments {
-                    attachments_all.append(&mut attachments);
+                if let Some(ref mut attachments) = view.attachments {
+                    attachments_all.append(attachments);
                 }
-                if let Some(attachment) = view.attachment {
+                if let Some(attachment) = view.attachment.take() {
                     attachments_all.push(attachment);
                 }
+                if let Some(ref mut replies) = view.replies {
+                    stack.extend(replies.iter_mut());
+                }
             }
 
             // Generate HTML file with discussion and comments
-            let html_content = generate_discussion_html(&discussion, &comments);
-            let html_path = path.join(format!("{discussion_name}.html"));
-            let mut html_file = std::fs::File::create(html_path.clone())
-                .with_context(|| format!("Unable to create file for {:?}", html_path))?;
-            html_file
-                .write_all(html_content.as_bytes())
-                .with_context(|| format!("Could not write to file {:?}", html_path))?;
+            let html_content = generate_discussion_html(&discussion, &discussion_view.view);
+            let html_path = flatten_path(&path.join(format!("{discussion_name}.html")), &options);
+            save_content(&html_path, &html_content, &options).await?;
         }
         Result::Err(e) => {
-            tracing::error!(
-                "Error when getting discussion views at link:{url}, path:{path:?}\n{e:?}",
-            );
+            options.record_error(anyhow::anyhow!(
+                "Error when getting discussion views at link:{url}, path:{path:?}\n{e:?}"
+            ));
         }
     }
 
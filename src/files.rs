@@ -1,46 +1,362 @@
 use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::hash::{Hash, Hasher};
+use std::io::Write;
 use std::ops::Add;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::Ordering;
 
 use anyhow::{Context, Error, Result};
 use chrono::{DateTime, Local};
-use lazy_regex::regex;
-use reqwest::header;
+use futures::future::ready;
+use futures::{StreamExt, stream};
+use lazy_regex::{regex, regex_captures};
+use percent_encoding::percent_decode_str;
+use reqwest::{StatusCode, header};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-use crate::api::get_canvas_api;
-use crate::api::get_pages;
-use crate::canvas::{File, FileResult, FolderResult, ProcessOptions};
-use crate::utils::{create_folder_if_not_exist_or_ignored, ignored};
+use crate::api::{backoff_delay, get_canvas_api, get_pages, get_pages_until};
+use crate::canvas::{
+    File, FileResult, FolderResult, OverwritePolicy, ProcessOptions, ProgressMode,
+};
+use crate::utils::{
+    INTERRUPTED, create_folder_if_not_exist_or_ignored, enclosing_course_folder, extension_allowed,
+    flatten_path, format_bytes, group_by_type_path, ignored, sanitize_path_component, stable_hash,
+};
 
-pub async fn atomic_download_file(file: File, options: Arc<ProcessOptions>) -> Result<()> {
+/// Temp files of downloads in progress, removed by remove_tmp_files_in_flight on Ctrl-C
+static TMP_FILES_IN_FLIGHT: std::sync::Mutex<BTreeSet<PathBuf>> =
+    std::sync::Mutex::new(BTreeSet::new());
+
+/// Keeps a temp file in TMP_FILES_IN_FLIGHT for as long as it lives
+struct TmpFileGuard(PathBuf);
+
+impl TmpFileGuard {
+    fn new(tmp_path: &Path) -> Self {
+        TMP_FILES_IN_FLIGHT
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(tmp_path.to_path_buf());
+        TmpFileGuard(tmp_path.to_path_buf())
+    }
+}
+
+impl Drop for TmpFileGuard {
+    fn drop(&mut self) {
+        TMP_FILES_IN_FLIGHT
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .remove(&self.0);
+    }
+}
+
+pub fn n_tmp_files_in_flight() -> usize {
+    TMP_FILES_IN_FLIGHT
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .len()
+}
+
+/// Delete the temp files of unfinished downloads, returning how many were removed
+pub fn remove_tmp_files_in_flight() -> usize {
+    let tmp_files = std::mem::take(
+        &mut *TMP_FILES_IN_FLIGHT
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()),
+    );
+    tmp_files
+        .iter()
+        .filter(|tmp_path| {
+            let _ = std::fs::remove_file(validator_path(tmp_path));
+            std::fs::remove_file(tmp_path).is_ok()
+        })
+        .count()
+}
+
+pub async fn atomic_download_file(mut file: File, options: Arc<ProcessOptions>) -> Result<()> {
+    if INTERRUPTED.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    let claimed_filepath = claim_filepath(&file.filepath, &options);
+    if claimed_filepath != file.filepath && options.local_links {
+        // Links to this file must point at the name it is actually saved under
+        options
+            .link_targets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .values_mut()
+            .filter(|(url, _)| *url == file.url)
+            .for_each(|(_, filepath)| *filepath = claimed_filepath.clone());
+    }
+    file.filepath = claimed_filepath;
+    let res = download_and_rename(&file, &options).await;
+    options.total_progress.finish_file();
+    if let Err(ref e) = res
+        && let Some(unavailable) = e.downcast_ref::<FileUnavailable>()
+    {
+        tracing::warn!("{unavailable}, skipping it");
+        return Ok(());
+    }
+    if let Err(ref e) = res
+        && let Some(no_captions) = e.downcast_ref::<NoCaptions>()
+    {
+        tracing::debug!("{no_captions}, skipping it");
+        return Ok(());
+    }
+    record_course_stats(&file, res.is_ok(), &options);
+    if res.is_ok() && options.on_download.is_some() {
+        fork!(
+            run_download_hook,
+            file.filepath.clone(),
+            PathBuf,
+            options.clone(),
+            sem_hooks
+        );
+    }
+    res
+}
+
+/// Run the --on-download command for a downloaded file. A failing command is logged but
+/// doesn't count as a failed download.
+async fn run_download_hook(filepath: PathBuf, options: Arc<ProcessOptions>) -> Result<()> {
+    let Some(ref command) = options.on_download else {
+        return Ok(());
+    };
+    let quoted_path = shell_quote(&filepath);
+    let command = if command.contains("{}") {
+        command.replace("{}", &quoted_path)
+    } else {
+        format!("{command} {quoted_path}")
+    };
+    let mut shell = if cfg!(windows) {
+        let mut shell = tokio::process::Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = tokio::process::Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    match shell.arg(&command).output().await {
+        Ok(output) if output.status.success() => {
+            tracing::debug!(
+                "--on-download command for {:?} finished: {}",
+                filepath,
+                String::from_utf8_lossy(&output.stdout).trim()
+            );
+        }
+        Ok(output) => tracing::warn!(
+            "--on-download command exited with {} for {:?}: {}",
+            output.status,
+            filepath,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(e) => tracing::warn!("Could not run --on-download command {command:?}: {e}"),
+    }
+    Ok(())
+}
+
+/// A path as one shell word, e.g. 'it'\''s.pdf' for sh
+fn shell_quote(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    if cfg!(windows) {
+        // Windows paths can't contain double quotes
+        format!("\"{path}\"")
+    } else {
+        format!("'{}'", path.replace('\'', "'\\''"))
+    }
+}
+
+/// Canvas served a web page in place of a file it won't hand out
+#[derive(Debug)]
+struct FileUnavailable(String);
+
+impl std::fmt::Display for FileUnavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Canvas returned a web page instead of {}, the file is probably locked or no longer available",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for FileUnavailable {}
+
+/// A Panopto session has no captions, which is common and not worth a warning
+#[derive(Debug)]
+struct NoCaptions(String);
+
+impl std::fmt::Display for NoCaptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No captions found for {}", self.0)
+    }
+}
+
+impl std::error::Error for NoCaptions {}
+
+/// Whether the file is captions, see videos::queue_captions
+fn is_captions(canvas_file: &File) -> bool {
+    Path::new(&canvas_file.display_name)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("srt"))
+}
+
+/// Give different files of one listing that would be saved under the same name "name (1).ext",
+/// "name (2).ext", ... in the order of their ids, so every run saves and checks each of them
+/// under the same name regardless of the order they were found in
+fn number_same_named_files(files: &mut [File]) {
+    let mut same_named: HashMap<PathBuf, Vec<usize>> = HashMap::new();
+    for (i, f) in files.iter().enumerate() {
+        same_named.entry(f.filepath.clone()).or_default().push(i);
+    }
+    for (filepath, mut indices) in same_named {
+        indices.sort_by(|&a, &b| (files[a].id, &files[a].url).cmp(&(files[b].id, &files[b].url)));
+        // The same file listed twice keeps one name, see dedup_files
+        indices.dedup_by(|a, b| files[*a].id != 0 && files[*a].id == files[*b].id);
+        for (n, &i) in indices.iter().enumerate().skip(1) {
+            files[i].filepath = filepath.with_file_name(claimed_name(&filepath, n));
+        }
+    }
+}
+
+/// Reserve the target path for this run, so two different files with the same name in a
+/// folder don't overwrite each other: later ones become "name (1).ext", "name (2).ext", ...
+/// Files of one listing are already numbered by number_same_named_files, so this only
+/// catches files from different listings that end up in the same place.
+fn claim_filepath(filepath: &Path, options: &ProcessOptions) -> PathBuf {
+    let mut claimed_paths = options
+        .claimed_paths
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if claimed_paths.insert(filepath.to_path_buf()) {
+        return filepath.to_path_buf();
+    }
+
+    let mut n = 1;
+    loop {
+        let candidate = filepath.with_file_name(claimed_name(filepath, n));
+        if claimed_paths.insert(candidate.clone()) {
+            tracing::debug!(
+                "{:?} is taken by another file, saving as {:?}",
+                filepath,
+                candidate
+            );
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+/// n-th alternative name for a taken path, e.g. "slides (2).pdf"
+fn claimed_name(filepath: &Path, n: usize) -> String {
+    let stem = filepath.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = filepath
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    format!("{stem} ({n}){ext}")
+}
+
+/// Attribute a finished download to the course folder it was saved under
+fn record_course_stats(file: &File, success: bool, options: &ProcessOptions) {
+    let mut course_stats = options
+        .course_stats
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    // main() adds an entry for every course folder before discovery
+    let Some(course_folder) = file
+        .filepath
+        .ancestors()
+        .find(|folder| course_stats.contains_key(*folder))
+        .map(Path::to_path_buf)
+    else {
+        return;
+    };
+    let stats = course_stats.entry(course_folder).or_default();
+    if success {
+        // Videos and links have no size up front, so use what landed on disk
+        let size = std::fs::metadata(&file.filepath)
+            .map(|m| m.len())
+            .unwrap_or(file.size);
+        stats.files_downloaded += 1;
+        stats.bytes_downloaded += size;
+        options
+            .downloaded_files
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push((file.filepath.clone(), size));
+        options
+            .downloaded_ids
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert((file.id, file.url.clone()));
+    } else {
+        stats.errors += 1;
+    }
+}
+
+async fn download_and_rename(file: &File, options: &Arc<ProcessOptions>) -> Result<()> {
     // Create tmp file from hash
     let mut tmp_path = file.filepath.clone();
     tmp_path.pop();
+    // Type folders of --group-by-type are only created once a file goes in
+    if options.group_by_type {
+        std::fs::create_dir_all(&tmp_path)
+            .with_context(|| format!("Failed to create directory: {}", tmp_path.display()))?;
+    }
     let mut h = DefaultHasher::new();
-    file.display_name.hash(&mut h);
+    file.id.hash(&mut h);
+    file.filepath.hash(&mut h);
     tmp_path.push(h.finish().to_string().add(".tmp"));
+    let _tmp_guard = TmpFileGuard::new(&tmp_path);
 
     // Aborted download?
-    if let Err(e) = download_file((&tmp_path, &file), options.clone()).await {
-        if let Err(e) = std::fs::remove_file(&tmp_path) {
-            tracing::error!(
-                "Failed to remove temporary file {tmp_path:?} for {}, err={e:?}",
-                file.display_name
-            );
+    let mut downloaded = match download_file((&tmp_path, file), options.clone()).await {
+        Ok(downloaded) => downloaded,
+        Err(e) => {
+            // Keep partial data from network failures so the next run can resume it
+            if e.downcast_ref::<reqwest::Error>().is_some() && tmp_path.exists() {
+                tracing::debug!(
+                    "Keeping partial download {tmp_path:?} of {} for resuming",
+                    file.display_name
+                );
+                return Err(e);
+            }
+            let _ = std::fs::remove_file(validator_path(&tmp_path));
+            if let Err(e) = std::fs::remove_file(&tmp_path) {
+                tracing::error!(
+                    "Failed to remove temporary file {tmp_path:?} for {}, err={e:?}",
+                    file.display_name
+                );
+            }
+            return Err(e);
+        }
+    };
+    let _ = std::fs::remove_file(validator_path(&tmp_path));
+
+    if file.remux_to_mp4 {
+        if let Err(e) = remux_to_mp4(&tmp_path, options).await {
+            if let Err(e) = std::fs::remove_file(&tmp_path) {
+                tracing::error!(
+                    "Failed to remove temporary file {tmp_path:?} for {}, err={e:?}",
+                    file.display_name
+                );
+            }
+            return Err(e);
+        }
+        // Remuxing rewrote the content, so the hash of the streamed bytes is stale
+        if downloaded.sha256.is_some() {
+            downloaded.sha256 = sha256_file(&tmp_path)
+                .inspect_err(|e| tracing::error!("Failed to hash {}, err={e:?}", file.display_name))
+                .ok();
         }
-        return Err(e);
     }
 
     // Update file time
-    let updated_at = DateTime::parse_from_rfc3339(&file.updated_at)?;
-    let updated_time = filetime::FileTime::from_unix_time(
-        updated_at.timestamp(),
-        updated_at.timestamp_subsec_nanos(),
-    );
-    if let Err(e) = filetime::set_file_mtime(&tmp_path, updated_time) {
+    if let Err(e) = set_file_updated_at(&tmp_path, &file.updated_at) {
         tracing::error!(
             "Failed to set modified time of {} with updated_at of {}, err={e:?}",
             file.display_name,
@@ -50,60 +366,517 @@ pub async fn atomic_download_file(file: File, options: Arc<ProcessOptions>) -> R
 
     // Atomically rename file, doesn't change mtime
     std::fs::rename(&tmp_path, &file.filepath)?;
+
+    if options.track_etags
+        && let Some(etag) = downloaded.etag
+        && let Err(e) = record_etag(&file.filepath, &etag, options)
+    {
+        tracing::error!("Failed to record ETag for {}, err={e:?}", file.display_name);
+    }
+    if let Some(ref manifest) = options.manifest {
+        manifest.record(&file.filepath, &file.updated_at);
+    }
+    if let Some(sha256) = downloaded.sha256 {
+        options
+            .file_checksums
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push((file.filepath.clone(), sha256));
+    }
+    Ok(())
+}
+
+/// Turn a downloaded MPEG-TS video into an MP4 in place, without re-encoding
+async fn remux_to_mp4(tmp_path: &Path, options: &ProcessOptions) -> Result<()> {
+    let Some(ref ffmpeg) = options.ffmpeg else {
+        return Ok(());
+    };
+    let remuxed_path = tmp_path.with_extension("mp4.tmp");
+    let _tmp_guard = TmpFileGuard::new(&remuxed_path);
+    let output = tokio::process::Command::new(ffmpeg)
+        .args(["-y", "-loglevel", "error", "-f", "mpegts", "-i"])
+        .arg(tmp_path)
+        .args(["-c", "copy", "-f", "mp4"])
+        .arg(&remuxed_path)
+        .output()
+        .await
+        .with_context(|| format!("Failed to run {:?}", ffmpeg))?;
+    if !output.status.success() {
+        let _ = std::fs::remove_file(&remuxed_path);
+        return Err(anyhow::anyhow!(
+            "ffmpeg exited with {} when remuxing {:?}: {}",
+            output.status,
+            tmp_path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    std::fs::rename(&remuxed_path, tmp_path)?;
+    Ok(())
+}
+
+fn set_file_updated_at(filepath: &Path, updated_at: &str) -> Result<()> {
+    let updated_at = DateTime::parse_from_rfc3339(updated_at)?;
+    let updated_time = filetime::FileTime::from_unix_time(
+        updated_at.timestamp(),
+        updated_at.timestamp_subsec_nanos(),
+    );
+    filetime::set_file_mtime(filepath, updated_time)?;
     Ok(())
 }
 
+/// What download_file learned about the content it wrote
+struct Downloaded {
+    etag: Option<String>,   // if the server sent one
+    sha256: Option<String>, // with --checksums
+}
+
+/// Writer that hashes the bytes passing through it, so --checksums needs no re-read
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Option<Sha256>,
+}
+
+impl<W> HashingWriter<W> {
+    fn new(inner: W, hash: bool) -> Self {
+        HashingWriter {
+            inner,
+            hasher: hash.then(Sha256::new),
+        }
+    }
+
+    fn finish(self) -> Option<String> {
+        self.hasher.map(|hasher| format!("{:x}", hasher.finalize()))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if let Some(ref mut hasher) = self.hasher {
+            hasher.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn sha256_file(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
 async fn download_file(
     (tmp_path, canvas_file): (&Path, &File),
     options: Arc<ProcessOptions>,
-) -> Result<()> {
+) -> Result<Downloaded> {
+    if !canvas_file.segments.is_empty() {
+        let sha256 = download_segments((tmp_path, canvas_file), options).await?;
+        return Ok(Downloaded { etag: None, sha256 });
+    }
+
+    // Resume from a temp file left behind by an interrupted download, as long as the server
+    // can confirm through If-Range that the file is still the one it started from
+    let mut resume_from = std::fs::metadata(tmp_path).map(|m| m.len()).unwrap_or(0);
+    let validator_path = validator_path(tmp_path);
+    let mut validator = std::fs::read_to_string(&validator_path).ok();
+    if resume_from > 0 && validator.is_none() {
+        tracing::debug!(
+            "Cannot tell if the partial download of {} is still current, restarting download",
+            canvas_file.display_name
+        );
+        resume_from = 0;
+    }
+    let request = |range_start: u64, validator: Option<&str>| {
+        let mut req = file_request(&canvas_file.url, canvas_file, &options);
+        if range_start > 0 {
+            req = req.header(header::RANGE, format!("bytes={range_start}-"));
+            if let Some(validator) = validator {
+                req = req.header(header::IF_RANGE, validator);
+            }
+        }
+        req.send()
+    };
+
     // Get file
-    let mut resp = options
-        .client
-        .get(&canvas_file.url)
-        .bearer_auth(&options.canvas_token)
-        .send()
+    let mut resp = request(resume_from, validator.as_deref())
         .await
         .with_context(|| format!("Something went wrong when reaching {}", canvas_file.url))?;
+    if resume_from > 0 && resp.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        // Temp file doesn't match the remote file anymore, start over
+        tracing::debug!(
+            "Cannot resume {}, restarting download",
+            canvas_file.display_name
+        );
+        resume_from = 0;
+        resp = request(resume_from, None)
+            .await
+            .with_context(|| format!("Something went wrong when reaching {}", canvas_file.url))?;
+    }
+    // Sessions without captions give an error, an empty body or an HTML error page
+    if is_captions(canvas_file)
+        && (!resp.status().is_success()
+            || resp.content_length() == Some(0)
+            || is_html_placeholder(&resp, canvas_file))
+    {
+        return Err(NoCaptions(canvas_file.display_name.clone()).into());
+    }
     if !resp.status().is_success() {
         return Err(Error::msg(format!(
             "Failed to download {}, got {resp:?}",
             canvas_file.display_name
         )));
     }
+    // Locked or purged files sometimes come back as a 200 with an HTML error page
+    if is_html_placeholder(&resp, canvas_file) {
+        return Err(FileUnavailable(canvas_file.display_name.clone()).into());
+    }
+
+    // Servers ignoring Range, or whose file changed since (If-Range), reply 200 with the
+    // full body, so truncate and restart then
+    let resuming = resume_from > 0 && resp.status() == StatusCode::PARTIAL_CONTENT;
+    if resuming {
+        tracing::debug!(
+            "Resuming {} from byte {resume_from}",
+            canvas_file.display_name
+        );
+    } else {
+        resume_from = 0;
+        validator = save_validator(&resp, &validator_path);
+    }
 
     // Create + Open file
-    let mut file = std::fs::File::create(tmp_path)
-        .with_context(|| format!("Unable to create tmp file for {:?}", canvas_file.filepath))?;
+    let file = if resuming {
+        std::fs::OpenOptions::new().append(true).open(tmp_path)
+    } else {
+        std::fs::File::create(tmp_path)
+    }
+    .with_context(|| format!("Unable to create tmp file for {:?}", canvas_file.filepath))?;
+    let mut file = HashingWriter::new(file, options.checksums);
+    if resuming && let Some(ref mut hasher) = file.hasher {
+        // Only the part downloaded on an earlier run has to be read back
+        std::io::copy(&mut std::fs::File::open(tmp_path)?, hasher)?;
+    }
 
     // Progress bar
+    let progress_bar = file_progress_bar(resume_from, &options);
+    progress_bar.set_position(resume_from);
+    progress_bar.set_message(canvas_file.display_name.to_string());
+    // Only the bytes an earlier run left on disk; retries below count what they re-download
+    options.total_progress.add_bytes(resume_from);
+
+    // Download
+    let etag = resp
+        .headers()
+        .get(header::ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .map(str::to_string);
+    let mut result = write_response(resp, &mut file, &progress_bar, canvas_file, 0, &options).await;
+
+    // A dropped connection resumes where it stopped; local failures like a full disk
+    // wouldn't go away by retrying
+    let mut retry = 0;
+    while let Err(ref e) = result
+        && retry + 1 < options.retries
+        && e.downcast_ref::<std::io::Error>().is_none()
+    {
+        let offset = std::fs::metadata(tmp_path).map(|m| m.len()).unwrap_or(0);
+        let wait_time = backoff_delay(retry);
+        tracing::debug!(
+            "Download of {} broke off at byte {offset}, resuming in {wait_time:?}: {e:?}",
+            canvas_file.display_name
+        );
+        tokio::time::sleep(wait_time).await;
+        retry += 1;
+        result = async {
+            let resp = request(offset, validator.as_deref())
+                .await?
+                .error_for_status()?;
+            // Without Range support the only way to continue is from the start, and the
+            // bytes up to the offset are already in the total progress
+            let (offset, counted) = if resp.status() == StatusCode::PARTIAL_CONTENT {
+                (offset, 0)
+            } else {
+                validator = save_validator(&resp, &validator_path);
+                let restarted = std::fs::File::create(tmp_path).with_context(|| {
+                    format!("Unable to create tmp file for {:?}", canvas_file.filepath)
+                })?;
+                file = HashingWriter::new(restarted, options.checksums);
+                (0, offset)
+            };
+            progress_bar.set_length(offset);
+            progress_bar.set_position(offset);
+            write_response(
+                resp,
+                &mut file,
+                &progress_bar,
+                canvas_file,
+                counted,
+                &options,
+            )
+            .await
+        }
+        .await;
+    }
+    result.with_context(|| {
+        format!(
+            "Failed to download {} after {} attempt{}",
+            canvas_file.display_name,
+            retry + 1,
+            if retry == 0 { "" } else { "s" }
+        )
+    })?;
+    progress_bar.finish();
+    if is_captions(canvas_file) && std::fs::metadata(tmp_path).is_ok_and(|m| m.len() == 0) {
+        return Err(NoCaptions(canvas_file.display_name.clone()).into());
+    }
+    Ok(Downloaded {
+        etag,
+        sha256: file.finish(),
+    })
+}
+
+/// Where the ETag or Last-Modified of a partial download is kept for If-Range. It ends in
+/// .tmp like the partial download, so --clean leaves it alone too.
+fn validator_path(tmp_path: &Path) -> PathBuf {
+    tmp_path.with_extension("validator.tmp")
+}
+
+/// Remember the response's strong ETag, or else its Last-Modified, for resuming the download
+/// on a later run. Without either, a later run can't resume and starts over.
+fn save_validator(resp: &reqwest::Response, validator_path: &Path) -> Option<String> {
+    let headers = resp.headers();
+    let validator = headers
+        .get(header::ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        // If-Range only accepts strong ETags
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| {
+            headers
+                .get(header::LAST_MODIFIED)
+                .and_then(|last_modified| last_modified.to_str().ok())
+        })
+        .map(str::to_string);
+    let saved = match validator {
+        Some(ref validator) => std::fs::write(validator_path, validator),
+        None => std::fs::remove_file(validator_path).or(Ok(())),
+    };
+    if let Err(e) = saved {
+        tracing::debug!("Could not write {validator_path:?}: {e}");
+    }
+    validator
+}
+
+/// Extensions of files that may legitimately be served as text/html
+const HTML_LIKE_EXTENSIONS: [&str; 6] = ["html", "htm", "xhtml", "txt", "xml", "url"];
+
+/// Whether the response is an HTML page although the file's name says it's something else
+fn is_html_placeholder(resp: &reqwest::Response, canvas_file: &File) -> bool {
+    let is_html = resp
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|x| x.to_str().ok())
+        .is_some_and(|content_type| content_type.trim_start().starts_with("text/html"));
+    let extension = Path::new(&canvas_file.display_name)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    is_html && extension.is_some_and(|ext| !HTML_LIKE_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// Download HLS segments in order and concatenate them into a single file. Each segment is
+/// retried with backoff; one that keeps failing aborts the video with --strict-videos and is
+/// left out otherwise, so a single bad segment doesn't cost the whole lecture.
+/// Returns the SHA-256 of the video with --checksums.
+async fn download_segments(
+    (tmp_path, canvas_file): (&Path, &File),
+    options: Arc<ProcessOptions>,
+) -> Result<Option<String>> {
+    // Segments can't be resumed individually, so always start from scratch
+    let file = std::fs::File::create(tmp_path)
+        .with_context(|| format!("Unable to create tmp file for {:?}", canvas_file.filepath))?;
+    let mut file = HashingWriter::new(file, options.checksums);
+
+    // Total size is only known segment by segment
+    let progress_bar = file_progress_bar(0, &options);
+
+    let n_segments = canvas_file.segments.len();
+    let mut missing_segments = Vec::new();
+    for (i, segment_url) in canvas_file.segments.iter().enumerate() {
+        progress_bar.set_message(format!(
+            "{} [{}/{n_segments}]",
+            canvas_file.display_name,
+            i + 1
+        ));
+
+        let mut retry = 0;
+        let segment = loop {
+            match download_segment(segment_url, &progress_bar, canvas_file, &options).await {
+                Ok(segment) => break Some(segment),
+                Err(e) if retry + 1 < options.retries => {
+                    let wait_time = backoff_delay(retry);
+                    tracing::debug!(
+                        "Segment {}/{n_segments} of {} failed, retrying in {wait_time:?}: {e:?}",
+                        i + 1,
+                        canvas_file.display_name
+                    );
+                    tokio::time::sleep(wait_time).await;
+                    retry += 1;
+                }
+                Err(e) if options.strict_videos => {
+                    return Err(e.context(format!(
+                        "Failed to download segment {}/{n_segments} of {}",
+                        i + 1,
+                        canvas_file.display_name
+                    )));
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Skipping segment {}/{n_segments} of {} after {} attempts, the video will have a gap: {e:?}",
+                        i + 1,
+                        canvas_file.display_name,
+                        options.retries
+                    );
+                    break None;
+                }
+            }
+        };
+        match segment {
+            Some(segment) => file
+                .write_all(&segment)
+                .with_context(|| format!("Could not write to file {:?}", canvas_file.filepath))?,
+            None => missing_segments.push(i + 1),
+        }
+    }
+    progress_bar.finish();
+
+    if !missing_segments.is_empty() {
+        tracing::warn!(
+            "{} is incomplete, {} of {n_segments} segments are missing",
+            canvas_file.filepath.display(),
+            missing_segments.len()
+        );
+        options
+            .partial_videos
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push((canvas_file.filepath.clone(), missing_segments));
+    }
+    Ok(file.finish())
+}
+
+/// GET `url` for `canvas_file`, with the file's own client if it has one, e.g. Panopto's.
+/// Only Canvas downloads get the Canvas token.
+fn file_request(
+    url: &str,
+    canvas_file: &File,
+    options: &ProcessOptions,
+) -> reqwest::RequestBuilder {
+    match canvas_file.client {
+        Some(ref client) => client.get(url),
+        None => options.client.get(url).bearer_auth(&options.canvas_token),
+    }
+}
+
+/// Download one HLS segment into memory, so a failed attempt leaves nothing in the video
+async fn download_segment(
+    segment_url: &str,
+    progress_bar: &indicatif::ProgressBar,
+    canvas_file: &File,
+    options: &ProcessOptions,
+) -> Result<Vec<u8>> {
+    let (position, length) = (progress_bar.position(), progress_bar.length());
+    let result = async {
+        let resp = file_request(segment_url, canvas_file, options)
+            .send()
+            .await
+            .with_context(|| format!("Something went wrong when reaching {segment_url}"))?;
+        if !resp.status().is_success() {
+            return Err(Error::msg(format!("Got {resp:?}")));
+        }
+        let mut segment = Vec::new();
+        write_response(resp, &mut segment, progress_bar, canvas_file, 0, options).await?;
+        Ok(segment)
+    }
+    .await;
+    if result.is_err() {
+        // Undo the progress of the failed attempt
+        progress_bar.set_position(position);
+        if let Some(length) = length {
+            progress_bar.set_length(length);
+        }
+    }
+    result
+}
+
+/// Bar for one download, left hidden with --progress total
+fn file_progress_bar(length: u64, options: &ProcessOptions) -> indicatif::ProgressBar {
+    let progress_bar = if options.progress == ProgressMode::Total {
+        indicatif::ProgressBar::hidden()
+    } else {
+        options
+            .progress_bars
+            .add(indicatif::ProgressBar::new(length))
+    };
+    progress_bar.set_length(length);
+    progress_bar.set_style(options.progress_style.clone());
+    progress_bar
+}
+
+/// Stream a response body into `file`, checking it against the Content-Length when present.
+/// The first `counted` bytes were already added to the total progress by an earlier attempt.
+async fn write_response(
+    mut resp: reqwest::Response,
+    file: &mut impl std::io::Write,
+    progress_bar: &indicatif::ProgressBar,
+    canvas_file: &File,
+    mut counted: u64,
+    options: &ProcessOptions,
+) -> Result<()> {
     let download_size = resp
         .headers() // Gives us the HeaderMap
         .get(header::CONTENT_LENGTH) // Gives us an Option containing the HeaderValue
         .and_then(|ct_len| ct_len.to_str().ok()) // Unwraps the Option as &str
         .and_then(|ct_len| ct_len.parse().ok()) // Parses the Option as u64
         .unwrap_or(0); // Fallback to 0
-    let progress_bar = options
-        .progress_bars
-        .add(indicatif::ProgressBar::new(download_size));
-    progress_bar.set_message(canvas_file.display_name.to_string());
-    progress_bar.set_style(options.progress_style.clone());
+    progress_bar.inc_length(download_size);
+    // Queued files of unknown size aren't in the total yet
+    if canvas_file.size == 0 {
+        options.total_progress.add_length(download_size);
+    }
 
-    // Download
+    let mut bytes_written: u64 = 0;
     while let Some(chunk) = resp.chunk().await? {
-        progress_bar.inc(chunk.len() as u64);
+        if let Some(ref rate_limiter) = options.rate_limiter {
+            rate_limiter.acquire(chunk.len() as u64).await;
+        }
+        let chunk_len = chunk.len() as u64;
+        progress_bar.inc(chunk_len);
+        options
+            .total_progress
+            .add_bytes(chunk_len.saturating_sub(counted));
+        counted = counted.saturating_sub(chunk_len);
         let mut cursor = std::io::Cursor::new(chunk);
-        std::io::copy(&mut cursor, &mut file)
+        bytes_written += std::io::copy(&mut cursor, file)
             .with_context(|| format!("Could not write to file {:?}", canvas_file.filepath))?;
     }
 
-    progress_bar.finish();
+    // Truncated transfer? Only checkable when Content-Length was sent
+    if download_size != 0 && bytes_written != download_size {
+        return Err(Error::msg(format!(
+            "Incomplete download of {}, expected {download_size} bytes but got {bytes_written}",
+            canvas_file.display_name
+        )));
+    }
     Ok(())
 }
 
 // async recursion needs boxing
+/// List the folders at `url`, which are `depth` levels below the course's files folder
 pub async fn process_folders(
-    (url, path): (String, PathBuf),
+    (url, path, depth): (String, PathBuf, usize),
     options: Arc<ProcessOptions>,
 ) -> Result<()> {
     let pages = get_pages(url, &options).await?;
@@ -118,7 +891,22 @@ pub async fn process_folders(
             Ok(FolderResult::Ok(folders)) => {
                 for folder in folders {
                     // println!("  * {} - {}", folder.id, folder.name);
-                    let sanitized_folder_name = sanitize_filename::sanitize(folder.name);
+                    // Shared or linked folders can lead back to one crawled already
+                    let first_visit = options
+                        .visited_folders
+                        .lock()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .insert(folder.id);
+                    if !first_visit {
+                        tracing::debug!(
+                            "Skipping folder {} ({}) at {path:?}, already crawled",
+                            folder.id,
+                            folder.name
+                        );
+                        continue;
+                    }
+                    let sanitized_folder_name =
+                        sanitize_path_component(&folder.name, options.filename_mode);
                     // if the folder has no parent, it is the root folder of a course
                     // so we avoid the extra directory nesting by not appending the root folder name
                     let folder_path = if folder.parent_folder_id.is_some() {
@@ -142,10 +930,22 @@ pub async fn process_folders(
                         (String, PathBuf),
                         options.clone()
                     );
+                    if let Some(max_depth) = options.max_depth
+                        && depth >= max_depth
+                    {
+                        if folder.folders_count > 0 {
+                            tracing::warn!(
+                                "Skipping {} subfolder{} of {folder_path:?} deeper than --max-depth {max_depth}",
+                                folder.folders_count,
+                                if folder.folders_count == 1 { "" } else { "s" }
+                            );
+                        }
+                        continue;
+                    }
                     fork!(
                         process_folders,
-                        (folder.folders_url, folder_path),
-                        (String, PathBuf),
+                        (folder.folders_url, folder_path, depth + 1),
+                        (String, PathBuf, usize),
                         options.clone()
                     );
                 }
@@ -155,15 +955,17 @@ pub async fn process_folders(
             Ok(FolderResult::Err { status }) => {
                 let course_has_no_folders = status == "unauthorized";
                 if !course_has_no_folders {
-                    tracing::error!(
-                        "Failed to access folders at link:{uri}, path:{path:?}, status:{status}",
-                    );
+                    options.record_error(anyhow::anyhow!(
+                        "Failed to access folders at link:{uri}, path:{path:?}, status:{status}"
+                    ));
                 }
             }
 
             // Parse error
             Err(e) => {
-                tracing::error!("Error when getting folders at link:{uri}, path:{path:?}\n{e:?}",);
+                options.record_error(anyhow::anyhow!(
+                    "Error when getting folders at link:{uri}, path:{path:?}\n{e:?}"
+                ));
             }
         }
     }
@@ -175,7 +977,18 @@ pub async fn process_files(
     (url, path): (String, PathBuf),
     options: Arc<ProcessOptions>,
 ) -> Result<()> {
-    let pages = get_pages(url, &options).await?;
+    let pages = match options.since {
+        // Newest first, so pages past the cutoff don't have to be fetched at all
+        Some(since) => {
+            let separator = if url.contains('?') { '&' } else { '?' };
+            let url = format!("{url}{separator}sort=updated_at&order=desc");
+            get_pages_until(url, &options, |body| all_updated_before(body, since)).await?
+        }
+        None => get_pages(url, &options).await?,
+    };
+    // Every file Canvas lists in this folder, for --clean; None once a page fails.
+    // With --since the listing may stop early, so nothing can be told stale then.
+    let mut listed_paths = options.since.is_none().then(Vec::new);
 
     // For each page
     for pg in pages {
@@ -185,6 +998,18 @@ pub async fn process_files(
         match files_result {
             // Got files
             Ok(FileResult::Ok(files)) => {
+                if options.link_module_files {
+                    record_course_file_paths(&options, &path, &files);
+                }
+                if options.clean
+                    && let Some(ref mut listed_paths) = listed_paths
+                {
+                    listed_paths.extend(
+                        files.iter().map(|f| {
+                            flatten_path(&path.join(local_file_name(f, &options)), &options)
+                        }),
+                    );
+                }
                 let mut filtered_files = filter_files(&options, &path, files);
                 let mut lock = options.files_to_download.lock().await;
                 lock.append(&mut filtered_files);
@@ -192,46 +1017,345 @@ pub async fn process_files(
 
             // Got status code
             Ok(FileResult::Err { status }) => {
+                listed_paths = None;
                 let course_has_no_files = status == "unauthorized";
                 if !course_has_no_files {
-                    tracing::error!(
-                        "Failed to access files at link:{uri}, path:{path:?}, status:{status}",
-                    );
+                    options.record_error(anyhow::anyhow!(
+                        "Failed to access files at link:{uri}, path:{path:?}, status:{status}"
+                    ));
                 }
             }
 
             // Parse error
             Err(e) => {
-                tracing::error!("Error when getting files at link:{uri}, path:{path:?}\n{e:?}",);
+                listed_paths = None;
+                options.record_error(anyhow::anyhow!(
+                    "Error when getting files at link:{uri}, path:{path:?}\n{e:?}"
+                ));
             }
         };
     }
 
+    if let Some(listed_paths) = listed_paths {
+        record_listed_files(&options, &path, listed_paths);
+    }
+
     Ok(())
 }
 
+/// Drop files queued more than once, e.g. found both in a folder and through a module or link.
+/// Canvas files are keyed by (id, path), or by id alone with --group-by-type, where the
+/// folder a file was found in doesn't matter; files without an id (id 0) by their URL.
+pub fn dedup_files(files: &mut Vec<File>, group_by_type: bool) {
+    #[derive(PartialEq, Eq, Hash)]
+    enum Key {
+        Canvas(u32, PathBuf),
+        Url(String),
+    }
+
+    let mut seen = std::collections::HashSet::new();
+    let before = files.len();
+    files.retain(|f| {
+        let key = if f.id != 0 && group_by_type {
+            Key::Canvas(f.id, PathBuf::new())
+        } else if f.id != 0 {
+            Key::Canvas(f.id, f.filepath.clone())
+        } else {
+            Key::Url(f.url.clone())
+        };
+        seen.insert(key)
+    });
+    if files.len() < before {
+        tracing::debug!(
+            "Dropped {} duplicate files from the queue",
+            before - files.len()
+        );
+    }
+}
+
+/// Remember which files Canvas lists in a folder, for --clean. Same-named files are saved
+/// as "name (1).ext", ... (see claim_filepath), so those names are expected as well.
+fn record_listed_files(options: &ProcessOptions, path: &Path, listed_paths: Vec<PathBuf>) {
+    if !options.clean {
+        return;
+    }
+    let mut listed_files = options
+        .listed_files
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    // A folder that Canvas lists as empty still needs cleaning, unless it's flattened away
+    if path.is_dir() {
+        listed_files.entry(path.to_path_buf()).or_default();
+    }
+    for listed_path in listed_paths {
+        let Some(folder) = listed_path.parent() else {
+            continue;
+        };
+        let names = listed_files.entry(folder.to_path_buf()).or_default();
+        let mut name = listed_path.file_name().unwrap_or_default().to_os_string();
+        let mut n = 1;
+        while !names.insert(name) {
+            name = claimed_name(&listed_path, n).into();
+            n += 1;
+        }
+    }
+}
+
+/// Move local files that Canvas no longer lists into .trash/ under the download folder,
+/// keeping their relative path. Only folders of the files/ tree that were listed completely
+/// are cleaned; partial downloads (*.tmp) and the ETag state file are kept.
+pub fn clean_stale_files(options: &ProcessOptions, dry_run: bool) -> usize {
+    let listed_files = options
+        .listed_files
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let trash_path = options.base_path.join(".trash");
+
+    let mut n_cleaned = 0;
+    for (folder, names) in listed_files.iter() {
+        let Ok(entries) = std::fs::read_dir(folder) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            if !entry.file_type().is_ok_and(|t| t.is_file())
+                || names.contains(&name)
+                || name == STATE_FILE_NAME
+                || Path::new(&name).extension().is_some_and(|ext| ext == "tmp")
+            {
+                continue;
+            }
+
+            let stale_path = entry.path();
+            let relative_path = stale_path
+                .strip_prefix(&options.base_path)
+                .unwrap_or(&stale_path);
+            let trashed_path = trash_path.join(relative_path);
+            if dry_run {
+                status!("  {} (removed on Canvas)", stale_path.to_string_lossy());
+                n_cleaned += 1;
+                continue;
+            }
+            let moved = trashed_path
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|_| std::fs::rename(&stale_path, &trashed_path));
+            match moved {
+                Ok(()) => {
+                    tracing::info!(
+                        "Moved {:?}, removed on Canvas, to {:?}",
+                        stale_path,
+                        trashed_path
+                    );
+                    n_cleaned += 1;
+                }
+                Err(e) => {
+                    tracing::warn!("Could not move {:?} to {:?}: {e}", stale_path, trashed_path)
+                }
+            }
+        }
+    }
+    n_cleaned
+}
+
+/// Remember where each course file lives (or will live) under files/, for --link-module-files
+fn record_course_file_paths(options: &ProcessOptions, path: &Path, files: &[File]) {
+    let mut course_file_paths = options
+        .course_file_paths
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    for f in files {
+        let filepath = path.join(local_file_name(f, options));
+        if !ignored(
+            &filepath,
+            false,
+            &options.base_path,
+            options.ignore_matcher.as_deref(),
+        ) {
+            course_file_paths.insert(f.id, flatten_path(&filepath, options));
+        }
+    }
+}
+
+/// Split module files into those with a copy under files/ to link to, along with that copy's
+/// path, and those that still need downloading
+pub fn module_file_links(
+    module_files: Vec<File>,
+    options: &ProcessOptions,
+) -> (Vec<(File, PathBuf)>, Vec<File>) {
+    let course_file_paths = options
+        .course_file_paths
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    let mut links = Vec::new();
+    let mut to_download = Vec::new();
+    for f in module_files {
+        match course_file_paths.get(&f.id) {
+            Some(target) => links.push((f, target.clone())),
+            None => to_download.push(f),
+        }
+    }
+    (links, to_download)
+}
+
+/// Link module files to the same file under files/ and return those that couldn't be linked,
+/// to download them instead. Links are relative symlinks, or a .txt file holding the target
+/// path on Windows.
+pub fn link_module_files(links: Vec<(File, PathBuf)>) -> Vec<File> {
+    let mut to_download = Vec::new();
+    for (f, target) in links {
+        if let Err(e) = link_file(&f.filepath, &target) {
+            tracing::warn!(
+                "Could not link {:?} to {:?}, downloading instead: {e:?}",
+                f.filepath,
+                target
+            );
+            to_download.push(f);
+        }
+    }
+    to_download
+}
+
+#[cfg(unix)]
+fn link_file(link: &Path, target: &Path) -> Result<()> {
+    let link_dir = link.parent().context("Link has no parent folder")?;
+    let target = crate::utils::relative_path(link_dir, target);
+    // A dangling link from an earlier run would block the new one
+    if link.symlink_metadata().is_ok() {
+        std::fs::remove_file(link)?;
+    }
+    std::os::unix::fs::symlink(&target, link)
+        .with_context(|| format!("Could not create symlink {:?}", link))
+}
+
+#[cfg(not(unix))]
+fn link_file(link: &Path, target: &Path) -> Result<()> {
+    let mut pointer = link.as_os_str().to_owned();
+    pointer.push(".txt");
+    std::fs::write(&pointer, target.to_string_lossy().as_bytes())
+        .with_context(|| format!("Could not write to file {:?}", pointer))
+}
+
+/// Whether `updated_at` is later than a recorded `updated_at`
+fn newer_than(updated_at: &str, recorded: &str) -> bool {
+    match (
+        DateTime::parse_from_rfc3339(updated_at),
+        DateTime::parse_from_rfc3339(recorded),
+    ) {
+        (Ok(updated_at), Ok(recorded)) => updated_at > recorded,
+        _ => updated_at != recorded,
+    }
+}
+
 fn updated(filepath: &Path, new_modified: &str) -> bool {
     (|| -> Result<bool> {
         let old_modified = std::fs::metadata(filepath)?.modified()?;
         let new_modified = std::time::SystemTime::from(DateTime::parse_from_rfc3339(new_modified)?);
         let updated = old_modified < new_modified;
         if updated {
-            println!("Found update for {filepath:?}.");
+            tracing::info!("Found update for {filepath:?}.");
         }
         Ok(updated)
     })()
     .unwrap_or(false)
 }
+
+/// Whether every file on a listing page was updated before `since`
+fn all_updated_before(body: &str, since: DateTime<chrono::Utc>) -> bool {
+    let Ok(files) = serde_json::from_str::<Vec<serde_json::Value>>(body) else {
+        return false;
+    };
+    files.iter().all(|file| {
+        file.get("updated_at")
+            .and_then(|updated_at| updated_at.as_str())
+            .and_then(|updated_at| DateTime::parse_from_rfc3339(updated_at).ok())
+            .is_some_and(|updated_at| updated_at < since)
+    })
+}
+
+/// Name a Canvas file is saved under: its display name, with --prefix-date preceded by the
+/// day it was last updated, e.g. "2024-01-15_lecture.pdf"
+pub fn local_file_name(file: &File, options: &ProcessOptions) -> String {
+    match updated_date(&file.updated_at) {
+        Some(date) if options.prefix_date => sanitize_path_component(
+            &format!("{date}_{}", file.display_name),
+            options.filename_mode,
+        ),
+        _ => sanitize_path_component(&file.display_name, options.filename_mode),
+    }
+}
+
+fn updated_date(updated_at: &str) -> Option<String> {
+    let updated_at = DateTime::parse_from_rfc3339(updated_at).ok()?;
+    Some(
+        updated_at
+            .with_timezone(&Local)
+            .format("%Y-%m-%d")
+            .to_string(),
+    )
+}
+
+/// With --prefix-date, the latest copy of a file saved under the date of an earlier version,
+/// so an update is only downloaded with --download-newer, like for undated names
+fn earlier_dated_copy(filepath: &Path, updated_at: &str) -> Option<PathBuf> {
+    let date_prefix = format!("{}_", updated_date(updated_at)?);
+    let name = filepath.file_name()?.to_str()?;
+    let (before, after) = name.split_at(name.find(&date_prefix)?);
+    let after = &after[date_prefix.len()..];
+    std::fs::read_dir(filepath.parent()?)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(before)?.strip_suffix(after))
+                .is_some_and(|date_prefix| regex!(r"^\d{4}-\d{2}-\d{2}_$").is_match(date_prefix))
+        })
+        .max()
+}
+
 pub fn filter_files(options: &ProcessOptions, path: &Path, files: Vec<File>) -> Vec<File> {
-    // only download files that do not exist or are updated
-    files
+    let mut files = files
         .into_iter()
         .map(|mut f| {
-            let sanitized_filename = sanitize_filename::sanitize(&f.display_name);
-            f.filepath = path.join(sanitized_filename);
+            f.filepath = path.join(local_file_name(&f, options));
             f
         })
-        .filter(|f| !f.locked_for_user)
+        // ignore patterns match the nested layout, even when it gets flattened
+        .filter(|f| {
+            !ignored(
+                &f.filepath,
+                false,
+                &options.base_path,
+                options.ignore_matcher.as_deref(),
+            )
+        })
+        .map(|mut f| {
+            f.filepath = group_by_type_path(
+                &flatten_path(&f.filepath, options),
+                f.content_type.as_deref(),
+                options,
+            );
+            f
+        })
+        .collect::<Vec<_>>();
+    number_same_named_files(&mut files);
+
+    // only download files that do not exist or are updated
+    files
+        .into_iter()
+        .filter(|f| {
+            if f.locked_for_user {
+                options
+                    .locked_files
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .push(f.clone());
+            }
+            !f.locked_for_user
+        })
         .filter(|f| {
             if DateTime::parse_from_rfc3339(&f.updated_at).is_ok() {
                 return true;
@@ -244,15 +1368,54 @@ pub fn filter_files(options: &ProcessOptions, path: &Path, files: Vec<File>) ->
             false
         })
         .filter(|f| {
-            !f.filepath.exists() || (updated(&f.filepath, &f.updated_at) && options.download_newer)
+            options.since.is_none_or(|since| {
+                DateTime::parse_from_rfc3339(&f.updated_at)
+                    .is_ok_and(|updated_at| updated_at >= since)
+            })
         })
         .filter(|f| {
-            !ignored(
-                &f.filepath,
-                false,
-                &options.base_path,
-                options.ignore_matcher.as_deref(),
-            )
+            let existing = if f.filepath.exists() {
+                Some(f.filepath.clone())
+            } else if options.prefix_date {
+                earlier_dated_copy(&f.filepath, &f.updated_at)
+            } else {
+                None
+            };
+            let Some(existing) = existing else {
+                // Downloaded by an earlier run, maybe into another destination
+                return options
+                    .manifest
+                    .as_ref()
+                    .and_then(|manifest| manifest.recorded_updated_at(&f.filepath))
+                    .is_none_or(|recorded| {
+                        options.download_newer && newer_than(&f.updated_at, recorded)
+                    });
+            };
+            match options.overwrite {
+                OverwritePolicy::Skip => {
+                    // Links have no updated_at in a listing, only what their server sends
+                    options.download_newer
+                        && !(options.local_times_only && f.id == 0)
+                        && updated(&existing, &f.updated_at)
+                }
+                OverwritePolicy::Always => true,
+                // sizes are compared by skip_same_size once discovery is done
+                OverwritePolicy::IfDifferent => true,
+            }
+        })
+        .filter(|f| extension_allowed(&f.filepath, options))
+        .filter(|f| match options.max_size {
+            // size 0 means unknown (e.g. videos and links), so keep those
+            Some(max_size) if f.size > max_size => {
+                tracing::info!(
+                    "Skipping {:?} ({}), larger than max size {}.",
+                    f.filepath,
+                    format_bytes(f.size),
+                    format_bytes(max_size)
+                );
+                false
+            }
+            _ => true,
         })
         .collect()
 }
@@ -265,9 +1428,7 @@ pub async fn process_file_id(
     let file_result = file_resp.json::<File>().await;
     match file_result {
         Ok(mut file) => {
-            let sanitized_filename = sanitize_filename::sanitize(&file.display_name);
-            let file_path = path.join(sanitized_filename);
-            file.filepath = file_path;
+            file.filepath = path.join(local_file_name(&file, &options));
             Ok(file)
         }
         Err(e) => {
@@ -276,32 +1437,43 @@ pub async fn process_file_id(
         }
     }
 }
+/// Turn a link found in HTML into a File named after what the server calls it. Redirects are
+/// followed, so the name can come from the final hop; without any usable name the file is
+/// called after a hash of the link, so different unnamed links don't overwrite each other.
 pub async fn prepare_link_for_download(
     (link, path): (String, PathBuf),
     options: Arc<ProcessOptions>,
 ) -> Result<File> {
-    let resp = options
-        .client
-        .head(&link)
-        .bearer_auth(&options.canvas_token)
-        .timeout(Duration::from_secs(10))
-        .send()
-        .await?;
+    let request = |method| {
+        options
+            .client
+            .request(method, &link)
+            .bearer_auth(&options.canvas_token)
+            .timeout(options.timeout)
+            .send()
+    };
+    let mut resp = request(reqwest::Method::HEAD).await?;
+    // Some servers don't support HEAD, their GET headers are just as good
+    if !resp.status().is_success() {
+        resp = request(reqwest::Method::GET).await?;
+    }
     let headers = resp.headers();
-    // get filename out of Content-Disposition header
+
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|x| x.to_str().ok());
     let filename = headers
         .get(header::CONTENT_DISPOSITION)
         .and_then(|x| x.to_str().ok())
-        .and_then(|x| regex!(r#"filename="(.*)""#).captures(x))
-        .and_then(|x| x.get(1))
-        .map(|x| x.as_str())
-        .unwrap_or_else(|| {
-            regex!(r"/([^/]+)$")
-                .captures(&link)
-                .and_then(|x| x.get(1))
-                .map(|x| x.as_str())
-                .unwrap_or("unknown")
-        });
+        .and_then(content_disposition_filename)
+        .or_else(|| url_filename(resp.url()))
+        .or_else(|| url_filename(&reqwest::Url::parse(&link).ok()?))
+        .unwrap_or_else(|| format!("link_{:08x}", stable_hash(&link)));
+    let filename = match content_type {
+        Some(content_type) => fix_extension(filename, content_type),
+        None => filename,
+    };
+
     // last-modified header to TZ string
     let updated_at = headers
         .get(header::LAST_MODIFIED)
@@ -312,16 +1484,383 @@ pub async fn prepare_link_for_download(
         })
         .unwrap_or_else(|| Local::now().to_rfc3339());
 
-    let sanitized_filename = sanitize_filename::sanitize(filename);
+    let sanitized_filename = sanitize_path_component(&filename, options.filename_mode);
     let file = File {
         id: 0,
         folder_id: None,
-        display_name: filename.to_string(),
+        display_name: filename,
         size: 0,
         url: link.clone(),
         updated_at,
         locked_for_user: false,
+        lock_explanation: None,
+        unlock_at: None,
+        content_type: None,
         filepath: path.join(sanitized_filename),
+        segments: Vec::new(),
+        remux_to_mp4: false,
+        client: None,
     };
     Ok(file)
 }
+
+/// File name from a Content-Disposition header, preferring the UTF-8 `filename*` form
+fn content_disposition_filename(header: &str) -> Option<String> {
+    if let Some((_, encoded)) = regex_captures!(r#"filename\*\s*=\s*(?i:utf-8)''([^;]+)"#, header) {
+        let filename = percent_decode_str(encoded.trim()).decode_utf8().ok()?;
+        return Some(filename.into_owned()).filter(|f| !f.is_empty());
+    }
+    regex_captures!(r#"filename\s*=\s*(?:"([^"]*)"|([^;\s]+))"#, header)
+        .map(|(_, quoted, token)| if quoted.is_empty() { token } else { quoted })
+        .map(str::to_string)
+        .filter(|f| !f.is_empty())
+}
+
+/// Last path segment of a URL, unless it's a generic endpoint like Canvas' ".../download"
+fn url_filename(url: &reqwest::Url) -> Option<String> {
+    let segment = url.path_segments()?.rfind(|s| !s.is_empty())?;
+    let filename = percent_decode_str(segment).decode_utf8().ok()?;
+    if ["download", "preview", "view"].contains(&filename.as_ref()) {
+        return None;
+    }
+    Some(filename.into_owned())
+}
+
+/// Extensions of dynamic pages that serve a file, e.g. "image.php" or "download.aspx"
+const SERVER_PAGE_EXTENSIONS: [&str; 6] = ["php", "asp", "aspx", "ashx", "jsp", "cgi"];
+
+/// Add the extension the content type tells when the file name has none, or replace one that
+/// is certainly wrong: a server page, or another image format. Other mismatches are left
+/// alone, as servers often send e.g. docx as application/zip or code as text/plain.
+fn fix_extension(filename: String, content_type: &str) -> String {
+    let Some(expected) = extension_for_content_type(content_type) else {
+        return filename;
+    };
+    let path = Path::new(&filename);
+    let Some(current) = path.extension().and_then(|ext| ext.to_str()) else {
+        return format!("{filename}.{expected}");
+    };
+    let current = current.to_lowercase();
+    let same_type = current == expected
+        || matches!(
+            (current.as_str(), expected),
+            ("jpeg" | "jpe", "jpg") | ("htm", "html") | ("text", "txt")
+        );
+    let is_image = |ext: &str| ["png", "jpg", "jpeg", "gif", "svg", "webp"].contains(&ext);
+    let wrong = SERVER_PAGE_EXTENSIONS.contains(&current.as_str())
+        || (is_image(&current) && is_image(expected));
+    if same_type || !wrong {
+        return filename;
+    }
+    path.with_extension(expected).to_string_lossy().into_owned()
+}
+
+/// Usual file extension of common content types found behind links
+fn extension_for_content_type(content_type: &str) -> Option<&'static str> {
+    let mime = content_type.split(';').next()?.trim().to_lowercase();
+    let extension = match mime.as_str() {
+        "application/pdf" => "pdf",
+        "application/zip" => "zip",
+        "application/msword" => "doc",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => "docx",
+        "application/vnd.ms-powerpoint" => "ppt",
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation" => "pptx",
+        "application/vnd.ms-excel" => "xls",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet" => "xlsx",
+        "text/html" => "html",
+        "text/plain" => "txt",
+        "text/csv" => "csv",
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "image/gif" => "gif",
+        "image/svg+xml" => "svg",
+        "image/webp" => "webp",
+        "video/mp4" => "mp4",
+        "audio/mpeg" => "mp3",
+        _ => return None,
+    };
+    Some(extension)
+}
+
+/// Name of the per-course list of files Canvas wouldn't let us download
+const LOCKED_LIST_NAME: &str = "locked.txt";
+
+/// Write a locked.txt into each course folder, listing the files skipped as locked and why.
+/// Courses without locked files get their old list removed.
+pub fn write_locked_lists(options: &ProcessOptions) -> Result<()> {
+    let mut locked_files = std::mem::take(
+        &mut *options
+            .locked_files
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()),
+    );
+    // The same file can be listed under files/ and in modules
+    locked_files.sort_by(|a, b| a.filepath.cmp(&b.filepath));
+    locked_files.dedup_by(|a, b| a.filepath == b.filepath);
+
+    let mut lists: BTreeMap<PathBuf, String> = options
+        .course_stats
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .keys()
+        .map(|course_folder| (course_folder.clone(), String::new()))
+        .collect();
+    for file in &locked_files {
+        let Some(course_folder) = enclosing_course_folder(&file.filepath, options) else {
+            continue;
+        };
+        let relative_path = file
+            .filepath
+            .strip_prefix(&course_folder)
+            .unwrap_or(&file.filepath);
+        let list = lists.entry(course_folder.clone()).or_default();
+        list.push_str(&format!("{}\n", relative_path.display()));
+        if let Some(ref unlock_at) = file.unlock_at {
+            list.push_str(&format!("    Unlocks at: {unlock_at}\n"));
+        }
+        if let Some(ref lock_explanation) = file.lock_explanation {
+            let lock_explanation = regex!(r"<[^>]*>").replace_all(lock_explanation, "");
+            list.push_str(&format!("    {}\n", lock_explanation.trim()));
+        }
+    }
+
+    for (course_folder, list) in lists {
+        let list_path = course_folder.join(LOCKED_LIST_NAME);
+        if list.is_empty() {
+            if list_path.exists() {
+                std::fs::remove_file(&list_path)
+                    .with_context(|| format!("Could not remove {:?}", list_path))?;
+            }
+            continue;
+        }
+        let header = format!(
+            "Files locked for you on Canvas, as of {}\n\n",
+            Local::now().format("%Y-%m-%d %H:%M")
+        );
+        std::fs::write(&list_path, header + &list)
+            .with_context(|| format!("Could not write to file {:?}", list_path))?;
+    }
+    Ok(())
+}
+
+/// Checksum list in each course folder, readable by `sha256sum -c`
+const CHECKSUMS_FILE_NAME: &str = "SHA256SUMS";
+
+/// Merge the hashes of this run's downloads into each course's SHA256SUMS. Entries of files
+/// downloaded on earlier runs are kept as long as the file is still there.
+pub fn write_checksums(options: &ProcessOptions) -> Result<()> {
+    let file_checksums = std::mem::take(
+        &mut *options
+            .file_checksums
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()),
+    );
+    let mut updates: BTreeMap<PathBuf, Vec<(String, &str)>> = BTreeMap::new();
+    for (filepath, sha256) in &file_checksums {
+        let Some(course_folder) = enclosing_course_folder(filepath, options) else {
+            continue;
+        };
+        let relative_path = filepath
+            .strip_prefix(&course_folder)
+            .unwrap_or(filepath)
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        updates
+            .entry(course_folder)
+            .or_default()
+            .push((relative_path, sha256));
+    }
+
+    for (course_folder, checksums) in updates {
+        let sums_path = course_folder.join(CHECKSUMS_FILE_NAME);
+        let mut sums: BTreeMap<String, String> = BTreeMap::new();
+        if let Ok(content) = std::fs::read_to_string(&sums_path) {
+            for line in content.lines() {
+                // "<hash>  <path>", or "<hash> *<path>" in binary mode
+                let Some((sha256, relative_path)) = line.split_once(' ') else {
+                    continue;
+                };
+                let relative_path = relative_path.trim_start_matches([' ', '*']);
+                if course_folder.join(relative_path).exists() {
+                    sums.insert(relative_path.to_string(), sha256.to_string());
+                }
+            }
+        }
+        for (relative_path, sha256) in checksums {
+            sums.insert(relative_path, sha256.to_string());
+        }
+
+        let content: String = sums
+            .iter()
+            .map(|(relative_path, sha256)| format!("{sha256}  {relative_path}\n"))
+            .collect();
+        std::fs::write(&sums_path, content)
+            .with_context(|| format!("Could not write to file {:?}", sums_path))?;
+    }
+    Ok(())
+}
+
+/// Per-folder sidecar remembering what was last downloaded, keyed by file name
+const STATE_FILE_NAME: &str = ".canvas-state.json";
+
+#[derive(Deserialize, Serialize)]
+struct FileState {
+    etag: String,
+}
+
+fn read_folder_state(folder_path: &Path) -> BTreeMap<String, FileState> {
+    std::fs::read_to_string(folder_path.join(STATE_FILE_NAME))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn recorded_etag(filepath: &Path) -> Option<String> {
+    let folder_path = filepath.parent()?;
+    let filename = filepath.file_name()?.to_string_lossy();
+    read_folder_state(folder_path)
+        .remove(filename.as_ref())
+        .map(|state| state.etag)
+}
+
+fn record_etag(filepath: &Path, etag: &str, options: &ProcessOptions) -> Result<()> {
+    let folder_path = filepath
+        .parent()
+        .ok_or_else(|| Error::msg(format!("No parent folder for {filepath:?}")))?;
+    let filename = filepath
+        .file_name()
+        .ok_or_else(|| Error::msg(format!("No file name for {filepath:?}")))?
+        .to_string_lossy()
+        .to_string();
+
+    // Concurrent downloads into the same folder share one state file
+    let _guard = options
+        .state_lock
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut state = read_folder_state(folder_path);
+    state.insert(
+        filename,
+        FileState {
+            etag: etag.to_string(),
+        },
+    );
+    let state_path = folder_path.join(STATE_FILE_NAME);
+    std::fs::write(&state_path, serde_json::to_string_pretty(&state)?)
+        .with_context(|| format!("Could not write to file {:?}", state_path))?;
+    Ok(())
+}
+
+/// Drop queued files whose ETag still matches the one recorded when they were last downloaded.
+/// Their mtime is bumped to updated_at so they are not checked again on the next run.
+pub async fn skip_unchanged(files: Vec<File>, options: &ProcessOptions) -> Vec<File> {
+    stream::iter(files)
+        .map(|file| async move {
+            let unchanged = is_unchanged(&file, options).await;
+            (file, unchanged)
+        })
+        .buffered(8)
+        .filter_map(|(file, unchanged)| ready((!unchanged).then_some(file)))
+        .collect()
+        .await
+}
+
+/// Drop queued files that already exist locally with the size Canvas serves them with,
+/// for --overwrite if-different
+pub async fn skip_same_size(files: Vec<File>, options: &ProcessOptions) -> Vec<File> {
+    stream::iter(files)
+        .map(|file| async move {
+            let same_size = is_same_size(&file, options).await;
+            (file, same_size)
+        })
+        .buffered(8)
+        .filter_map(|(file, same_size)| ready((!same_size).then_some(file)))
+        .collect()
+        .await
+}
+
+async fn is_same_size(file: &File, options: &ProcessOptions) -> bool {
+    let Ok(local_size) = std::fs::metadata(&file.filepath).map(|m| m.len()) else {
+        return false;
+    };
+    // Segmented videos have no size to compare, keep what is there
+    if !file.segments.is_empty() {
+        return true;
+    }
+
+    let resp = options
+        .client
+        .head(&file.url)
+        .bearer_auth(&options.canvas_token)
+        .timeout(options.timeout)
+        .send()
+        .await;
+    let remote_size = match resp {
+        Ok(resp) if resp.status().is_success() => resp
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|ct_len| ct_len.to_str().ok())
+            .and_then(|ct_len| ct_len.parse().ok()),
+        Ok(resp) => {
+            tracing::debug!(
+                "Could not check size of {}, got {resp:?}",
+                file.display_name
+            );
+            None
+        }
+        Err(e) => {
+            tracing::debug!("Could not check size of {}, err={e:?}", file.display_name);
+            None
+        }
+    }
+    .unwrap_or(file.size); // size from the listing, 0 if unknown
+    if remote_size != local_size || remote_size == 0 {
+        return false;
+    }
+
+    tracing::info!("Skipping {:?}, same size as on Canvas.", file.filepath);
+    true
+}
+
+async fn is_unchanged(file: &File, options: &ProcessOptions) -> bool {
+    if !file.segments.is_empty() || !file.filepath.exists() {
+        return false;
+    }
+    let Some(recorded) = recorded_etag(&file.filepath) else {
+        return false;
+    };
+
+    let resp = options
+        .client
+        .head(&file.url)
+        .bearer_auth(&options.canvas_token)
+        .timeout(options.timeout)
+        .send()
+        .await;
+    let etag = match resp {
+        Ok(resp) => resp
+            .headers()
+            .get(header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string),
+        Err(e) => {
+            tracing::debug!("Could not check ETag of {}, err={e:?}", file.display_name);
+            return false;
+        }
+    };
+    if etag.as_deref() != Some(recorded.as_str()) {
+        return false;
+    }
+
+    tracing::info!("Skipping {:?}, content unchanged.", file.filepath);
+    if let Err(e) = set_file_updated_at(&file.filepath, &file.updated_at) {
+        tracing::error!(
+            "Failed to set modified time of {} with updated_at of {}, err={e:?}",
+            file.display_name,
+            file.updated_at
+        )
+    }
+    true
+}
@@ -0,0 +1,101 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::api::get_pages;
+use crate::canvas::{ProcessOptions, Submission};
+use crate::utils::{flatten_path, ignored};
+
+#[derive(Deserialize)]
+struct AssignmentGroup {
+    name: String,
+    group_weight: Option<f64>, // percent of the final grade, 0 unless the course weights groups
+    #[serde(default)]
+    assignments: Vec<GradedAssignment>,
+}
+
+#[derive(Deserialize)]
+struct GradedAssignment {
+    name: String,
+    points_possible: Option<f64>,
+    submission: Option<Submission>, // the user's own, requested with include[]=submission
+}
+
+/// Write grades.csv into the course folder: every assignment with its group, the group's
+/// weight, the points possible and the user's score, as an overview next to each grade.txt
+pub async fn process_grades(
+    (url, path): (String, PathBuf),
+    options: Arc<ProcessOptions>,
+) -> Result<()> {
+    let grades_path = path.join("grades.csv");
+    if ignored(
+        &grades_path,
+        false,
+        &options.base_path,
+        options.ignore_matcher.as_deref(),
+    ) {
+        return Ok(());
+    }
+
+    let groups_url = format!(
+        "{}assignment_groups?include[]=assignments&include[]=submission",
+        url
+    );
+    let pages = get_pages(groups_url, &options).await?;
+
+    let mut rows = Vec::new();
+    for pg in pages {
+        let uri = pg.url().to_string();
+        let page_body = pg.text().await?;
+        let groups = match serde_json::from_str::<Vec<AssignmentGroup>>(&page_body) {
+            Ok(groups) => groups,
+            Err(e) => {
+                // e.g. {"status": "unauthorized"} when the grades tab is hidden
+                tracing::debug!("Could not get assignment groups at link:{uri}\n{e:?}");
+                continue;
+            }
+        };
+        for group in groups {
+            for assignment in group.assignments {
+                let submission = assignment.submission.as_ref();
+                rows.push([
+                    assignment.name,
+                    group.name.clone(),
+                    format_number(group.group_weight),
+                    format_number(assignment.points_possible),
+                    format_number(submission.and_then(|s| s.score)),
+                    submission.and_then(|s| s.grade.clone()).unwrap_or_default(),
+                ]);
+            }
+        }
+    }
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    let mut csv = String::from("Assignment,Group,Group Weight (%),Points Possible,Score,Grade\n");
+    for row in rows {
+        let fields = row.iter().map(|field| csv_field(field)).collect::<Vec<_>>();
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    let grades_path = flatten_path(&grades_path, &options);
+    std::fs::write(&grades_path, csv)
+        .with_context(|| format!("Could not write to file {:?}", grades_path))?;
+    Ok(())
+}
+
+fn format_number(number: Option<f64>) -> String {
+    number.map(|n| n.to_string()).unwrap_or_default()
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
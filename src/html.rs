@@ -1,16 +1,22 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use futures::future::join_all;
 use lazy_regex::regex;
+use percent_encoding::utf8_percent_encode;
 use reqwest::Url;
 use select::document::Document;
 use select::predicate::Name;
 
 use crate::canvas::{File, ProcessOptions};
-use crate::files::{filter_files, prepare_link_for_download, process_file_id};
-use crate::utils::create_folder_if_not_exist_or_ignored;
+use crate::files::{filter_files, local_file_name, prepare_link_for_download, process_file_id};
+#[cfg(feature = "pdf")]
+use crate::pdf::save_pdf;
+use crate::utils::{
+    HREF_ESCAPES, create_folder_if_not_exist_or_ignored, flatten_path, group_by_type_path,
+    relative_path, sanitize_path_component,
+};
 
 /// process_html_links processes HTML content to find links and add them to the download queue.
 /// will create a folder of the given folder_name under path if there are any files to download.
@@ -18,31 +24,40 @@ pub async fn process_html_links(
     (html, path, folder_name): (String, PathBuf, String),
     options: Arc<ProcessOptions>,
 ) -> Result<()> {
-    let destination_path = path.join(sanitize_filename::sanitize(&folder_name));
+    let destination_path = path.join(sanitize_path_component(&folder_name, options.filename_mode));
     // If file link is part of course files
     let re = regex!(r"/courses/[0-9]+/files/([0-9]+)");
     let file_links = Document::from(html.as_str())
         .find(Name("a"))
         .filter_map(|n| n.attr("href"))
         .filter(|x| x.starts_with(&options.canvas_url))
-        .filter_map(|x| Url::parse(x).ok())
-        .filter(|x| re.is_match(x.path()))
         .filter_map(|x| {
             // Extract file ID and use the correct Canvas API endpoint
-            re.captures(x.path())
-                .and_then(|cap| cap.get(1))
-                .map(|file_id| format!("{}/api/v1/files/{}", options.canvas_url, file_id.as_str()))
+            let file_id = re
+                .captures(Url::parse(x).ok()?.path())?
+                .get(1)?
+                .as_str()
+                .to_string();
+            Some((
+                x.to_string(),
+                format!("{}/api/v1/files/{}", options.canvas_url, file_id),
+            ))
         })
-        .collect::<Vec<String>>();
+        .collect::<Vec<(String, String)>>();
 
     let mut link_files = join_all(
         file_links
-            .into_iter()
-            .map(|x| process_file_id((x, destination_path.clone()), options.clone())),
+            .iter()
+            .map(|(_, x)| process_file_id((x.clone(), destination_path.clone()), options.clone())),
     )
     .await
     .into_iter()
-    .filter_map(|x| x.ok())
+    .zip(&file_links)
+    .filter_map(|(x, (href, _))| {
+        let file = x.ok()?;
+        remember_link_target(href, &file, &destination_path, &options);
+        Some(file)
+    })
     .collect::<Vec<File>>();
 
     // If image is from canvas it is likely the file url gives permission denied, so download from the CDN
@@ -55,14 +70,17 @@ pub async fn process_html_links(
         .collect::<Vec<String>>();
 
     link_files.append(
-        join_all(
-            image_links
-                .into_iter()
-                .map(|x| prepare_link_for_download((x, destination_path.clone()), options.clone())),
-        )
+        join_all(image_links.iter().map(|x| {
+            prepare_link_for_download((x.clone(), destination_path.clone()), options.clone())
+        }))
         .await
         .into_iter()
-        .filter_map(|x| x.ok())
+        .zip(&image_links)
+        .filter_map(|(x, src)| {
+            let file = x.ok()?;
+            remember_link_target(src, &file, &destination_path, &options);
+            Some(file)
+        })
         .collect::<Vec<File>>()
         .as_mut(),
     );
@@ -79,3 +97,126 @@ pub async fn process_html_links(
 
     Ok(())
 }
+
+/// Remember where a file linked as `link` is saved, so --local-links can point the link there.
+/// The path is the one filter_files gives the file; atomic_download_file updates it when
+/// another file already took that name.
+fn remember_link_target(
+    link: &str,
+    file: &File,
+    destination_path: &Path,
+    options: &ProcessOptions,
+) {
+    if !options.local_links {
+        return;
+    }
+    let filepath = group_by_type_path(
+        &flatten_path(
+            &destination_path.join(local_file_name(file, options)),
+            options,
+        ),
+        file.content_type.as_deref(),
+        options,
+    );
+    options
+        .link_targets
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(link.to_string(), (file.url.clone(), filepath));
+}
+
+/// Point the links and images of the saved pages that were downloaded at their local copies,
+/// so the pages work offline. Runs after the downloads; links whose file isn't on disk stay.
+pub fn rewrite_local_links(options: &ProcessOptions) {
+    let link_targets = options
+        .link_targets
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let link_targets = link_targets
+        .iter()
+        .map(|(link, (_, filepath))| (link, filepath))
+        .filter(|(_, filepath)| filepath.exists())
+        .collect::<Vec<_>>();
+    if link_targets.is_empty() {
+        return;
+    }
+    let saved_content = options
+        .saved_content
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    for html_path in saved_content.iter() {
+        for path in [html_path.clone(), html_path.with_extension("md")] {
+            let Ok(content) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            let folder = path.parent().unwrap_or(Path::new(""));
+            let mut rewritten = content.clone();
+            for (link, filepath) in &link_targets {
+                let relative = relative_path(folder, filepath)
+                    .components()
+                    .map(|c| {
+                        utf8_percent_encode(&c.as_os_str().to_string_lossy(), HREF_ESCAPES)
+                            .to_string()
+                    })
+                    .collect::<Vec<_>>()
+                    .join("/");
+                // Quoted attribute values in HTML, where & is escaped, or (link) in Markdown
+                for (before, after) in [("\"", "\""), ("(", ")")] {
+                    for link in [link.to_string(), link.replace('&', "&amp;")] {
+                        rewritten = rewritten.replace(
+                            &format!("{before}{link}{after}"),
+                            &format!("{before}{relative}{after}"),
+                        );
+                    }
+                }
+            }
+            if rewritten != content
+                && let Err(e) = std::fs::write(&path, rewritten)
+            {
+                tracing::warn!("Could not write to file {:?}: {e}", path);
+            }
+        }
+    }
+}
+
+/// Write a generated HTML document in the formats chosen by --content-format.
+/// The Markdown version sits next to it, e.g. "Week 1.html" -> "Week 1.md".
+pub async fn save_content(html_path: &Path, html: &str, options: &ProcessOptions) -> Result<()> {
+    if options.local_links {
+        options
+            .saved_content
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(html_path.to_path_buf());
+    }
+    if options.content_format.writes_html() {
+        std::fs::write(html_path, html)
+            .with_context(|| format!("Could not write to file {:?}", html_path))?;
+        #[cfg(feature = "pdf")]
+        save_pdf(html_path, options).await;
+    }
+    if options.content_format.writes_markdown() {
+        let md_path = html_path.with_extension("md");
+        std::fs::write(&md_path, html_to_markdown(html))
+            .with_context(|| format!("Could not write to file {:?}", md_path))?;
+    }
+    Ok(())
+}
+
+/// Convert a generated HTML document to Markdown; the <title> becomes the heading
+/// unless the body already has one, and head/style/script contents are dropped.
+fn html_to_markdown(html: &str) -> String {
+    let title = regex!(r"(?is)<title>(.*?)</title>")
+        .captures(html)
+        .map(|c| c[1].trim().to_string());
+    let body =
+        regex!(r"(?is)<(head|style|script)\b.*?</(head|style|script)>").replace_all(html, "");
+    let markdown = html2md::parse_html(&body);
+
+    match title {
+        Some(title) if !title.is_empty() && !regex!(r"(?i)<h1\b").is_match(&body) => {
+            format!("# {title}\n\n{}\n", markdown.trim())
+        }
+        _ => format!("{}\n", markdown.trim()),
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use percent_encoding::utf8_percent_encode;
+use serde_json::Value;
+
+use crate::canvas::{Course, ProcessOptions};
+use crate::utils::{HREF_ESCAPES, course_folder, get_raw_json_path, html_escape, write_json_array};
+
+/// Record the synced courses in raw/courses.json and write an index.html at the destination
+/// root linking to each course folder. Courses synced on earlier runs stay listed.
+pub fn write_courses_index(course_jsons: &[Value], options: &ProcessOptions) -> Result<()> {
+    let raw_path = options.base_path.join("raw").join("courses.json");
+    let courses: BTreeMap<u64, Value> = std::fs::read_to_string(&raw_path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<Vec<Value>>(&content).ok())
+        .unwrap_or_default()
+        .into_iter()
+        .chain(course_jsons.iter().cloned())
+        .filter_map(|json| Some((json.get("id")?.as_u64()?, json)))
+        .collect();
+
+    let course_jsons = courses.values().cloned().collect::<Vec<_>>();
+    if let Some(courses_json_path) = get_raw_json_path(
+        &options.base_path,
+        "courses.json",
+        &options.base_path,
+        options.save_json,
+    )? {
+        write_json_array(&courses_json_path, &course_jsons)?;
+    }
+
+    let mut courses = courses
+        .into_values()
+        .filter_map(|json| serde_json::from_value::<Course>(json).ok())
+        .collect::<Vec<_>>();
+    courses.sort_by(|a, b| (term_name(a), &a.course_code).cmp(&(term_name(b), &b.course_code)));
+    let index_path = options.base_path.join("index.html");
+    std::fs::write(&index_path, generate_courses_index_html(&courses, options))
+        .with_context(|| format!("Could not write to file {:?}", index_path))?;
+    Ok(())
+}
+
+fn term_name(course: &Course) -> String {
+    course
+        .term
+        .as_ref()
+        .map_or_else(|| course.enrollment_term_id.to_string(), |t| t.name.clone())
+}
+
+fn generate_courses_index_html(courses: &[Course], options: &ProcessOptions) -> String {
+    let mut html = String::new();
+
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n");
+    html.push_str("    <meta charset=\"UTF-8\">\n");
+    html.push_str("    <title>Courses</title>\n");
+    html.push_str(r#"    <style>
+        body { font-family: system-ui, -apple-system, "Segoe UI", Arial, sans-serif; font-size: 16px; line-height: 1.5; max-width: 900px; margin: 20px auto; padding: 0 20px; }
+        table { border-collapse: collapse; width: 100%; }
+        th, td { text-align: left; padding: 6px 10px; border-bottom: 1px solid #ddd; }
+        th { background: #f9f9f9; }
+    </style>
+"#);
+    html.push_str("</head>\n<body>\n");
+    html.push_str("    <h1>Courses</h1>\n");
+    html.push_str("    <table>\n");
+    html.push_str("        <tr><th>Term</th><th>Code</th><th>Name</th><th>ID</th></tr>\n");
+
+    for course in courses {
+        let folder = course_folder(course, options);
+        // Courses with their own destination may lie outside the download folder
+        let href = match folder.strip_prefix(&options.base_path) {
+            Ok(relative) => {
+                let relative = relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                utf8_percent_encode(&format!("{relative}/"), HREF_ESCAPES).to_string()
+            }
+            Err(_) => reqwest::Url::from_directory_path(&folder)
+                .map(|url| url.to_string())
+                .unwrap_or_default(),
+        };
+        html.push_str(&format!(
+            "        <tr><td>{}</td><td>{}</td><td><a href=\"{}\">{}</a></td><td>{}</td></tr>\n",
+            html_escape(&term_name(course)),
+            html_escape(&course.course_code),
+            html_escape(&href),
+            html_escape(&course.name),
+            course.id
+        ));
+    }
+
+    html.push_str("    </table>\n");
+    html.push_str("</body>\n</html>");
+    html
+}
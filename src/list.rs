@@ -0,0 +1,165 @@
+use anyhow::Result;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+
+use crate::api::get_pages;
+use crate::canvas::{Course, File, Module, ModuleItem, Page, ProcessOptions};
+use crate::utils::format_bytes;
+use crate::videos::list_videos;
+
+/// One line of the --list output and the lines nested under it
+pub struct TreeNode {
+    label: String,
+    children: Vec<TreeNode>,
+}
+
+impl TreeNode {
+    pub fn new(label: impl Into<String>, children: Vec<TreeNode>) -> Self {
+        TreeNode {
+            label: label.into(),
+            children,
+        }
+    }
+
+    pub fn leaf(label: impl Into<String>) -> Self {
+        TreeNode::new(label, Vec::new())
+    }
+
+    /// Print the tree with branches drawn like the `tree` command
+    fn print(&self) {
+        status!("{}", self.label);
+        self.print_children("");
+    }
+
+    fn print_children(&self, prefix: &str) {
+        for (i, child) in self.children.iter().enumerate() {
+            let last = i + 1 == self.children.len();
+            status!(
+                "{prefix}{}{}",
+                if last { "└── " } else { "├── " },
+                child.label
+            );
+            child.print_children(&format!("{prefix}{}", if last { "    " } else { "│   " }));
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ListedFolder {
+    id: u32,
+    name: String,
+    parent_folder_id: Option<u32>,
+}
+
+/// Print what a course contains: its folders and files with their sizes, modules, pages
+/// and video sessions. Only listings are requested, nothing is downloaded or written.
+pub async fn list_course(course: &Course, options: &ProcessOptions) {
+    let course_link = format!("{}/api/v1/courses/{}", options.canvas_url, course.id);
+    let mut sections = Vec::new();
+    if !options.skip_files {
+        sections.push(section("files/", list_files(&course_link, options).await));
+    }
+    if !options.skip_modules {
+        sections.push(section(
+            "modules/",
+            list_modules(&course_link, options).await,
+        ));
+    }
+    if !options.skip_pages {
+        let pages = get_all::<Page>(format!("{course_link}/pages"), options)
+            .await
+            .map(|pages| {
+                pages
+                    .into_iter()
+                    .map(|page| TreeNode::leaf(page.title))
+                    .collect()
+            });
+        sections.push(section("pages/", pages));
+    }
+    if !options.skip_videos {
+        sections.push(section(
+            "videos/",
+            list_videos(&options.canvas_url, course.id, options).await,
+        ));
+    }
+    TreeNode::new(
+        format!("{} - {}", course.course_code, course.name),
+        sections,
+    )
+    .print();
+}
+
+/// A course section, or a note that Canvas wouldn't list it (e.g. a disabled tab)
+fn section(label: &str, children: Result<Vec<TreeNode>>) -> TreeNode {
+    match children {
+        Ok(children) if children.is_empty() => TreeNode::leaf(format!("{label} (empty)")),
+        Ok(children) => TreeNode::new(label, children),
+        Err(e) => {
+            tracing::debug!("Could not list {label}: {e:?}");
+            TreeNode::leaf(format!("{label} (not available)"))
+        }
+    }
+}
+
+async fn list_files(course_link: &str, options: &ProcessOptions) -> Result<Vec<TreeNode>> {
+    let folders = get_all::<ListedFolder>(format!("{course_link}/folders"), options).await?;
+    let files = get_all::<File>(format!("{course_link}/files"), options).await?;
+    // The root folder's contents sit right in files/, like when downloading
+    Ok(folders
+        .iter()
+        .find(|folder| folder.parent_folder_id.is_none())
+        .map(|root| folder_children(root.id, &folders, &files))
+        .unwrap_or_default())
+}
+
+fn folder_children(folder_id: u32, folders: &[ListedFolder], files: &[File]) -> Vec<TreeNode> {
+    let mut subfolders = folders
+        .iter()
+        .filter(|folder| folder.parent_folder_id == Some(folder_id))
+        .collect::<Vec<_>>();
+    subfolders.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut folder_files = files
+        .iter()
+        .filter(|file| file.folder_id == Some(folder_id))
+        .collect::<Vec<_>>();
+    folder_files.sort_by(|a, b| a.display_name.cmp(&b.display_name));
+
+    subfolders
+        .into_iter()
+        .map(|folder| {
+            TreeNode::new(
+                format!("{}/", folder.name),
+                folder_children(folder.id, folders, files),
+            )
+        })
+        .chain(folder_files.into_iter().map(|file| {
+            TreeNode::leaf(format!(
+                "{} ({})",
+                file.display_name,
+                format_bytes(file.size)
+            ))
+        }))
+        .collect()
+}
+
+async fn list_modules(course_link: &str, options: &ProcessOptions) -> Result<Vec<TreeNode>> {
+    let mut nodes = Vec::new();
+    for module in get_all::<Module>(format!("{course_link}/modules"), options).await? {
+        let items = get_all::<ModuleItem>(module.items_url, options)
+            .await?
+            .into_iter()
+            .map(|item| TreeNode::leaf(format!("{} ({})", item.title, item.item_type)))
+            .collect();
+        nodes.push(TreeNode::new(format!("{}/", module.name), items));
+    }
+    Ok(nodes)
+}
+
+/// Every item of a paginated listing
+async fn get_all<T: DeserializeOwned>(link: String, options: &ProcessOptions) -> Result<Vec<T>> {
+    let mut items = Vec::new();
+    for page in get_pages(link, options).await? {
+        items.extend(page.json::<Vec<T>>().await?);
+    }
+    Ok(items)
+}
@@ -2,13 +2,26 @@
 macro_rules! fork {
     // Motivation: recursive async functions are unsupported. We avoid this by using a non-async
     // function `f` to tokio::spawn our recursive function. Conveniently, we can wrap our barrier logic in this function
-    ($f:expr, $arg:expr, $T:ty, $options:expr) => {{
+    // Optionally name the semaphore limiting these tasks, sem_requests by default
+    ($f:expr, $arg:expr, $T:ty, $options:expr) => {
+        fork!($f, $arg, $T, $options, sem_requests)
+    };
+    ($f:expr, $arg:expr, $T:ty, $options:expr, $sem:ident) => {{
         fn g(arg: $T, options: std::sync::Arc<$crate::canvas::ProcessOptions>) {
             options
                 .n_active_requests
                 .fetch_add(1, std::sync::atomic::Ordering::AcqRel);
-            tokio::spawn(async move {
-                let _sem = options.sem_requests.acquire().await.unwrap_or_else(|e| {
+            let course = $crate::utils::COURSE.try_with(|course| *course).ok();
+            let task = async move {
+                // The course's slot first, so waiting for it doesn't hold a shared one
+                let course_sem = course.and_then(|course| options.course_semaphore(course));
+                let _course_sem = match course_sem {
+                    Some(sem) => Some(sem.acquire_owned().await.unwrap_or_else(|e| {
+                        panic!("Please report on GitHub. Unexpected closed sem, err={e}")
+                    })),
+                    None => None,
+                };
+                let _sem = options.$sem.acquire().await.unwrap_or_else(|e| {
                     panic!("Please report on GitHub. Unexpected closed sem, err={e}")
                 });
                 let res = $f(arg, options.clone()).await;
@@ -20,10 +33,42 @@ macro_rules! fork {
                     options.notify_main.notify_one();
                 }
                 if let Err(e) = res {
-                    tracing::error!("{e:?}");
+                    options.record_error(e);
                 }
-            });
+            };
+            match course {
+                Some(course) => tokio::spawn($crate::utils::COURSE.scope(course, task)),
+                None => tokio::spawn(task),
+            };
         }
         g($arg, $options);
     }};
 }
+
+/// println! for human-readable output. It goes to stderr with --json, so stdout only
+/// carries the JSON report.
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {{
+        if $crate::utils::JSON_OUTPUT.load(std::sync::atomic::Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    }};
+}
+
+/// Like status!, but without a newline and flushed, for prompts
+#[macro_export]
+macro_rules! status_prompt {
+    ($($arg:tt)*) => {{
+        use std::io::Write;
+        if $crate::utils::JSON_OUTPUT.load(std::sync::atomic::Ordering::Relaxed) {
+            eprint!($($arg)*);
+            std::io::stderr().flush()
+        } else {
+            print!($($arg)*);
+            std::io::stdout().flush()
+        }
+    }};
+}
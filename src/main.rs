@@ -4,14 +4,30 @@
 mod macros;
 
 mod api;
+mod archive;
 mod assignments;
+mod cache;
+mod calendar;
 mod canvas;
+mod check;
 mod discussions;
 mod files;
+mod grades;
 mod html;
+mod index;
+mod list;
+mod manifest;
 mod modules;
+mod oauth;
 mod pages;
+#[cfg(feature = "pdf")]
+mod pdf;
+mod progress;
+mod queue;
+mod quizzes;
+mod resolve;
 mod syllabus;
+mod throttle;
 mod users;
 mod utils;
 mod videos;
@@ -24,26 +40,52 @@ use std::sync::{
 use std::time::Duration;
 
 use anyhow::{Context, Result};
-use clap::{CommandFactory, Parser, Subcommand};
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 use clap_complete::{Shell, generate};
 use futures::future::ready;
-use futures::{StreamExt, TryStreamExt, stream};
+use futures::{StreamExt, stream};
 use ignore::gitignore::GitignoreBuilder;
 use indicatif::ProgressStyle;
+use serde::Serialize;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 
 use api::get_pages;
 use assignments::process_assignments;
-use canvas::ProcessOptions;
+use cache::ListingCache;
+use calendar::write_deadlines_ics;
+use canvas::{
+    ContentFormat, FilenameMode, ModuleLayout, OverwritePolicy, ProcessOptions, ProgressMode,
+    VideoContainer, VideoQuality,
+};
 use discussions::process_discussions;
-use files::{atomic_download_file, process_folders};
+use files::{
+    atomic_download_file, clean_stale_files, dedup_files, link_module_files, module_file_links,
+    n_tmp_files_in_flight, process_folders, remove_tmp_files_in_flight, skip_same_size,
+    skip_unchanged, write_checksums, write_locked_lists,
+};
+use grades::process_grades;
+use html::rewrite_local_links;
+use index::write_courses_index;
+use list::list_course;
+use manifest::Manifest;
 use modules::process_modules;
 use pages::process_pages;
+#[cfg(feature = "pdf")]
+use pdf::{PdfRenderer, write_course_pdfs};
+use quizzes::process_quizzes;
+use resolve::{CanvasItem, process_item, resolve_url};
 use syllabus::process_syllabus;
+use throttle::RateLimiter;
 use users::process_users;
 use utils::{
-    create_folder_if_not_exist_or_ignored, format_bytes, ignored, print_all_courses_by_term,
+    client_builder, course_folder, create_folder_if_not_exist_or_ignored, find_in_path,
+    format_bytes, ignored, load_courses_file, normalize_canvas_url, normalize_extensions,
+    parse_date, parse_layout, parse_size, pick_courses_interactively, print_all_courses_by_term,
+    print_course_summary, sanitize_path_component,
 };
-use videos::process_videos;
+use videos::{process_videos, write_video_indexes};
 
 #[derive(Subcommand)]
 enum Commands {
@@ -69,6 +111,12 @@ struct CommandLineOptions {
     )]
     config: Option<PathBuf>,
 
+    #[arg(
+        long,
+        help = "Make the config file readable only by you (chmod 600) if others can read its token"
+    )]
+    fix_permissions: bool,
+
     #[arg(
         short = 'd',
         long,
@@ -78,6 +126,14 @@ struct CommandLineOptions {
     )]
     destination_folder: PathBuf,
 
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with = "destination_folder",
+        help = "Save everything into this zip archive instead of a folder tree, staged in a hidden folder next to it first (needs about twice the space)"
+    )]
+    archive: Option<PathBuf>,
+
     #[arg(
         short = 'n',
         long,
@@ -85,6 +141,21 @@ struct CommandLineOptions {
     )]
     download_newer: bool,
 
+    #[arg(
+        long,
+        conflicts_with_all = ["overwrite", "track_etags"],
+        help = "Like -n, but only compare local modification times with the Canvas listings, without extra requests per file (may miss some updates)"
+    )]
+    newer_than_local_only: bool,
+
+    #[arg(
+        long,
+        value_name = "POLICY",
+        default_value = "skip",
+        help = "Existing local files: keep them (skip, see -n), download them again (always), or only if their size on Canvas differs (if-different)"
+    )]
+    overwrite: OverwritePolicy,
+
     #[arg(
         short = 't',
         long,
@@ -103,6 +174,40 @@ struct CommandLineOptions {
     )]
     course_names: Option<Vec<String>>,
 
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Download the course IDs listed in this file, one per line or as a JSON array, optionally with a destination folder each"
+    )]
+    courses_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Skip courses that a previous run downloaded completely, i.e. without errors"
+    )]
+    only_new_courses: bool,
+
+    #[arg(
+        long,
+        help = "Also list concluded courses and completed enrollments, e.g. to archive past terms"
+    )]
+    include_concluded: bool,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        conflicts_with_all = ["term_ids", "course_names", "courses_file", "interactive", "list"],
+        help = "Only download the page, assignment, file, discussion or announcement this Canvas link points to"
+    )]
+    url: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["url", "list"],
+        help = "Also download your personal Canvas files into a my_files folder; works without -t or -c"
+    )]
+    user_files: bool,
+
     #[arg(
         short = 'i',
         long,
@@ -112,15 +217,397 @@ struct CommandLineOptions {
     )]
     ignore_file: PathBuf,
 
+    #[arg(
+        long,
+        value_name = "EXT",
+        value_delimiter = ',',
+        help = "Only download files with these extensions, comma-separated (e.g. pdf,pptx)"
+    )]
+    include_ext: Option<Vec<String>>,
+
+    #[arg(
+        long,
+        value_name = "EXT",
+        value_delimiter = ',',
+        help = "Skip files with these extensions, comma-separated (takes precedence over --include-ext)"
+    )]
+    exclude_ext: Option<Vec<String>>,
+
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        help = "Skip files larger than this size (e.g. 500M, 2G)"
+    )]
+    max_size: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Only download course files nested at most N folders deep, skipping deeper subfolders"
+    )]
+    max_depth: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "DATE",
+        value_parser = parse_date,
+        help = "Only download files updated on Canvas after this date (YYYY-MM-DD or RFC 3339)"
+    )]
+    since: Option<chrono::DateTime<chrono::Utc>>,
+
+    #[arg(
+        long,
+        value_name = "RATE",
+        value_parser = parse_size,
+        help = "Limit total download speed in bytes per second (e.g. 2M)"
+    )]
+    rate_limit: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Put everything below a course's content folders into that folder, joining names with __"
+    )]
+    flatten: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["flatten", "link_module_files", "clean"],
+        help = "Put each course's downloaded files into folders by type (documents, slides, videos, ...) instead of where they are on Canvas"
+    )]
+    group_by_type: bool,
+
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        value_parser = parse_layout,
+        help = "Course folder path, e.g. \"{term}/{course_code} - {course_name}\"; placeholders: {term}, {course_code}, {course_name}, {course_id} (default: {course_code})"
+    )]
+    layout: Option<String>,
+
     #[arg(long, help = "Preview downloads without executing")]
     dry_run: bool,
 
+    #[arg(
+        long,
+        help = "Print the folders, files, modules, pages and videos of the selected courses as a tree, then exit without downloading"
+    )]
+    list: bool,
+
+    #[arg(
+        long,
+        help = "Check the token and connection: list accessible courses, show token scopes and measure download speed, then exit"
+    )]
+    check: bool,
+
+    #[arg(
+        long,
+        help = "Download even if the destination seems to lack free space for all queued files"
+    )]
+    force: bool,
+
     #[arg(long, default_value = "false", help = "Do not save raw JSON responses")]
     no_raw: bool,
 
     #[arg(long, help = "Do not download assignment submission files")]
     no_submissions: bool,
 
+    #[arg(long, help = "Skip the course files folder")]
+    no_files: bool,
+
+    #[arg(long, help = "Skip assignments, including submissions")]
+    no_assignments: bool,
+
+    #[arg(long, help = "Skip discussions and announcements")]
+    no_discussions: bool,
+
+    #[arg(long, help = "Skip pages")]
+    no_pages: bool,
+
+    #[arg(long, help = "Skip modules")]
+    no_modules: bool,
+
+    #[arg(long, help = "Skip Panopto lecture videos")]
+    no_videos: bool,
+
+    #[arg(
+        long,
+        help = "Download user avatars into an avatars folder next to users.json"
+    )]
+    avatars: bool,
+
+    #[arg(
+        long,
+        value_name = "QUALITY",
+        default_value = "highest",
+        help = "Panopto video quality: highest, lowest, or a target height like 720"
+    )]
+    video_quality: VideoQuality,
+
+    #[arg(
+        long,
+        help = "Fail a Panopto video if one of its segments can't be downloaded, instead of leaving a gap"
+    )]
+    strict_videos: bool,
+
+    #[arg(
+        long,
+        value_name = "HOST",
+        help = "Host of your school's Panopto tool in Canvas, e.g. mediaweb.ap.panopto.com (default: the first Panopto tool found)"
+    )]
+    panopto_host: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "HOST",
+        default_value = "s-cloudfront.cdn.ap.panopto.com",
+        help = "Panopto CDN host for the videos of sessions that don't name one"
+    )]
+    panopto_cdn_host: String,
+
+    #[arg(
+        long,
+        value_name = "CONTAINER",
+        default_value = "ts",
+        help = "Save segmented Panopto videos as the raw transport stream (ts) or remux them to mp4 (needs ffmpeg)"
+    )]
+    video_container: VideoContainer,
+
+    #[arg(
+        long,
+        help = "Write an index.html into each Panopto videos folder listing the sessions, their start time and download status"
+    )]
+    video_index: bool,
+
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value = "html",
+        help = "Save pages, syllabi, assignments, discussions and quizzes as html, markdown or both"
+    )]
+    content_format: ContentFormat,
+
+    #[arg(
+        long,
+        value_name = "MODE",
+        default_value = "safe",
+        help = "File and folder names valid everywhere (safe), as on Canvas minus slashes and control characters (preserve), or transliterated to ASCII (ascii)"
+    )]
+    filename_mode: FilenameMode,
+
+    #[arg(
+        long,
+        help = "Start file names with the date they were last updated on Canvas, e.g. 2024-01-15_lecture.pdf"
+    )]
+    prefix_date: bool,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value = "10",
+        help = "Timeout for Canvas API requests (file downloads are not limited)"
+    )]
+    timeout: u64,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value = "3",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Maximum attempts for rate-limited Canvas API requests, video segments and interrupted downloads"
+    )]
+    retries: u32,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value = "8",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        help = "Maximum parallel Canvas API requests while discovering files"
+    )]
+    concurrency: usize,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "Start with one parallel Canvas API request and raise it to --concurrency over this many seconds, for instances that rate-limit bursts"
+    )]
+    ramp_up: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        help = "Maximum parallel Canvas API requests per course, so one large course can't hold up the others"
+    )]
+    course_concurrency: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value = "8",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        help = "Maximum parallel file downloads"
+    )]
+    download_concurrency: usize,
+
+    #[arg(
+        long,
+        value_name = "MODE",
+        default_value = "both",
+        help = "Progress bars while downloading: one for all files (total), one per file (per-file), or both"
+    )]
+    progress: ProgressMode,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value = "100",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Items per page for paginated Canvas API requests (Canvas allows up to 100)"
+    )]
+    per_page: u32,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "HTTP(S) or SOCKS5 proxy, e.g. socks5://127.0.0.1:1080 (default: HTTPS_PROXY/ALL_PROXY)"
+    )]
+    proxy: Option<String>,
+
+    #[arg(
+        long,
+        help = "Only use HTTP/1.1, for proxies and firewalls that break HTTP/2 connections"
+    )]
+    http1_only: bool,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value = "10",
+        help = "TCP keep-alive interval for connections to Canvas and Panopto, 0 to turn it off"
+    )]
+    keepalive: u64,
+
+    #[cfg(feature = "pdf")]
+    #[arg(
+        long,
+        help = "Also save syllabi, pages, assignments and discussions as PDF (needs wkhtmltopdf or Chromium)"
+    )]
+    pdf: bool,
+
+    #[cfg(feature = "pdf")]
+    #[arg(
+        long,
+        help = "Also combine each course's syllabus, pages and assignment descriptions into one course.pdf (needs wkhtmltopdf or Chromium)"
+    )]
+    combine_pdf: bool,
+
+    #[arg(
+        long,
+        help = "Record ETags in .canvas-state.json and skip re-downloading files whose content is unchanged"
+    )]
+    track_etags: bool,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Cache course listings here and only re-fetch those Canvas reports as changed"
+    )]
+    cache_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Record downloaded files by relative path in this file and skip them on later runs, even into another destination"
+    )]
+    manifest: Option<PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["archive", "url", "list", "clean"],
+        help = "Download the files the last interrupted run had queued, without discovering them again"
+    )]
+    resume_queue: bool,
+
+    #[arg(
+        long,
+        value_name = "HOURS",
+        default_value = "12",
+        help = "Discover files again instead of using a queue saved longer ago than this, as its download links may have expired"
+    )]
+    queue_ttl: u64,
+
+    #[arg(
+        long,
+        help = "Export assignment due dates to deadlines.ics per course and for all courses"
+    )]
+    ics: bool,
+
+    #[arg(
+        long,
+        help = "Move local files that were removed on Canvas to .trash (course files folders only)"
+    )]
+    clean: bool,
+
+    #[arg(
+        long,
+        help = "Write a SHA256SUMS file of the downloaded files into each course folder"
+    )]
+    checksums: bool,
+
+    #[arg(
+        long,
+        value_name = "COMMAND",
+        help = "Run this shell command after each file is downloaded, with {} replaced by the file's path (appended without {})"
+    )]
+    on_download: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "LAYOUT",
+        default_value = "by-module",
+        help = "Put module files in their module folder (by-module) or only under files/ (by-files)"
+    )]
+    module_layout: ModuleLayout,
+
+    #[arg(
+        long,
+        help = "Link module files to their copy under files/ instead of downloading them twice"
+    )]
+    link_module_files: bool,
+
+    #[arg(
+        long,
+        help = "Point links and images in saved pages at the downloaded copies, so the pages work offline"
+    )]
+    local_links: bool,
+
+    #[arg(
+        long,
+        help = "Pick courses from a numbered list when neither -t nor -c is given"
+    )]
+    interactive: bool,
+
+    #[arg(
+        long,
+        help = "Log in through the browser with the Canvas developer key in the config file and cache the token there"
+    )]
+    oauth: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Also append log messages to this file"
+    )]
+    log_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Print a JSON report of courses, downloaded files, errors and elapsed time to stdout; other output goes to stderr"
+    )]
+    json: bool,
+
     #[arg(short = 'v', long, help = "Enable debug logging")]
     verbose: bool,
 }
@@ -136,6 +623,74 @@ fn load_ignore_file(
         .with_context(|| format!("Failed to parse ignore file: {:?}", ignore_file_path))
 }
 
+/// Parse the command line, filling in options it doesn't give from the [defaults] table of
+/// the config file, e.g. `destination-folder = "~/Canvas"` or `no-videos = true`
+fn parse_args_with_config_defaults() -> Result<CommandLineOptions> {
+    let matches = CommandLineOptions::command().get_matches();
+    let args = CommandLineOptions::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if args.command.is_some() {
+        return Ok(args);
+    }
+
+    // Without a config file, credentials may come from the environment
+    let Ok(config_path) = find_config_file(args.config.clone()) else {
+        return Ok(args);
+    };
+    let config_content = std::fs::read_to_string(&config_path)
+        .with_context(|| format!("Could not read config file: {}", config_path.display()))?;
+    let config: toml::Table =
+        toml::from_str(&config_content).with_context(|| "Config file is not valid TOML")?;
+    let Some(defaults) = config.get("defaults") else {
+        return Ok(args);
+    };
+    let defaults = defaults
+        .as_table()
+        .with_context(|| format!("defaults in {} must be a table", config_path.display()))?;
+
+    let command = CommandLineOptions::command();
+    let mut cli: Vec<std::ffi::OsString> = std::env::args_os().take(1).collect();
+    for (key, value) in defaults {
+        let long = key.replace('_', "-");
+        let Some(arg) = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(long.as_str()) && long != "config")
+        else {
+            anyhow::bail!(
+                "Unknown option {key:?} in [defaults] of {}",
+                config_path.display()
+            );
+        };
+        if matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        match value {
+            toml::Value::Boolean(true) => cli.push(format!("--{long}").into()),
+            toml::Value::Boolean(false) => {}
+            toml::Value::Array(values) => {
+                for value in values {
+                    cli.push(format!("--{long}={}", config_value_string(value)).into());
+                }
+            }
+            value => cli.push(format!("--{long}={}", config_value_string(value)).into()),
+        }
+    }
+    cli.extend(std::env::args_os().skip(1));
+
+    CommandLineOptions::try_parse_from(cli)
+        .with_context(|| format!("Invalid [defaults] in {}", config_path.display()))
+}
+
+/// A config value as it would be typed on the command line, with a leading ~/ expanded
+fn config_value_string(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => match (s.strip_prefix("~/"), directories::BaseDirs::new()) {
+            (Some(rest), Some(base_dirs)) => base_dirs.home_dir().join(rest).display().to_string(),
+            _ => s.clone(),
+        },
+        value => value.to_string(),
+    }
+}
+
 fn find_config_file(config_path: Option<PathBuf>) -> Result<PathBuf> {
     // If config path is explicitly provided, use it
     if let Some(path) = config_path {
@@ -161,14 +716,121 @@ fn find_config_file(config_path: Option<PathBuf>) -> Result<PathBuf> {
     }
 
     anyhow::bail!(
-        "Config file not found. Please create {}.toml in the current directory, or config.toml in your config directory, or use --config to specify a path.",
+        "Config file not found. Please create {}.toml in the current directory, or config.toml in your config directory, or use --config to specify a path. Alternatively, set the CANVAS_URL and CANVAS_TOKEN environment variables.",
         env!("CARGO_PKG_NAME")
     )
 }
 
+/// A config file holds one account at the top level, or several as [[accounts]] tables
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum CredentialsFile {
+    Accounts { accounts: Vec<canvas::Credentials> },
+    Single(canvas::Credentials),
+}
+
+fn load_credentials(
+    config_path: Option<PathBuf>,
+    fix_permissions: bool,
+) -> Result<Vec<canvas::Credentials>> {
+    // Without an explicit --config, prefer CANVAS_URL and CANVAS_TOKEN from the environment
+    if config_path.is_none() {
+        let canvas_url = std::env::var("CANVAS_URL").ok();
+        let canvas_token = std::env::var("CANVAS_TOKEN").ok();
+        match (canvas_url, canvas_token) {
+            (Some(canvas_url), Some(canvas_token)) => {
+                return Ok(vec![canvas::Credentials {
+                    canvas_url,
+                    canvas_token,
+                    no_submissions: false,
+                    oauth_client_id: None,
+                    oauth_client_secret: None,
+                    oauth_refresh_token: None,
+                }]);
+            }
+            (None, None) => {}
+            _ => tracing::warn!(
+                "Only one of CANVAS_URL and CANVAS_TOKEN is set, both are needed to use them instead of a config file; using the config file"
+            ),
+        }
+    }
+
+    let config_path = find_config_file(config_path)?;
+    let config_content = std::fs::read_to_string(&config_path)
+        .with_context(|| format!("Could not read config file: {}", config_path.display()))?;
+    check_config_permissions(&config_path, fix_permissions)?;
+    let credentials_file: CredentialsFile =
+        toml::from_str(&config_content).with_context(|| "Config file is not valid TOML")?;
+    match credentials_file {
+        CredentialsFile::Accounts { accounts } if accounts.is_empty() => {
+            anyhow::bail!("No accounts in config file: {}", config_path.display())
+        }
+        CredentialsFile::Accounts { accounts } => Ok(accounts),
+        CredentialsFile::Single(cred) => Ok(vec![cred]),
+    }
+}
+
+/// Warn like ssh does about private keys if others can read the token in the config file,
+/// or restrict it to the owner with --fix-permissions
+#[cfg(unix)]
+fn check_config_permissions(config_path: &Path, fix_permissions: bool) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = std::fs::metadata(config_path)?.permissions().mode();
+    if mode & 0o077 == 0 {
+        return Ok(());
+    }
+    if fix_permissions {
+        std::fs::set_permissions(config_path, std::fs::Permissions::from_mode(0o600))
+            .with_context(|| {
+                format!("Could not change permissions of {}", config_path.display())
+            })?;
+        status!("🔒 Made {} readable only by you", config_path.display());
+    } else {
+        tracing::warn!(
+            "Config file {} holds your Canvas token but is accessible by others (mode {:o}). Run `chmod 600 {}` or pass --fix-permissions.",
+            config_path.display(),
+            mode & 0o777,
+            config_path.display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_config_permissions(_config_path: &Path, _fix_permissions: bool) -> Result<()> {
+    Ok(())
+}
+
+fn keepalive(args: &CommandLineOptions) -> Option<Duration> {
+    (args.keepalive > 0).then(|| Duration::from_secs(args.keepalive))
+}
+
+fn load_proxy(proxy_url: Option<&str>) -> Result<Option<reqwest::Proxy>> {
+    if let Some(proxy_url) = proxy_url {
+        let proxy = reqwest::Proxy::all(proxy_url)
+            .with_context(|| format!("Invalid proxy URL: {proxy_url}"))?;
+        return Ok(Some(proxy));
+    }
+
+    // Fall back to the conventional environment variables, honoring NO_PROXY
+    let env_proxy = ["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|v| !v.is_empty()));
+    match env_proxy {
+        Some(proxy_url) => {
+            let proxy = reqwest::Proxy::all(&proxy_url)
+                .with_context(|| format!("Invalid proxy URL in environment: {proxy_url}"))?
+                .no_proxy(reqwest::NoProxy::from_env());
+            Ok(Some(proxy))
+        }
+        None => Ok(None),
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    let mut args = CommandLineOptions::parse();
+    let args = parse_args_with_config_defaults()?;
 
     // Handle subcommands
     if let Some(command) = args.command {
@@ -181,70 +843,357 @@ async fn main() -> Result<()> {
         }
     }
 
+    let started = std::time::Instant::now();
+    utils::JSON_OUTPUT.store(args.json, Ordering::Relaxed);
+
     // Initialize tracing
     let filter = if args.verbose {
         "canvas_downloader=debug"
     } else {
         "canvas_downloader=info"
     };
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .with_target(false)
+    let log_file = match args.log_file {
+        Some(ref log_file_path) => Some(
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(log_file_path)
+                .with_context(|| format!("Could not open log file {:?}", log_file_path))?,
+        ),
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::EnvFilter::new(filter))
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_target(false)
+                .with_writer(std::io::stderr),
+        )
+        .with(log_file.map(|log_file| {
+            tracing_subscriber::fmt::layer()
+                .with_target(false)
+                .with_ansi(false)
+                .with_writer(std::sync::Mutex::new(log_file))
+        }))
         .init();
 
-    // Load credentials
-    let config_path = find_config_file(args.config)?;
-    let config_content = std::fs::read_to_string(&config_path)
-        .with_context(|| format!("Could not read config file: {}", config_path.display()))?;
-    let cred: canvas::Credentials =
-        toml::from_str(&config_content).with_context(|| "Config file is not valid TOML")?;
+    handle_ctrl_c();
+
+    // Load credentials; --oauth needs a config file to cache the token in
+    let oauth_config_path = if args.oauth {
+        Some(find_config_file(args.config.clone())?)
+    } else {
+        None
+    };
+    let mut accounts = load_credentials(
+        oauth_config_path.clone().or(args.config.clone()),
+        args.fix_permissions,
+    )?;
+    // API links are built by appending /api/v1/... to canvas_url
+    for cred in accounts.iter_mut() {
+        cred.canvas_url = normalize_canvas_url(&cred.canvas_url)?;
+    }
+    if oauth_config_path.is_some() && accounts.len() > 1 {
+        anyhow::bail!("--oauth only supports a config file with a single account");
+    }
+
+    // Prepare GET request options
+    let proxy = load_proxy(args.proxy.as_deref())?;
+    let client = client_builder(proxy.as_ref(), args.http1_only, keepalive(&args))
+        .build()
+        .with_context(|| "Failed to create HTTP client")?;
+
+    let destination_folder = match args.archive {
+        Some(ref archive_path) => archive::staging_folder(archive_path),
+        None => args.destination_folder.clone(),
+    };
+
+    let mut report = SyncReport::default();
+    let result = sync_accounts(
+        &args,
+        &client,
+        proxy,
+        accounts,
+        oauth_config_path.as_deref(),
+        &destination_folder,
+        &mut report,
+    )
+    .await;
+    let result = match args.archive {
+        Some(ref archive_path) if !args.dry_run && !args.list && !args.check => {
+            archive::finish_archive(&destination_folder, archive_path, result, report.downloaded)
+        }
+        _ => result,
+    };
+
+    if args.json {
+        report.elapsed_secs = started.elapsed().as_secs_f64();
+        report.error = result.as_ref().err().map(|e| format!("{e:#}"));
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    }
+    result
+}
+
+/// Written into a course folder once the course was synced without errors, see --only-new-courses
+const COURSE_COMPLETE_MARKER: &str = ".canvas-complete";
+
+/// How long the first Ctrl-C waits for running downloads to finish
+const CTRL_C_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// The first Ctrl-C stops starting new downloads and gives running ones CTRL_C_GRACE_PERIOD
+/// to finish; a second one quits right away. Temp files of unfinished downloads are removed.
+fn handle_ctrl_c() {
+    tokio::spawn(async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        utils::INTERRUPTED.store(true, Ordering::Relaxed);
+        status!();
+        status!("⏹️  Interrupted, finishing running downloads. Press Ctrl-C again to quit now.");
+
+        let wait_for_downloads = async {
+            while n_tmp_files_in_flight() > 0 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = tokio::time::timeout(CTRL_C_GRACE_PERIOD, wait_for_downloads) => {}
+        }
+
+        let n_removed = remove_tmp_files_in_flight();
+        if n_removed > 0 {
+            status!(
+                "🧹 Removed {n_removed} unfinished download{}",
+                if n_removed == 1 { "" } else { "s" }
+            );
+        }
+        std::process::exit(130);
+    });
+}
+
+/// Machine-readable summary of a run, printed by --json
+#[derive(Default, Serialize)]
+struct SyncReport {
+    courses: Vec<CourseReport>,
+    files: Vec<FileReport>,
+    partial_videos: Vec<PartialVideoReport>,
+    errors: usize,
+    error_messages: Vec<String>, // one per failed item
+    error: Option<String>,
+    elapsed_secs: f64,
+    #[serde(skip)]
+    downloaded: bool, // the user confirmed and files were downloaded, see --archive
+}
+
+#[derive(Serialize)]
+struct CourseReport {
+    id: u32,
+    course_code: String,
+    name: String,
+    path: PathBuf,
+}
+
+#[derive(Serialize)]
+struct FileReport {
+    path: PathBuf,
+    size: u64,
+}
+
+/// A video saved with gaps, see --strict-videos
+#[derive(Serialize)]
+struct PartialVideoReport {
+    path: PathBuf,
+    missing_segments: Vec<usize>, // 1-based, in playback order
+}
+
+async fn sync_accounts(
+    args: &CommandLineOptions,
+    client: &reqwest::Client,
+    proxy: Option<reqwest::Proxy>,
+    accounts: Vec<canvas::Credentials>,
+    oauth_config_path: Option<&Path>,
+    destination_folder: &Path,
+    report: &mut SyncReport,
+) -> Result<()> {
+    // With several accounts, each institution gets its own folder under the destination
+    let multiple_accounts = accounts.len() > 1;
+    let mut failed_accounts = 0;
+    for mut cred in accounts {
+        if let Some(config_path) = oauth_config_path {
+            oauth::login(client, &mut cred, config_path).await?;
+        } else if cred.canvas_token.is_empty() {
+            anyhow::bail!(
+                "No canvas_token for {} in the config file, add one or log in with --oauth",
+                cred.canvas_url
+            );
+        }
+
+        if !multiple_accounts {
+            return sync_account(
+                args,
+                client,
+                proxy,
+                cred,
+                destination_folder.to_path_buf(),
+                report,
+            )
+            .await;
+        }
+        status!("🏫 {}", cred.canvas_url);
+        let destination_folder = destination_folder.join(account_folder_name(&cred.canvas_url));
+        if let Err(e) = sync_account(
+            args,
+            client,
+            proxy.clone(),
+            cred,
+            destination_folder,
+            report,
+        )
+        .await
+        {
+            tracing::error!("{e:?}");
+            failed_accounts += 1;
+        }
+        status!();
+    }
+    if failed_accounts > 0 {
+        anyhow::bail!("Sync failed for {failed_accounts} account(s), see the log above");
+    }
+    Ok(())
+}
+
+/// Folder name for one institution when syncing several accounts, e.g. "canvas.stanford.edu"
+fn account_folder_name(canvas_url: &str) -> String {
+    let host = reqwest::Url::parse(canvas_url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string));
+    sanitize_path_component(
+        &host.unwrap_or_else(|| canvas_url.to_string()),
+        FilenameMode::Safe,
+    )
+}
+
+/// Discover and download the selected courses of one Canvas account into `destination_folder`
+async fn sync_account(
+    args: &CommandLineOptions,
+    client: &reqwest::Client,
+    proxy: Option<reqwest::Proxy>,
+    cred: canvas::Credentials,
+    destination_folder: PathBuf,
+    report: &mut SyncReport,
+) -> Result<()> {
+    if args.check {
+        return check::check_account(client, &cred).await;
+    }
 
     // Create sub-folder if not exists
-    if !args.destination_folder.exists() {
-        std::fs::create_dir(&args.destination_folder)
-            .unwrap_or_else(|e| panic!("Failed to create destination directory, err={e}"));
+    if !destination_folder.exists() {
+        std::fs::create_dir_all(&destination_folder).with_context(|| {
+            format!(
+                "Failed to create destination directory: {}",
+                destination_folder.display()
+            )
+        })?;
     }
 
-    // Prepare GET request options
-    let user_agent = format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
-    let client = reqwest::ClientBuilder::new()
-        .user_agent(user_agent)
-        .tcp_keepalive(Some(Duration::from_secs(10)))
-        .http2_keep_alive_interval(Some(Duration::from_secs(2)))
-        .build()
-        .with_context(|| "Failed to create HTTP client")?;
     let user_link = format!("{}/api/v1/users/self", cred.canvas_url);
     let user = client
         .get(&user_link)
         .bearer_auth(&cred.canvas_token)
         .send()
         .await?
+        .error_for_status()?
         .json::<canvas::User>()
         .await
         .with_context(|| "Failed to get user info")?;
-    let courses_link = format!("{}/api/v1/users/self/courses", cred.canvas_url);
+    let courses_link = format!(
+        "{}/api/v1/users/self/courses?include[]=term",
+        cred.canvas_url
+    );
+    // Students only see available courses by default, and only current enrollments
+    let courses_links = if args.include_concluded {
+        let courses_link = format!("{courses_link}&state[]=available&state[]=completed");
+        vec![
+            courses_link.clone(),
+            format!("{courses_link}&enrollment_state=completed"),
+        ]
+    } else {
+        vec![courses_link]
+    };
 
     // Load ignore file if it exists
     let ignore_matcher = if args.ignore_file.exists() {
         Some(Arc::new(load_ignore_file(
             &args.ignore_file,
-            &args.destination_folder,
+            &destination_folder,
         )?))
     } else {
         None
     };
 
     // if ignored by ignore file, disable saving raw json
-    let raw_folder_path = args.destination_folder.join("raw");
+    let mut no_raw = args.no_raw;
+    let raw_folder_path = destination_folder.join("raw");
     if ignored(
         &raw_folder_path,
         true,
-        &args.destination_folder,
+        &destination_folder,
         ignore_matcher.as_deref(),
     ) {
-        args.no_raw = true;
+        no_raw = true;
     }
 
+    // Find a PDF renderer up front so a missing one is reported only once
+    #[cfg(feature = "pdf")]
+    let pdf_renderer = if args.pdf || args.combine_pdf {
+        let renderer = PdfRenderer::find();
+        if renderer.is_none() {
+            tracing::warn!(
+                "--pdf and --combine-pdf require wkhtmltopdf or Chromium in PATH, continuing without PDF conversion"
+            );
+        }
+        renderer
+    } else {
+        None
+    };
+
+    // Likewise look for ffmpeg once, falling back to raw transport streams without it
+    let ffmpeg = if args.video_container == VideoContainer::Mp4 {
+        let ffmpeg = find_in_path("ffmpeg");
+        if ffmpeg.is_none() {
+            tracing::warn!(
+                "--video-container mp4 requires ffmpeg in PATH, saving videos as .ts instead"
+            );
+        }
+        ffmpeg
+    } else {
+        None
+    };
+
+    let courses_file = match args.courses_file {
+        Some(ref courses_file) => Some(load_courses_file(courses_file)?),
+        None => None,
+    };
+    let course_destinations = courses_file
+        .iter()
+        .flatten()
+        .filter_map(|entry| Some((entry.id(), entry.destination()?.to_path_buf())))
+        .collect();
+
+    let listing_cache = match args.cache_dir {
+        Some(ref cache_dir) => Some(ListingCache::load(cache_dir, &cred.canvas_url)?),
+        None => None,
+    };
+    let manifest = match args.manifest {
+        Some(ref manifest_path) => Some(Manifest::load(
+            manifest_path,
+            &cred.canvas_url,
+            &destination_folder,
+        )?),
+        None => None,
+    };
+
     let options = Arc::new(ProcessOptions {
         canvas_token: cred.canvas_token.clone(),
         canvas_url: cred.canvas_url.clone(),
@@ -252,12 +1201,77 @@ async fn main() -> Result<()> {
         user: user.clone(),
         // Process
         files_to_download: tokio::sync::Mutex::new(Vec::new()),
-        download_newer: args.download_newer,
+        download_newer: args.download_newer || args.newer_than_local_only,
+        local_times_only: args.newer_than_local_only,
+        overwrite: args.overwrite,
         ignore_matcher,
-        base_path: args.destination_folder.clone(),
+        include_ext: args.include_ext.as_deref().map(normalize_extensions),
+        exclude_ext: args
+            .exclude_ext
+            .as_deref()
+            .map(normalize_extensions)
+            .unwrap_or_default(),
+        max_size: args.max_size,
+        max_depth: args.max_depth,
+        since: args.since,
+        base_path: destination_folder.clone(),
+        flatten: args.flatten,
+        group_by_type: args.group_by_type,
+        course_layout: args.layout.clone(),
+        course_destinations,
         // dry_run: args.dry_run,
-        save_json: !args.no_raw,
+        save_json: !no_raw,
+        download_avatars: args.avatars,
         skip_submissions: args.no_submissions || cred.no_submissions,
+        skip_files: args.no_files,
+        skip_assignments: args.no_assignments,
+        skip_discussions: args.no_discussions,
+        skip_pages: args.no_pages,
+        skip_modules: args.no_modules,
+        skip_videos: args.no_videos,
+        video_quality: args.video_quality,
+        strict_videos: args.strict_videos,
+        panopto_host: args.panopto_host.clone(),
+        panopto_cdn_host: args.panopto_cdn_host.clone(),
+        video_index: args.video_index,
+        ffmpeg,
+        content_format: args.content_format,
+        filename_mode: args.filename_mode,
+        prefix_date: args.prefix_date,
+        timeout: Duration::from_secs(args.timeout),
+        retries: args.retries,
+        per_page: args.per_page,
+        proxy,
+        http1_only: args.http1_only,
+        keepalive: keepalive(args),
+        #[cfg(feature = "pdf")]
+        pdf_renderer,
+        #[cfg(feature = "pdf")]
+        pdf: args.pdf,
+        #[cfg(feature = "pdf")]
+        combine_pdf: args.combine_pdf,
+        #[cfg(feature = "pdf")]
+        course_pdf_parts: std::sync::Mutex::new(Vec::new()),
+        track_etags: args.track_etags,
+        listing_cache,
+        manifest,
+        rate_limiter: args
+            .rate_limit
+            .filter(|&rate| rate > 0)
+            .map(RateLimiter::new),
+        export_ics: args.ics,
+        deadlines: std::sync::Mutex::new(Vec::new()),
+        module_layout: args.module_layout,
+        clean: args.clean,
+        checksums: args.checksums,
+        on_download: args.on_download.clone(),
+        listed_files: std::sync::Mutex::new(std::collections::HashMap::new()),
+        link_module_files: args.link_module_files,
+        local_links: args.local_links,
+        link_targets: std::sync::Mutex::new(std::collections::HashMap::new()),
+        saved_content: std::sync::Mutex::new(Vec::new()),
+        course_file_paths: std::sync::Mutex::new(std::collections::HashMap::new()),
+        module_files: tokio::sync::Mutex::new(Vec::new()),
         // Download
         progress_bars: indicatif::MultiProgress::new(),
         progress_style: {
@@ -271,11 +1285,32 @@ async fn main() -> Result<()> {
                 .unwrap_or_else(|e| panic!("Please report this issue on GitHub: error with progress bar style={style_template}, err={e}"))
                 .progress_chars("=>-")
         },
+        progress: args.progress,
+        total_progress: progress::TotalProgress::new(),
         // Synchronization
         n_active_requests: AtomicUsize::new(0),
-        sem_requests: tokio::sync::Semaphore::new(8), // WARN magic constant.
+        // With --ramp-up, ramp_up_requests() adds the other permits once requests start
+        sem_requests: tokio::sync::Semaphore::new(if args.ramp_up.is_some() {
+            1
+        } else {
+            args.concurrency
+        }),
+        sem_downloads: tokio::sync::Semaphore::new(args.download_concurrency),
+        sem_hooks: tokio::sync::Semaphore::new(args.download_concurrency),
+        course_concurrency: args.course_concurrency,
+        course_semaphores: std::sync::Mutex::new(std::collections::HashMap::new()),
         notify_main: tokio::sync::Notify::new(),
+        state_lock: std::sync::Mutex::new(()),
+        claimed_paths: std::sync::Mutex::new(std::collections::HashSet::new()),
+        visited_folders: std::sync::Mutex::new(std::collections::HashSet::new()),
         // Progress counters
+        course_stats: std::sync::Mutex::new(std::collections::HashMap::new()),
+        downloaded_files: std::sync::Mutex::new(Vec::new()),
+        downloaded_ids: std::sync::Mutex::new(std::collections::HashSet::new()),
+        file_checksums: std::sync::Mutex::new(Vec::new()),
+        locked_files: std::sync::Mutex::new(Vec::new()),
+        partial_videos: std::sync::Mutex::new(Vec::new()),
+        video_sessions: std::sync::Mutex::new(Vec::new()),
         n_syllabi: AtomicUsize::new(0),
         n_users: AtomicUsize::new(0),
         n_assignments: AtomicUsize::new(0),
@@ -283,55 +1318,131 @@ async fn main() -> Result<()> {
         n_discussions: AtomicUsize::new(0),
         n_announcements: AtomicUsize::new(0),
         n_modules: AtomicUsize::new(0),
+        n_quizzes: AtomicUsize::new(0),
         n_videos: AtomicUsize::new(0),
+        errors: std::sync::Mutex::new(Vec::new()),
         // TODO handle canvas rate limiting errors, maybe scale up if possible
     });
 
-    // Get courses
-    let courses: Vec<canvas::Course> = get_pages(courses_link.clone(), &options)
-        .await?
-        .into_iter()
-        .map(|resp| resp.json::<Vec<serde_json::Value>>()) // resp --> Result<Vec<json>>
-        .collect::<stream::FuturesUnordered<_>>() // (in any order)
-        .flat_map_unordered(None, |json_res| {
-            let jsons = json_res.unwrap_or_else(|e| panic!("Failed to parse courses, err={e}")); // Result<Vec<json>> --> Vec<json>
-            stream::iter(jsons.into_iter()) // Vec<json> --> json
-        })
-        .filter(|json| ready(json.get("enrollments").is_some())) // (enrolled?)
-        .map(serde_json::from_value) // json --> Result<course>
-        .try_collect()
-        .await
-        .with_context(|| "Error when getting course json")?; // Result<course> --> course
-
-    // Filter courses by term IDs and/or course names
-    if args.term_ids.is_none() && args.course_names.is_none() {
-        println!("Please provide either Term ID(s) via -t or course name(s)/code(s) via -c");
-        print_all_courses_by_term(&courses);
-        return Ok(());
+    // Get courses, keeping their raw JSON for courses.json
+    let mut courses: Vec<canvas::Course> = Vec::new();
+    let mut course_jsons: Vec<serde_json::Value> = Vec::new();
+    for courses_link in courses_links {
+        let listed_jsons: Vec<serde_json::Value> = get_pages(courses_link, &options)
+            .await?
+            .into_iter()
+            .map(|resp| resp.json::<Vec<serde_json::Value>>()) // resp --> Result<Vec<json>>
+            .collect::<stream::FuturesUnordered<_>>() // (in any order)
+            .flat_map_unordered(None, |json_res| {
+                let jsons = json_res.unwrap_or_else(|e| panic!("Failed to parse courses, err={e}")); // Result<Vec<json>> --> Vec<json>
+                stream::iter(jsons) // Vec<json> --> json
+            })
+            // (enrolled?) Concluded courses may come without enrollments, but courses
+            // closed to the user only carry their id and access_restricted_by_date
+            .filter(|json| {
+                ready(
+                    json.get("enrollments").is_some()
+                        || (args.include_concluded
+                            && json.get("access_restricted_by_date").is_none()),
+                )
+            })
+            .collect()
+            .await;
+        let listed_courses: Vec<canvas::Course> = listed_jsons
+            .iter()
+            .cloned()
+            .map(serde_json::from_value) // json --> Result<course>
+            .collect::<Result<_, _>>()
+            .with_context(|| "Error when getting course json")?; // Result<course> --> course
+        // A course can be in both listings
+        for (course, json) in listed_courses.into_iter().zip(listed_jsons) {
+            if !courses.iter().any(|known| known.id == course.id) {
+                courses.push(course);
+                course_jsons.push(json);
+            }
+        }
     }
 
-    let courses_to_download: Vec<&canvas::Course> = courses
-        .iter()
-        .filter(|course| {
-            // Filter by term IDs if provided
-            let matches_term = args
-                .term_ids
-                .as_ref()
-                .is_none_or(|ids| ids.contains(&course.enrollment_term_id));
-
-            // Filter by course names if provided (exact match)
-            let matches_name = args.course_names.as_ref().is_none_or(|names| {
-                names
-                    .iter()
-                    .any(|name| &course.name == name || &course.course_code == name)
-            });
+    // With --url, only the course the link points into
+    let url_item = match args.url {
+        Some(ref url) => match resolve_url(url, &cred.canvas_url)? {
+            Some(url_item) => Some(url_item),
+            None => {
+                status!("{url} is not on {}, skipping it", cred.canvas_url);
+                return Ok(());
+            }
+        },
+        None => None,
+    };
 
-            matches_term && matches_name
-        })
-        .collect();
+    // Filter courses by term IDs, course names and/or the courses file
+    let courses_to_download: Vec<&canvas::Course> =
+        if let Some((course_id, _)) = url_item {
+            let course = courses.iter().find(|course| course.id == course_id);
+            vec![course.with_context(|| {
+                format!("Course {course_id} of the link is not among your courses")
+            })?]
+        } else if args.term_ids.is_none()
+            && args.course_names.is_none()
+            && courses_file.is_none()
+            && args.user_files
+            && !args.interactive
+        {
+            vec![] // only the user's own files
+        } else if args.term_ids.is_none() && args.course_names.is_none() && courses_file.is_none() {
+            if !args.interactive {
+                status!("Please provide either Term ID(s) via -t or course name(s)/code(s) via -c");
+                print_all_courses_by_term(&courses);
+                return Ok(());
+            }
+            let selected = pick_courses_interactively(&courses)?;
+            if selected.is_empty() {
+                status!("No course selected");
+                return Ok(());
+            }
+            selected
+        } else {
+            courses
+                .iter()
+                .filter(|course| {
+                    // Filter by term IDs if provided
+                    let matches_term = args
+                        .term_ids
+                        .as_ref()
+                        .is_none_or(|ids| ids.contains(&course.enrollment_term_id));
+
+                    // Filter by course names if provided (exact match)
+                    let matches_name = args.course_names.as_ref().is_none_or(|names| {
+                        names
+                            .iter()
+                            .any(|name| &course.name == name || &course.course_code == name)
+                    });
+
+                    // Filter by the courses file if provided
+                    let matches_file = courses_file
+                        .as_ref()
+                        .is_none_or(|entries| entries.iter().any(|entry| entry.id() == course.id));
+
+                    matches_term && matches_name && matches_file
+                })
+                .collect()
+        };
+
+    if let Some(ref entries) = courses_file {
+        for entry in entries {
+            if !courses.iter().any(|course| course.id == entry.id()) {
+                tracing::warn!(
+                    "Course {} from the courses file is not among your courses",
+                    entry.id()
+                );
+            }
+        }
+    }
 
     if courses_to_download.is_empty() {
-        if let Some(ref term_ids) = args.term_ids {
+        if courses_file.is_some() {
+            tracing::warn!("Could not find any course matching the courses file");
+        } else if let Some(ref term_ids) = args.term_ids {
             if let Some(ref course_names) = args.course_names {
                 tracing::warn!(
                     "Could not find any course matching Term ID(s) {term_ids:?} AND course name(s) {course_names:?}"
@@ -342,15 +1453,48 @@ async fn main() -> Result<()> {
         } else if let Some(ref course_names) = args.course_names {
             tracing::warn!("Could not find any course matching course name(s) {course_names:?}");
         }
-        println!("Please try the following instead:");
-        print_all_courses_by_term(&courses);
+        if !args.user_files {
+            status!("Please try the following instead:");
+            print_all_courses_by_term(&courses);
+            return Ok(());
+        }
+    }
+
+    let courses_to_download = if args.only_new_courses {
+        let (complete, new): (Vec<_>, Vec<_>) =
+            courses_to_download.into_iter().partition(|course| {
+                course_folder(course, &options)
+                    .join(COURSE_COMPLETE_MARKER)
+                    .exists()
+            });
+        for course in &complete {
+            status!(
+                "Skipping {} - {}, already downloaded",
+                course.course_code,
+                course.name
+            );
+        }
+        if new.is_empty() && !args.user_files {
+            status!("All selected courses were already downloaded");
+            return Ok(());
+        }
+        new
+    } else {
+        courses_to_download
+    };
+
+    if args.list {
+        for course in &courses_to_download {
+            list_course(course, &options).await;
+            status!();
+        }
         return Ok(());
     }
 
-    println!("Courses found:");
+    status!("Courses found:");
 
     // create raw folder if needed
-    if !args.no_raw && !raw_folder_path.exists() {
+    if !no_raw && !raw_folder_path.exists() {
         std::fs::create_dir(&raw_folder_path).with_context(|| {
             format!(
                 "Failed to create raw JSON directory: {}",
@@ -360,52 +1504,150 @@ async fn main() -> Result<()> {
     }
 
     for course in &courses_to_download {
-        println!("  * {} - {}", course.course_code, course.name);
+        status!("  * {} - {}", course.course_code, course.name);
+        report.courses.push(CourseReport {
+            id: course.id,
+            course_code: course.course_code.clone(),
+            name: course.name.clone(),
+            path: course_folder(course, &options),
+        });
+    }
+    if args.user_files {
+        status!("  * My files");
+    }
+    status!();
+
+    // An entry point into the courses synced so far, kept in raw/courses.json across runs
+    if !args.dry_run && options.save_json {
+        let selected_jsons = courses
+            .iter()
+            .zip(&course_jsons)
+            .filter(|(course, _)| courses_to_download.iter().any(|c| c.id == course.id))
+            .map(|(_, json)| json.clone())
+            .collect::<Vec<_>>();
+        if let Err(e) = write_courses_index(&selected_jsons, &options) {
+            tracing::warn!("Could not write courses index: {e:?}");
+        }
+    }
+
+    // The last run's queue stands in for discovery, so no task is forked
+    let queued_files = if args.resume_queue {
+        queue::load_queue(
+            &options.base_path,
+            Duration::from_secs(args.queue_ttl * 3600),
+        )?
+    } else {
+        None
+    };
+    let resumed = queued_files.is_some();
+    if let Some(files) = queued_files {
+        status!(
+            "Resuming the {} queued file(s) of the last run",
+            files.len()
+        );
+        status!();
+        options.files_to_download.lock().await.extend(files);
     }
-    println!();
 
-    for course in courses_to_download {
+    // Also with a resumed queue, so sem_requests is not left at a single permit
+    if let Some(seconds) = args.ramp_up {
+        ramp_up_requests(
+            options.clone(),
+            args.concurrency,
+            Duration::from_secs(seconds),
+        );
+    }
+
+    for course in &courses_to_download {
         // Prep path and mkdir -p
-        let course_folder_path = args
-            .destination_folder
-            .join(course.course_code.replace('/', "_"));
+        let course_folder_path = course_folder(course, &options);
         if !create_folder_if_not_exist_or_ignored(&course_folder_path, &options)? {
             continue;
         }
+        options
+            .course_stats
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(course_folder_path.clone())
+            .or_default();
+        if resumed {
+            continue;
+        }
         // Prep URL for course's root folder
         let course_folders_link = format!(
             "{}/api/v1/courses/{}/folders/by_path/",
             cred.canvas_url, course.id
         );
 
-        let folder_path = course_folder_path.join("files"); // TODO: if no files, skip creating folder
-        if create_folder_if_not_exist_or_ignored(&folder_path, &options)? {
+        // These tasks and every task they fork count against the course's --course-concurrency
+        utils::COURSE.sync_scope(course.id, || -> Result<()> {
+            if let Some((_, ref item)) = url_item {
+                let course_api_link = format!("{}/api/v1/courses/{}/", cred.canvas_url, course.id);
+                fork!(
+                    process_item,
+                    (course_api_link, item.clone(), course_folder_path.clone()),
+                    (String, CanvasItem, PathBuf),
+                    options.clone()
+                );
+                return Ok(());
+            }
+            let folder_path = course_folder_path.join("files"); // TODO: if no files, skip creating folder
+            if !options.skip_files && create_folder_if_not_exist_or_ignored(&folder_path, &options)?
+            {
+                fork!(
+                    process_folders,
+                    (course_folders_link, folder_path, 0),
+                    (String, PathBuf, usize),
+                    options.clone()
+                );
+            }
+
+            let course_api_link = format!("{}/api/v1/courses/{}/", cred.canvas_url, course.id);
             fork!(
-                process_folders,
-                (course_folders_link, folder_path),
-                (String, PathBuf),
+                process_data,
+                (course_api_link, course.id, course_folder_path.clone()),
+                (String, u32, PathBuf),
                 options.clone()
             );
-        }
 
-        let course_api_link = format!("{}/api/v1/courses/{}/", cred.canvas_url, course.id);
-        fork!(
-            process_data,
-            (course_api_link, course.id, course_folder_path.clone()),
-            (String, u32, PathBuf),
-            options.clone()
-        );
+            if !options.skip_videos {
+                fork!(
+                    process_videos,
+                    (
+                        cred.canvas_url.clone(),
+                        course.id,
+                        course_folder_path.clone()
+                    ),
+                    (String, u32, PathBuf),
+                    options.clone()
+                );
+            }
+            Ok(())
+        })?;
+    }
 
-        fork!(
-            process_videos,
-            (
-                cred.canvas_url.clone(),
-                course.id,
-                course_folder_path.clone()
-            ),
-            (String, u32, PathBuf),
-            options.clone()
-        );
+    // The user's personal files, outside of any course
+    if args.user_files {
+        let user_files_path = options.base_path.join("my_files");
+        if create_folder_if_not_exist_or_ignored(&user_files_path, &options)? {
+            // Counted like a course folder, e.g. for --checksums and --flatten
+            options
+                .course_stats
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .entry(user_files_path.clone())
+                .or_default();
+            if !resumed {
+                let user_folders_link =
+                    format!("{}/api/v1/users/self/folders/by_path/", cred.canvas_url);
+                fork!(
+                    process_folders,
+                    (user_folders_link, user_files_path, 0),
+                    (String, PathBuf, usize),
+                    options.clone()
+                );
+            }
+        }
     }
 
     // Invariants
@@ -415,9 +1657,34 @@ async fn main() -> Result<()> {
     //    3. --> n_active_requests == 0 only after all tasks done
     //    4. --> main() progresses only after all files have been queried
     // 2. No starvation: forks are done acyclically, all tasks +1 and -1 exactly once
-    // 3. Bounded concurrency: acquire or block on semaphore before request
+    // 3. Bounded concurrency: acquire or block on semaphore before request; with
+    //    --course-concurrency, on the course's semaphore first so a task waiting for its
+    //    course doesn't hold one of the shared slots
     // 4. No busy wait: Last task will see that there are 0 active requests and notify main
-    options.notify_main.notified().await;
+    // Nothing to wait for when the queue was resumed
+    if !resumed {
+        let discovery_bar = options
+            .progress_bars
+            .add(indicatif::ProgressBar::new_spinner());
+        discovery_bar.enable_steady_tick(Duration::from_millis(100));
+        let discovery_done = options.notify_main.notified();
+        tokio::pin!(discovery_done);
+        let mut refresh = tokio::time::interval(Duration::from_millis(200));
+        loop {
+            tokio::select! {
+                _ = &mut discovery_done => break,
+                _ = refresh.tick() => {
+                    let n_files = options.files_to_download.lock().await.len();
+                    discovery_bar.set_message(format!(
+                        "Discovering course content: {} active requests, {} files found",
+                        options.n_active_requests.load(Ordering::Relaxed),
+                        n_files
+                    ));
+                }
+            }
+        }
+        discovery_bar.finish_and_clear();
+    }
     assert_eq!(options.n_active_requests.load(Ordering::Acquire), 0);
 
     // Print sync summary
@@ -443,43 +1710,137 @@ async fn main() -> Result<()> {
     if options.n_modules.load(Ordering::Relaxed) > 0 {
         synced.push("📦 Modules");
     }
+    if options.n_quizzes.load(Ordering::Relaxed) > 0 {
+        synced.push("❓ Quizzes");
+    }
     if options.n_videos.load(Ordering::Relaxed) > 0 {
         synced.push("🎬 Videos");
     }
     if !synced.is_empty() {
-        println!("{} synced", synced.join(", "));
+        status!("{} synced", synced.join(", "));
+    }
+    status!();
+
+    // Combined calendar across all downloaded courses
+    if args.ics {
+        let deadlines = options
+            .deadlines
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if !deadlines.is_empty() {
+            let ics_path = destination_folder.join("deadlines.ics");
+            write_deadlines_ics(&ics_path, &deadlines)?;
+            status!("📅 Deadlines exported to {}", ics_path.display());
+            status!();
+        }
+    }
+
+    if let Some(ref listing_cache) = options.listing_cache
+        && let Err(e) = listing_cache.save()
+    {
+        tracing::warn!("Could not save listing cache: {e:?}");
     }
-    println!();
 
-    let files_to_download = options.files_to_download.lock().await;
+    #[cfg(feature = "pdf")]
+    write_course_pdfs(&options).await;
+
+    // Tell what couldn't be downloaded instead of dropping it silently
+    if let Err(e) = write_locked_lists(&options) {
+        tracing::warn!("Could not write list of locked files: {e:?}");
+    }
+
+    let mut files_to_download = options.files_to_download.lock().await;
+
+    // Module files already present under files/ become links instead of second downloads,
+    // made once the download is confirmed
+    let mut module_links = Vec::new();
+    if args.link_module_files {
+        let module_files = std::mem::take(&mut *options.module_files.lock().await);
+        let (links, to_download) = module_file_links(module_files, &options);
+        module_links = links;
+        files_to_download.extend(to_download);
+    }
+
+    dedup_files(&mut files_to_download, options.group_by_type);
+
+    // Both checks cost a request per file, which --newer-than-local-only avoids
+    if args.overwrite == OverwritePolicy::IfDifferent && !args.newer_than_local_only {
+        let files = std::mem::take(&mut *files_to_download);
+        *files_to_download = skip_same_size(files, &options).await;
+    }
+
+    // Updated on Canvas but possibly only in metadata: compare ETags before queueing
+    if args.track_etags && args.overwrite != OverwritePolicy::Always && !args.newer_than_local_only
+    {
+        let files = std::mem::take(&mut *files_to_download);
+        *files_to_download = skip_unchanged(files, &options).await;
+    }
+
+    // Kept for --resume-queue in case the downloads get interrupted
+    if !args.dry_run
+        && args.archive.is_none()
+        && let Err(e) = queue::save_queue(&files_to_download, &options.base_path)
+    {
+        tracing::warn!("Could not save download queue: {e:?}");
+    }
 
     if args.dry_run {
+        if args.clean {
+            trash_stale_files(&options, true);
+        }
+        for (f, target) in &module_links {
+            tracing::debug!("Would link {:?} to {:?}", f.filepath, target);
+        }
         // Dry run mode: just display what would be downloaded
         if files_to_download.is_empty() {
-            println!("[DRY RUN] No files to download.");
-            return Ok(());
+            status!("[DRY RUN] No files to download.");
+            return finish_sync(&options, report);
         }
 
-        println!("[DRY RUN] Active filters:");
+        status!("[DRY RUN] Active filters:");
         if args.ignore_file.exists() {
-            println!("  - Ignore file: {}", args.ignore_file.display());
+            status!("  - Ignore file: {}", args.ignore_file.display());
         } else {
-            println!("  - Ignore file: none");
+            status!("  - Ignore file: none");
+        }
+        if let Some(ref include_ext) = args.include_ext {
+            status!("  - Included extensions: {}", include_ext.join(", "));
         }
-        println!(
+        if let Some(ref exclude_ext) = args.exclude_ext {
+            status!("  - Excluded extensions: {}", exclude_ext.join(", "));
+        }
+        if let Some(max_size) = args.max_size {
+            status!("  - Max file size: {}", format_bytes(max_size));
+        }
+        if let Some(max_depth) = args.max_depth {
+            status!("  - Max folder depth: {max_depth}");
+        }
+        if let Some(since) = args.since {
+            status!("  - Updated since: {}", since.with_timezone(&chrono::Local));
+        }
+        status!(
             "  - Download newer files: {}",
-            if args.download_newer {
+            if args.newer_than_local_only {
+                "enabled, by local modification time only"
+            } else if args.download_newer {
                 "enabled"
             } else {
                 "disabled"
             }
         );
-        println!();
+        status!(
+            "  - Overwrite existing files: {}",
+            match args.overwrite {
+                OverwritePolicy::Skip => "skip",
+                OverwritePolicy::Always => "always",
+                OverwritePolicy::IfDifferent => "if different",
+            }
+        );
+        status!();
 
-        // Calculate total size
-        let total_size: u64 = files_to_download.iter().map(|f| f.size).sum();
+        let total_size = format_total_size(&files_to_download);
 
-        println!(
+        status!(
             "[DRY RUN] Would download {} file{} ({}):",
             files_to_download.len(),
             if files_to_download.len() == 1 {
@@ -487,19 +1848,19 @@ async fn main() -> Result<()> {
             } else {
                 "s"
             },
-            format_bytes(total_size)
+            total_size
         );
-        println!();
+        status!();
         for canvas_file in files_to_download.iter() {
-            println!(
+            status!(
                 "  {} -> {} ({})",
                 canvas_file.url,
                 canvas_file.filepath.to_string_lossy(),
                 format_bytes(canvas_file.size)
             );
         }
-        println!();
-        println!(
+        status!();
+        status!(
             "[DRY RUN] Total: {} file{} ({})",
             files_to_download.len(),
             if files_to_download.len() == 1 {
@@ -507,31 +1868,42 @@ async fn main() -> Result<()> {
             } else {
                 "s"
             },
-            format_bytes(total_size)
+            total_size
         );
     } else {
         // Normal mode: actually download files
-        // Calculate total size
-        let total_size: u64 = files_to_download.iter().map(|f| f.size).sum();
+        let total_size = format_total_size(&files_to_download);
+
+        // Links alone need no confirmation, but those that fail are downloaded instead
+        if files_to_download.is_empty() {
+            files_to_download.extend(link_module_files(std::mem::take(&mut module_links)));
+        }
 
         // Check if there are no files to download
         if files_to_download.is_empty() {
-            println!("No files to download.");
-            return Ok(());
+            status!("No files to download.");
+            if args.clean {
+                trash_stale_files(&options, false);
+            }
+            // A single item or a filtered sync doesn't make a course complete
+            if args.url.is_none() && !filtered_sync(args) {
+                write_course_complete_markers(&courses_to_download, &options);
+            }
+            return finish_sync(&options, report);
         }
 
         // Display files to be downloaded
-        println!("Files queued:");
-        println!();
+        status!("Files queued:");
+        status!();
         for canvas_file in files_to_download.iter() {
-            println!(
+            status!(
                 "  {} ({})",
                 canvas_file.filepath.to_string_lossy(),
                 format_bytes(canvas_file.size)
             );
         }
-        println!();
-        println!(
+        status!();
+        status!(
             "Total: {} file{} ({})",
             files_to_download.len(),
             if files_to_download.len() == 1 {
@@ -539,12 +1911,16 @@ async fn main() -> Result<()> {
             } else {
                 "s"
             },
-            format_bytes(total_size)
+            total_size
         );
 
+        // A sync that runs out of space fails partway, so stop before starting it
+        if !args.force {
+            check_free_space(&files_to_download, &destination_folder)?;
+        }
+
         // Ask for confirmation
-        print!("Proceed with download? [y]/n: ");
-        std::io::Write::flush(&mut std::io::stdout()).expect("Failed to flush stdout");
+        status_prompt!("Proceed with download? [y]/n: ").expect("Failed to flush stdout");
 
         let mut input = String::new();
         std::io::stdin()
@@ -553,12 +1929,31 @@ async fn main() -> Result<()> {
 
         let input = input.trim().to_lowercase();
         if !input.is_empty() && input != "y" && input != "yes" {
-            println!("Download cancelled.");
-            return Ok(());
+            status!("Download cancelled.");
+            return finish_sync(&options, report);
+        }
+        report.downloaded = true;
+        files_to_download.extend(link_module_files(module_links));
+        if args.clean {
+            trash_stale_files(&options, false);
         }
 
-        println!();
-        println!("Starting download...");
+        status!();
+        status!(
+            "Downloading {} file{}, {total_size}...",
+            files_to_download.len(),
+            if files_to_download.len() == 1 {
+                ""
+            } else {
+                "s"
+            }
+        );
+
+        if options.progress != ProgressMode::PerFile {
+            options
+                .total_progress
+                .start(&files_to_download, &options.progress_bars);
+        }
 
         // Download files
         options.n_active_requests.fetch_add(1, Ordering::AcqRel); // prevent notifying until all spawned
@@ -567,7 +1962,8 @@ async fn main() -> Result<()> {
                 atomic_download_file,
                 canvas_file.clone(),
                 canvas::File,
-                options.clone()
+                options.clone(),
+                sem_downloads
             );
         }
 
@@ -580,11 +1976,221 @@ async fn main() -> Result<()> {
         options.notify_main.notified().await;
         // Sanity check: running tasks trying to acquire sem will panic
         options.sem_requests.close();
+        options.sem_downloads.close();
+        options.sem_hooks.close();
         assert_eq!(options.n_active_requests.load(Ordering::Acquire), 0);
+        options.total_progress.finish();
+        if args.archive.is_none()
+            && let Err(e) = queue::prune_queue(&files_to_download, &options)
+        {
+            tracing::warn!("Could not save download queue: {e:?}");
+        }
+
+        status!("📁 Files downloaded");
+        status!();
+        print_course_summary(&courses_to_download, &options);
+        // A single item or a filtered sync doesn't make a course complete
+        if args.url.is_none() && !filtered_sync(args) {
+            write_course_complete_markers(&courses_to_download, &options);
+        }
+
+        if options.checksums
+            && let Err(e) = write_checksums(&options)
+        {
+            tracing::warn!("Could not write checksums: {e:?}");
+        }
+    }
+
+    finish_sync(&options, report)
+}
+
+/// Add the permits of sem_requests beyond the first one evenly over `duration`, so a rate
+/// limited Canvas instance isn't hit by --concurrency requests at once
+fn ramp_up_requests(options: Arc<ProcessOptions>, concurrency: usize, duration: Duration) {
+    let steps = concurrency.saturating_sub(1);
+    if steps == 0 {
+        return;
+    }
+    let interval = duration / steps as u32;
+    tokio::spawn(async move {
+        for _ in 0..steps {
+            tokio::time::sleep(interval).await;
+            options.sem_requests.add_permits(1);
+        }
+    });
+}
+
+/// Sum of the known file sizes; links and videos have no size until downloaded,
+/// so the total is marked as a lower bound when any of them are queued
+fn format_total_size(files: &[canvas::File]) -> String {
+    let total_size: u64 = files.iter().map(|f| f.size).sum();
+    let n_unknown = files.iter().filter(|f| f.size == 0).count();
+    if n_unknown == 0 {
+        format_bytes(total_size)
+    } else {
+        format!(
+            "at least {}, {n_unknown} file{} of unknown size",
+            format_bytes(total_size),
+            if n_unknown == 1 { "" } else { "s" }
+        )
+    }
+}
+
+/// Fail if the destination volume has less free space than the queued files need.
+/// Files that replace a local copy are counted in full, hence the --force escape hatch.
+fn check_free_space(files: &[canvas::File], destination_folder: &Path) -> Result<()> {
+    let total_size: u64 = files.iter().map(|f| f.size).sum();
+    let available = fs4::available_space(destination_folder).with_context(|| {
+        format!(
+            "Could not check free space in {}",
+            destination_folder.display()
+        )
+    })?;
+    if available < total_size {
+        anyhow::bail!(
+            "Not enough free space in {}: {} needed, {} available. Free up space or pass --force",
+            destination_folder.display(),
+            format_bytes(total_size),
+            format_bytes(available)
+        );
+    }
+    Ok(())
+}
 
-        println!("📁 Files downloaded");
+/// Move files deleted or replaced on Canvas to .trash for --clean, as they would otherwise
+/// linger locally forever. Only done once the user confirmed the download.
+fn trash_stale_files(options: &ProcessOptions, dry_run: bool) {
+    if dry_run {
+        status!("[DRY RUN] Files that would be moved to .trash:");
+    }
+    let n_cleaned = clean_stale_files(options, dry_run);
+    if n_cleaned > 0 {
+        status!(
+            "🗑️  {n_cleaned} file{} removed on Canvas {} .trash",
+            if n_cleaned == 1 { "" } else { "s" },
+            if dry_run {
+                "would be moved to"
+            } else {
+                "moved to"
+            }
+        );
+        status!();
+    }
+}
+
+/// Whether options leave out part of each course, so the sync can't mark a course complete
+fn filtered_sync(args: &CommandLineOptions) -> bool {
+    args.no_submissions
+        || args.no_files
+        || args.no_assignments
+        || args.no_discussions
+        || args.no_pages
+        || args.no_modules
+        || args.no_videos
+        || args.since.is_some()
+        || args.include_ext.is_some()
+        || args.exclude_ext.is_some()
+        || args.max_size.is_some()
+        || args.max_depth.is_some()
+}
+
+/// Mark the courses whose files all downloaded, so --only-new-courses skips them next time.
+/// Failures while crawling aren't tied to a course, so any of them leaves every course unmarked.
+fn write_course_complete_markers(courses: &[&canvas::Course], options: &ProcessOptions) {
+    if utils::INTERRUPTED.load(Ordering::Relaxed) {
+        return;
+    }
+    let course_stats = options
+        .course_stats
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let n_errors = options
+        .errors
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .len();
+    let n_download_errors: usize = course_stats.values().map(|stats| stats.errors).sum();
+    if n_errors > n_download_errors {
+        return;
+    }
+    for course in courses {
+        let course_folder_path = course_folder(course, options);
+        // Courses that were ignored have no stats and nothing on disk
+        if course_stats
+            .get(&course_folder_path)
+            .is_none_or(|stats| stats.errors > 0)
+        {
+            continue;
+        }
+        let marker_path = course_folder_path.join(COURSE_COMPLETE_MARKER);
+        if let Err(e) = std::fs::write(&marker_path, chrono::Utc::now().to_rfc3339()) {
+            tracing::warn!("Could not write to file {:?}: {e}", marker_path);
+        }
+    }
+}
+
+/// Point pages at local copies for --local-links, write the --video-index pages, add the downloads and errors to the --json report, then print the error count and
+/// fail if any task or request failed, so scripts can tell
+fn finish_sync(options: &ProcessOptions, report: &mut SyncReport) -> Result<()> {
+    if let Some(ref manifest) = options.manifest
+        && let Err(e) = manifest.save()
+    {
+        tracing::warn!("Could not save manifest: {e:?}");
+    }
+    if options.local_links {
+        rewrite_local_links(options);
+    }
+    // Also when nothing was downloaded, videos from earlier runs are still listed
+    if options.video_index
+        && let Err(e) = write_video_indexes(options)
+    {
+        tracing::warn!("Could not write video index: {e:?}");
     }
 
+    let downloaded_files = std::mem::take(
+        &mut *options
+            .downloaded_files
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()),
+    );
+    report.files.extend(
+        downloaded_files
+            .into_iter()
+            .map(|(path, size)| FileReport { path, size }),
+    );
+    let partial_videos = std::mem::take(
+        &mut *options
+            .partial_videos
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()),
+    );
+    report
+        .partial_videos
+        .extend(
+            partial_videos
+                .into_iter()
+                .map(|(path, missing_segments)| PartialVideoReport {
+                    path,
+                    missing_segments,
+                }),
+        );
+    let errors = std::mem::take(
+        &mut *options
+            .errors
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()),
+    );
+    let n_errors = errors.len();
+    report.errors += n_errors;
+    report.error_messages.extend(errors);
+    if n_errors > 0 {
+        status!();
+        status!(
+            "❌ {n_errors} error{}",
+            if n_errors == 1 { "" } else { "s" }
+        );
+        anyhow::bail!("{n_errors} error(s) occurred, see the log above");
+    }
     Ok(())
 }
 
@@ -592,38 +2198,58 @@ async fn process_data(
     (url, course_id, path): (String, u32, PathBuf),
     options: Arc<ProcessOptions>,
 ) -> Result<()> {
-    fork!(
-        process_assignments,
-        (url.clone(), path.clone()),
-        (String, PathBuf),
-        options.clone()
-    );
+    if !options.skip_assignments {
+        fork!(
+            process_assignments,
+            (url.clone(), path.clone()),
+            (String, PathBuf),
+            options.clone()
+        );
+        fork!(
+            process_grades,
+            (url.clone(), path.clone()),
+            (String, PathBuf),
+            options.clone()
+        );
+    }
     fork!(
         process_users,
         (url.clone(), path.clone()),
         (String, PathBuf),
         options.clone()
     );
+    if !options.skip_discussions {
+        fork!(
+            process_discussions,
+            (url.clone(), false, path.clone()),
+            (String, bool, PathBuf),
+            options.clone()
+        );
+        fork!(
+            process_discussions,
+            (url.clone(), true, path.clone()),
+            (String, bool, PathBuf),
+            options.clone()
+        );
+    }
+    if !options.skip_pages {
+        fork!(
+            process_pages,
+            (url.clone(), path.clone()),
+            (String, PathBuf),
+            options.clone()
+        );
+    }
+    if !options.skip_modules {
+        fork!(
+            process_modules,
+            (url.clone(), path.clone()),
+            (String, PathBuf),
+            options.clone()
+        );
+    }
     fork!(
-        process_discussions,
-        (url.clone(), false, path.clone()),
-        (String, bool, PathBuf),
-        options.clone()
-    );
-    fork!(
-        process_discussions,
-        (url.clone(), true, path.clone()),
-        (String, bool, PathBuf),
-        options.clone()
-    );
-    fork!(
-        process_pages,
-        (url.clone(), path.clone()),
-        (String, PathBuf),
-        options.clone()
-    );
-    fork!(
-        process_modules,
+        process_quizzes,
         (url.clone(), path.clone()),
         (String, PathBuf),
         options.clone()
@@ -0,0 +1,108 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// What runs with --manifest downloaded for one account
+#[derive(Default, Serialize, Deserialize)]
+struct AccountManifest {
+    base_path: PathBuf,              // destination of the last run that recorded files
+    files: BTreeMap<String, String>, // path relative to base_path -> updated_at
+}
+
+/// Files downloaded by earlier runs, recorded by their path relative to the destination, so
+/// a run into another destination (e.g. after moving the folder) still recognizes them
+pub struct Manifest {
+    path: PathBuf,
+    canvas_url: String,
+    base_path: PathBuf,
+    previous: AccountManifest,
+    current: Mutex<HashMap<String, String>>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path, canvas_url: &str, base_path: &Path) -> Result<Self> {
+        let previous = read_manifest(path)?.remove(canvas_url).unwrap_or_default();
+        if !previous.files.is_empty() && previous.base_path != base_path {
+            status!(
+                "📒 Manifest {} was recorded for {}, matching its files by relative path",
+                path.display(),
+                previous.base_path.display()
+            );
+        }
+        Ok(Manifest {
+            path: path.to_path_buf(),
+            canvas_url: canvas_url.to_string(),
+            base_path: base_path.to_path_buf(),
+            previous,
+            current: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The updated_at a file had when an earlier run downloaded it, wherever it was saved to
+    pub fn recorded_updated_at(&self, filepath: &Path) -> Option<&str> {
+        self.previous
+            .files
+            .get(&self.relative_path(filepath)?)
+            .map(String::as_str)
+    }
+
+    /// Remember a file downloaded on this run, to be written by save()
+    pub fn record(&self, filepath: &Path, updated_at: &str) {
+        // Courses with their own destination are outside the base path
+        let Some(relative_path) = self.relative_path(filepath) else {
+            return;
+        };
+        self.current
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(relative_path, updated_at.to_string());
+    }
+
+    /// Merge this run's downloads into the manifest under the current destination
+    pub fn save(&self) -> Result<()> {
+        let current = self
+            .current
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if current.is_empty() {
+            return Ok(());
+        }
+        // Other accounts may have been saved since this one was loaded
+        let mut accounts = read_manifest(&self.path)?;
+        let account = accounts.entry(self.canvas_url.clone()).or_default();
+        account.base_path = self.base_path.clone();
+        for (relative_path, updated_at) in current.iter() {
+            account
+                .files
+                .insert(relative_path.clone(), updated_at.clone());
+        }
+        std::fs::write(&self.path, serde_json::to_string_pretty(&accounts)?)
+            .with_context(|| format!("Could not write to file {:?}", self.path))?;
+        Ok(())
+    }
+
+    fn relative_path(&self, filepath: &Path) -> Option<String> {
+        let relative_path = filepath.strip_prefix(&self.base_path).ok()?;
+        Some(
+            relative_path
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+        )
+    }
+}
+
+/// Accounts in the manifest file by Canvas URL, empty if there is no file yet
+fn read_manifest(path: &Path) -> Result<BTreeMap<String, AccountManifest>> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read manifest {}", path.display()))?;
+    serde_json::from_str(&content)
+        .with_context(|| format!("Could not parse manifest {}", path.display()))
+}
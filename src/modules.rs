@@ -1,15 +1,18 @@
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
 
 use anyhow::{Context, Result};
 
 use crate::api::get_pages;
-use crate::canvas::{ModuleItemResult, ModuleResult, ProcessOptions};
+use crate::canvas::{ModuleItemResult, ModuleLayout, ModuleResult, ProcessOptions};
 use crate::files::{filter_files, process_file_id};
 use crate::pages::process_page_body;
-use crate::utils::{create_folder_if_not_exist_or_ignored, get_raw_json_path, prettify_json};
+use crate::utils::{
+    create_folder_if_not_exist_or_ignored, flatten_path, get_raw_json_path, prettify_json,
+    sanitize_path_component,
+};
 
 pub async fn process_modules(
     (url, path): (String, PathBuf),
@@ -59,8 +62,8 @@ pub async fn process_modules(
 
                 for module in modules {
                     if let Some(ref modules_path) = modules_folder_path {
-                        let module_path =
-                            modules_path.join(sanitize_filename::sanitize(&module.name));
+                        let module_path = modules_path
+                            .join(sanitize_path_component(&module.name, options.filename_mode));
                         if !create_folder_if_not_exist_or_ignored(&module_path, &options)? {
                             continue;
                         }
@@ -76,11 +79,19 @@ pub async fn process_modules(
             }
 
             Ok(ModuleResult::Err { status }) => {
-                tracing::error!("No modules found for url {} status: {}", url, status);
+                options.record_error(anyhow::anyhow!(
+                    "No modules found for url {} status: {}",
+                    url,
+                    status
+                ));
             }
 
             Err(e) => {
-                tracing::error!("No modules found for url {} error: {}", url, e);
+                options.record_error(anyhow::anyhow!(
+                    "No modules found for url {} error: {}",
+                    url,
+                    e
+                ));
             }
         };
     }
@@ -129,12 +140,16 @@ async fn process_module_items(
                 for item in items {
                     match item.item_type.as_str() {
                         "File" => {
-                            if let Some(content_id) = item.content_id {
-                                let file_url = format!(
-                                    "{}/api/v1/files/{}",
-                                    options.canvas_url.trim_end_matches('/'),
-                                    content_id
+                            if options.module_layout == ModuleLayout::ByFiles {
+                                tracing::debug!(
+                                    "Module item {} is left to the files folder",
+                                    item.title
                                 );
+                                continue;
+                            }
+                            if let Some(content_id) = item.content_id {
+                                let file_url =
+                                    format!("{}/api/v1/files/{}", options.canvas_url, content_id);
 
                                 match process_file_id((file_url, path.clone()), options.clone())
                                     .await
@@ -143,18 +158,19 @@ async fn process_module_items(
                                         files_to_process.push(file);
                                     }
                                     Err(e) => {
-                                        tracing::error!(
-                                            "Error processing module file {}: {:?}",
-                                            content_id,
-                                            e
-                                        );
+                                        options.record_error(e.context(format!(
+                                            "Error processing module file {content_id}"
+                                        )));
                                     }
                                 }
                             }
                         }
                         "Page" => {
                             if let Some(full_page_url) = item.url {
-                                let item_path = path.join(sanitize_filename::sanitize(&item.title));
+                                let item_path = path.join(sanitize_path_component(
+                                    &item.title,
+                                    options.filename_mode,
+                                ));
                                 if !create_folder_if_not_exist_or_ignored(&item_path, &options)? {
                                     continue;
                                 }
@@ -176,6 +192,15 @@ async fn process_module_items(
                                 );
                             }
                         }
+                        "Quiz" => {
+                            if let Some(content_id) = item.content_id {
+                                tracing::debug!(
+                                    "Module item {} references quiz {}",
+                                    item.title,
+                                    content_id
+                                );
+                            }
+                        }
                         "Discussion" => {
                             if let Some(content_id) = item.content_id {
                                 tracing::debug!(
@@ -187,20 +212,21 @@ async fn process_module_items(
                         }
                         "ExternalUrl" => {
                             if let Some(external_url) = &item.external_url {
-                                let url_file = path.join(format!(
-                                    "{}.url",
-                                    sanitize_filename::sanitize(&item.title)
-                                ));
-                                if let Ok(mut file) = std::fs::File::create(&url_file) {
-                                    let _ = writeln!(file, "[InternetShortcut]");
-                                    let _ = writeln!(file, "URL={}", external_url);
-                                }
+                                write_url_shortcut(&path, &item.title, external_url, &options);
+                            }
+                        }
+                        "ExternalTool" => {
+                            // LTI tools can't be downloaded, but their launch page is a useful bookmark
+                            if let Some(tool_url) =
+                                item.external_url.as_ref().or(item.html_url.as_ref())
+                            {
+                                write_url_shortcut(&path, &item.title, tool_url, &options);
                             }
                         }
                         "SubHeader" => {
                             // SubHeaders are just organizational - create a folder
-                            let subheader_path =
-                                path.join(sanitize_filename::sanitize(&item.title));
+                            let subheader_path = path
+                                .join(sanitize_path_component(&item.title, options.filename_mode));
                             if !create_folder_if_not_exist_or_ignored(&subheader_path, &options)? {
                                 continue;
                             }
@@ -219,25 +245,44 @@ async fn process_module_items(
                 if !files_to_process.is_empty() {
                     let filtered_files = filter_files(&options, &path, files_to_process);
                     if !filtered_files.is_empty() {
-                        let mut lock = options.files_to_download.lock().await;
+                        // Decided after discovery, once every course file under files/ is known
+                        let mut lock = if options.link_module_files {
+                            options.module_files.lock().await
+                        } else {
+                            options.files_to_download.lock().await
+                        };
                         lock.extend(filtered_files);
                     }
                 }
             }
 
             Ok(ModuleItemResult::Err { status }) => {
-                tracing::error!(
+                options.record_error(anyhow::anyhow!(
                     "Failed to access module items at link:{url}, path:{path:?}, status:{status}"
-                );
+                ));
             }
 
             Err(e) => {
-                tracing::error!(
+                options.record_error(anyhow::anyhow!(
                     "Error when getting module items at link:{url}, path:{path:?}\n{e:?}"
-                );
+                ));
             }
         }
     }
 
     Ok(())
 }
+
+fn write_url_shortcut(path: &Path, title: &str, url: &str, options: &ProcessOptions) {
+    let url_file = flatten_path(
+        &path.join(format!(
+            "{}.url",
+            sanitize_path_component(title, options.filename_mode)
+        )),
+        options,
+    );
+    if let Ok(mut file) = std::fs::File::create(&url_file) {
+        let _ = writeln!(file, "[InternetShortcut]");
+        let _ = writeln!(file, "URL={}", url);
+    }
+}
@@ -0,0 +1,184 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use crate::canvas::Credentials;
+
+/// Local port Canvas redirects back to; the developer key must list OAUTH_REDIRECT_URI
+const OAUTH_REDIRECT_PORT: u16 = 8765;
+const OAUTH_REDIRECT_URI: &str = "http://localhost:8765/oauth/callback";
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+}
+
+/// Obtain an access token through the Canvas OAuth2 flow and cache it in the config file.
+/// A cached refresh token is tried first; the browser is only opened if that fails.
+pub async fn login(
+    client: &reqwest::Client,
+    cred: &mut Credentials,
+    config_path: &Path,
+) -> Result<()> {
+    let (Some(client_id), Some(client_secret)) = (
+        cred.oauth_client_id.clone(),
+        cred.oauth_client_secret.clone(),
+    ) else {
+        anyhow::bail!(
+            "--oauth needs oauth_client_id and oauth_client_secret of a Canvas developer key in {}",
+            config_path.display()
+        );
+    };
+    let token_link = format!("{}/login/oauth2/token", cred.canvas_url);
+
+    if let Some(ref refresh_token) = cred.oauth_refresh_token {
+        let params = [
+            ("grant_type", "refresh_token"),
+            ("client_id", client_id.as_str()),
+            ("client_secret", client_secret.as_str()),
+            ("refresh_token", refresh_token.as_str()),
+        ];
+        match request_token(client, &token_link, &params).await {
+            Ok(token) => {
+                cred.canvas_token = token.access_token;
+                return save_tokens(config_path, cred);
+            }
+            Err(e) => tracing::warn!("Could not refresh OAuth token, logging in again: {e:?}"),
+        }
+    }
+
+    let code = authorize(&cred.canvas_url, &client_id).await?;
+    let params = [
+        ("grant_type", "authorization_code"),
+        ("client_id", client_id.as_str()),
+        ("client_secret", client_secret.as_str()),
+        ("redirect_uri", OAUTH_REDIRECT_URI),
+        ("code", code.as_str()),
+    ];
+    let token = request_token(client, &token_link, &params).await?;
+    cred.canvas_token = token.access_token;
+    cred.oauth_refresh_token = token.refresh_token;
+    save_tokens(config_path, cred)
+}
+
+/// Open the authorize page in the browser and wait for Canvas to redirect back with a code
+async fn authorize(canvas_url: &str, client_id: &str) -> Result<String> {
+    let state = format!("{:016x}", rand::random::<u64>());
+    let authorize_url = reqwest::Url::parse_with_params(
+        &format!("{canvas_url}/login/oauth2/auth"),
+        &[
+            ("client_id", client_id),
+            ("response_type", "code"),
+            ("redirect_uri", OAUTH_REDIRECT_URI),
+            ("state", &state),
+        ],
+    )
+    .with_context(|| format!("Invalid Canvas URL: {canvas_url}"))?;
+
+    let listener = TcpListener::bind(("127.0.0.1", OAUTH_REDIRECT_PORT))
+        .await
+        .with_context(|| format!("Could not listen on port {OAUTH_REDIRECT_PORT}"))?;
+
+    status!("Log in to Canvas in your browser to continue. If it does not open, visit:");
+    status!("  {authorize_url}");
+    if let Err(e) = open::that(authorize_url.as_str()) {
+        tracing::debug!("Could not open browser: {e}");
+    }
+
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+
+        // The redirect is a GET request, its first line carries the query string
+        let mut buf = vec![0; 8192];
+        let n = stream.read(&mut buf).await?;
+        let request = String::from_utf8_lossy(&buf[..n]);
+        let Some(target) = request
+            .lines()
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+        else {
+            continue;
+        };
+        let url = reqwest::Url::parse(&format!("http://localhost{target}"))?;
+        if url.path() != "/oauth/callback" {
+            stream
+                .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+                .await?;
+            continue;
+        }
+
+        let query = |key: &str| {
+            url.query_pairs()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.into_owned())
+        };
+        let result = if let Some(error) = query("error") {
+            Err(anyhow::anyhow!("Canvas authorization failed: {error}"))
+        } else if query("state").as_deref() != Some(state.as_str()) {
+            Err(anyhow::anyhow!(
+                "Canvas authorization returned a mismatched state"
+            ))
+        } else {
+            query("code").context("Canvas authorization returned no code")
+        };
+
+        let body = match result {
+            Ok(_) => "Logged in, you can close this window.",
+            Err(_) => "Login failed, see the terminal for details.",
+        };
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(response.as_bytes()).await?;
+        return result;
+    }
+}
+
+async fn request_token(
+    client: &reqwest::Client,
+    token_link: &str,
+    params: &[(&str, &str)],
+) -> Result<TokenResponse> {
+    client
+        .post(token_link)
+        .form(params)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<TokenResponse>()
+        .await
+        .with_context(|| "Failed to parse OAuth token response")
+}
+
+/// Write the tokens back into the config file, keeping its other settings, comments and key order.
+/// A config with `[[accounts]]` gets them in its only account, where they are read back.
+fn save_tokens(config_path: &Path, cred: &Credentials) -> Result<()> {
+    let config_content = std::fs::read_to_string(config_path)
+        .with_context(|| format!("Could not read config file: {}", config_path.display()))?;
+    let mut config = config_content
+        .parse::<toml_edit::DocumentMut>()
+        .with_context(|| "Config file is not valid TOML")?;
+    let table = match config.get_mut("accounts") {
+        Some(accounts) => accounts
+            .as_array_of_tables_mut()
+            .and_then(|accounts| accounts.get_mut(0))
+            .with_context(|| {
+                format!(
+                    "Could not save the OAuth token, expected [[accounts]] in {}",
+                    config_path.display()
+                )
+            })?,
+        None => config.as_table_mut(),
+    };
+    table["canvas_token"] = toml_edit::value(cred.canvas_token.clone());
+    if let Some(ref refresh_token) = cred.oauth_refresh_token {
+        table["oauth_refresh_token"] = toml_edit::value(refresh_token.clone());
+    }
+    std::fs::write(config_path, config.to_string())
+        .with_context(|| format!("Could not write to file {:?}", config_path))
+}
@@ -1,14 +1,19 @@
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
 
 use anyhow::{Context, Result};
 
 use crate::api::{get_canvas_api, get_pages};
-use crate::canvas::{PageBody, PageResult, ProcessOptions};
-use crate::html::process_html_links;
-use crate::utils::{create_folder_if_not_exist_or_ignored, get_raw_json_path, prettify_json};
+use crate::canvas::{Page, PageBody, PageResult, ProcessOptions};
+use crate::html::{process_html_links, save_content};
+#[cfg(feature = "pdf")]
+use crate::pdf::{CourseSection, add_to_course_pdf};
+use crate::utils::{
+    create_folder_if_not_exist_or_ignored, flatten_path, get_raw_json_path, prettify_json,
+    sanitize_path_component,
+};
 
 pub async fn process_pages(
     (url, path): (String, PathBuf),
@@ -91,17 +96,41 @@ pub async fn process_pages(
     Ok(())
 }
 
-pub async fn process_page_body(
-    (url, title, path): (String, String, PathBuf),
+/// Download one page, e.g. for --url, given the name in its link (`slug`)
+pub async fn process_page(
+    (url, slug, path): (String, String, PathBuf),
     options: Arc<ProcessOptions>,
 ) -> Result<()> {
-    let page_resp = get_canvas_api(url.clone(), &options).await?;
-
-    let title = sanitize_filename::sanitize(&title);
-    let page_resp_text = page_resp.text().await?;
+    let page_url = format!("{url}pages/{slug}");
+    let page = get_canvas_api(page_url.clone(), &options)
+        .await?
+        .json::<Page>()
+        .await
+        .with_context(|| format!("Error when getting page at link:{page_url}"))?;
+
+    let pages_path = path.join("pages");
+    if !create_folder_if_not_exist_or_ignored(&pages_path, &options)? {
+        return Ok(());
+    }
+    fork!(
+        process_page_body,
+        (format!("{url}pages/{}", page.url), page.title, pages_path),
+        (String, String, PathBuf),
+        options.clone()
+    );
+    options.n_pages.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
 
+/// Save the raw JSON of a page as <title>.json under raw/, unless --no-raw
+fn save_page_json(
+    path: &Path,
+    title: &str,
+    page_resp_text: &str,
+    options: &ProcessOptions,
+) -> Result<()> {
     if let Some(page_file_path) = get_raw_json_path(
-        &path,
+        path,
         &format!("{}.json", title),
         &options.base_path,
         options.save_json,
@@ -109,11 +138,27 @@ pub async fn process_page_body(
         let mut page_file = std::fs::File::create(page_file_path.clone())
             .with_context(|| format!("Unable to create file for {:?}", page_file_path))?;
 
-        let pretty_json = prettify_json(&page_resp_text).unwrap_or(page_resp_text.clone());
+        let pretty_json = prettify_json(page_resp_text).unwrap_or(page_resp_text.to_string());
         page_file
             .write_all(pretty_json.as_bytes())
             .with_context(|| format!("Could not write to file {:?}", page_file_path))?;
     }
+    Ok(())
+}
+
+pub async fn process_page_body(
+    (url, title, path): (String, String, PathBuf),
+    options: Arc<ProcessOptions>,
+) -> Result<()> {
+    let page_resp = get_canvas_api(url.clone(), &options).await?;
+
+    let title = sanitize_path_component(&title, options.filename_mode);
+    let page_resp_text = page_resp.text().await?;
+
+    // The page is still saved when its raw JSON can't be
+    if let Err(e) = save_page_json(&path, &title, &page_resp_text, &options) {
+        options.record_error(e);
+    }
 
     let page_body_result = serde_json::from_str::<PageBody>(&page_resp_text);
     match page_body_result {
@@ -124,13 +169,13 @@ pub async fn process_page_body(
                 page_body.body.unwrap_or_default()
             );
 
-            let page_html_path = path.join(format!("{}.html", title));
-            let mut page_html_file = std::fs::File::create(page_html_path.clone())
-                .with_context(|| format!("Unable to create file for {:?}", page_html_path))?;
-
-            page_html_file
-                .write_all(page_html.as_bytes())
-                .with_context(|| format!("Could not write to file {:?}", page_html_path))?;
+            let page_html_path = flatten_path(&path.join(format!("{}.html", title)), &options);
+            // Files linked from the page are still worth fetching if it can't be written
+            if let Err(e) = save_content(&page_html_path, &page_html, &options).await {
+                options.record_error(e);
+            }
+            #[cfg(feature = "pdf")]
+            add_to_course_pdf(CourseSection::Pages, &page_html_path, &page_html, &options);
 
             fork!(
                 process_html_links,
@@ -140,7 +185,9 @@ pub async fn process_page_body(
             )
         }
         Result::Err(e) => {
-            tracing::error!("Error when parsing page body at link:{url}, path:{path:?}\n{e:?}",);
+            options.record_error(anyhow::anyhow!(
+                "Error when parsing page body at link:{url}, path:{path:?}\n{e:?}"
+            ));
         }
     }
     Ok(())
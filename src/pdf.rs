@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use lazy_regex::regex;
+use reqwest::Url;
+
+use crate::canvas::ProcessOptions;
+use crate::utils::{enclosing_course_folder, find_in_path, html_escape};
+
+/// External program used to render saved HTML files into PDFs
+#[derive(Clone, Debug)]
+pub enum PdfRenderer {
+    Wkhtmltopdf(PathBuf),
+    Chromium(PathBuf),
+}
+
+impl PdfRenderer {
+    /// Look for a supported renderer in PATH, preferring wkhtmltopdf
+    pub fn find() -> Option<Self> {
+        if let Some(path) = find_in_path("wkhtmltopdf") {
+            return Some(PdfRenderer::Wkhtmltopdf(path));
+        }
+        ["chromium", "chromium-browser", "google-chrome", "chrome"]
+            .iter()
+            .find_map(|name| find_in_path(name))
+            .map(PdfRenderer::Chromium)
+    }
+
+    async fn render(&self, html_path: &Path, pdf_path: &Path) -> Result<()> {
+        let output = match self {
+            PdfRenderer::Wkhtmltopdf(bin) => {
+                tokio::process::Command::new(bin)
+                    .arg("--quiet")
+                    .arg("--enable-local-file-access")
+                    .arg(html_path)
+                    .arg(pdf_path)
+                    .output()
+                    .await
+            }
+            PdfRenderer::Chromium(bin) => {
+                let html_url = Url::from_file_path(std::fs::canonicalize(html_path)?)
+                    .map_err(|_| anyhow!("Could not build file URL for {:?}", html_path))?;
+                let mut print_to_pdf = std::ffi::OsString::from("--print-to-pdf=");
+                print_to_pdf.push(pdf_path);
+                tokio::process::Command::new(bin)
+                    .arg("--headless")
+                    .arg("--disable-gpu")
+                    .arg("--no-pdf-header-footer")
+                    .arg(print_to_pdf)
+                    .arg(html_url.as_str())
+                    .output()
+                    .await
+            }
+        }
+        .with_context(|| format!("Failed to run PDF renderer {self:?}"))?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "PDF renderer exited with {} for {:?}: {}",
+                output.status,
+                html_path,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Render `html_path` to a sibling .pdf when --pdf is enabled. Failures only warn.
+pub async fn save_pdf(html_path: &Path, options: &ProcessOptions) {
+    let Some(ref renderer) = options.pdf_renderer else {
+        return;
+    };
+    if !options.pdf {
+        return;
+    }
+    let pdf_path = html_path.with_extension("pdf");
+    if let Err(e) = renderer.render(html_path, &pdf_path).await {
+        tracing::warn!("Failed to convert {:?} to PDF: {e:#}", html_path);
+    }
+}
+
+/// Parts of the combined course PDF, in the order they appear in it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CourseSection {
+    Syllabus,
+    Pages,
+    Assignments,
+}
+
+/// File name of the combined PDF in each course folder, see --combine-pdf
+const COURSE_PDF_NAME: &str = "course.pdf";
+
+/// Remember a generated document for the combined course PDF when --combine-pdf is enabled
+pub fn add_to_course_pdf(
+    section: CourseSection,
+    html_path: &Path,
+    html: &str,
+    options: &ProcessOptions,
+) {
+    if !options.combine_pdf {
+        return;
+    }
+    options
+        .course_pdf_parts
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .push((section, html_path.to_path_buf(), html.to_string()));
+}
+
+/// Render the syllabus, pages and assignment descriptions of each course into one course.pdf.
+/// Failures only warn.
+pub async fn write_course_pdfs(options: &ProcessOptions) {
+    let Some(ref renderer) = options.pdf_renderer else {
+        return;
+    };
+    let mut parts = std::mem::take(
+        &mut *options
+            .course_pdf_parts
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()),
+    );
+    // Sections in order, then alphabetically by path within a section
+    parts.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+
+    let mut courses: BTreeMap<PathBuf, Vec<&str>> = BTreeMap::new();
+    for (_, html_path, html) in &parts {
+        if let Some(course_folder) = enclosing_course_folder(html_path, options) {
+            courses.entry(course_folder).or_default().push(html);
+        }
+    }
+
+    for (course_folder, documents) in courses {
+        let title = course_folder
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        // Rendered next to the PDF, so relative links resolve as in the individual files
+        let html_path = course_folder.join(".course.pdf.html");
+        let pdf_path = course_folder.join(COURSE_PDF_NAME);
+        let result = match std::fs::write(&html_path, combine_html(&title, &documents)) {
+            Ok(()) => renderer.render(&html_path, &pdf_path).await,
+            Err(e) => Err(e.into()),
+        };
+        let _ = std::fs::remove_file(&html_path);
+        if let Err(e) = result {
+            tracing::warn!("Failed to write {:?}: {e:#}", pdf_path);
+        }
+    }
+}
+
+/// Join HTML documents into one, keeping each one's styles and starting each on a new page
+fn combine_html(title: &str, documents: &[&str]) -> String {
+    let mut styles: Vec<&str> = Vec::new();
+    let mut body = String::new();
+    for (i, document) in documents.iter().enumerate() {
+        for style in regex!(r"(?is)<style\b.*?</style>").find_iter(document) {
+            if !styles.contains(&style.as_str()) {
+                styles.push(style.as_str());
+            }
+        }
+        let content = regex!(r"(?is)<body\b[^>]*>(.*)</body>")
+            .captures(document)
+            .and_then(|captures| captures.get(1))
+            .map_or(*document, |content| content.as_str());
+        if i > 0 {
+            body.push_str("<div style=\"page-break-before: always\"></div>\n");
+        }
+        body.push_str(content);
+        body.push('\n');
+    }
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"UTF-8\">\n<title>{}</title>\n{}\n</head>\n<body>\n{body}</body>\n</html>",
+        html_escape(title),
+        styles.join("\n")
+    )
+}
@@ -0,0 +1,66 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+
+use crate::canvas::File;
+
+/// One bar above the per-file bars with the files finished and bytes downloaded in total,
+/// see --progress. It stays hidden until start() is called.
+pub struct TotalProgress {
+    bar: ProgressBar,
+    n_files: AtomicUsize,
+    n_finished: AtomicUsize,
+}
+
+impl TotalProgress {
+    pub fn new() -> Self {
+        TotalProgress {
+            bar: ProgressBar::hidden(),
+            n_files: AtomicUsize::new(0),
+            n_finished: AtomicUsize::new(0),
+        }
+    }
+
+    /// Show the bar on top of the others for the queued files. Files of unknown size
+    /// (videos and links) add to the total once their download reports a size.
+    pub fn start(&self, files: &[File], progress_bars: &MultiProgress) {
+        self.n_files.store(files.len(), Ordering::Relaxed);
+        let style_template = if termsize::get().is_some_and(|size| size.cols < 100) {
+            "[{wide_bar:.green/blue}] {total_bytes} - {msg}"
+        } else {
+            "[{bar:20.green/blue}] {bytes}/{total_bytes} - {bytes_per_sec} - {msg}"
+        };
+        if let Ok(style) = ProgressStyle::default_bar().template(style_template) {
+            self.bar.set_style(style.progress_chars("=>-"));
+        }
+        self.bar.set_length(files.iter().map(|f| f.size).sum());
+        self.bar.set_message(self.message());
+        progress_bars.insert(0, self.bar.clone());
+    }
+
+    pub fn add_length(&self, bytes: u64) {
+        self.bar.inc_length(bytes);
+    }
+
+    pub fn add_bytes(&self, bytes: u64) {
+        self.bar.inc(bytes);
+    }
+
+    /// Count a file as done, whether it was downloaded or failed
+    pub fn finish_file(&self) {
+        self.n_finished.fetch_add(1, Ordering::Relaxed);
+        self.bar.set_message(self.message());
+    }
+
+    pub fn finish(&self) {
+        self.bar.finish();
+    }
+
+    fn message(&self) -> String {
+        format!(
+            "{}/{} files",
+            self.n_finished.load(Ordering::Relaxed),
+            self.n_files.load(Ordering::Relaxed)
+        )
+    }
+}
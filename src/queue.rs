@@ -0,0 +1,96 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::canvas::{File, ProcessOptions};
+
+/// Files still to download, saved in the destination so --resume-queue can skip discovery
+const QUEUE_FILE_NAME: &str = ".canvas-queue.json";
+
+/// A queued file with the fields discovery fills in, which File doesn't serialize
+#[derive(Serialize, Deserialize)]
+struct QueuedFile {
+    #[serde(flatten)]
+    file: File,
+    filepath: PathBuf,
+    #[serde(default)]
+    segments: Vec<String>,
+    #[serde(default)]
+    remux_to_mp4: bool,
+}
+
+/// Save the files about to be downloaded, or remove the saved queue if there are none
+pub fn save_queue(files: &[File], base_path: &Path) -> Result<()> {
+    let queue_path = base_path.join(QUEUE_FILE_NAME);
+    if files.is_empty() {
+        if queue_path.exists() {
+            std::fs::remove_file(&queue_path)
+                .with_context(|| format!("Could not remove {:?}", queue_path))?;
+        }
+        return Ok(());
+    }
+    let queued = files
+        .iter()
+        .map(|file| QueuedFile {
+            file: file.clone(),
+            filepath: file.filepath.clone(),
+            segments: file.segments.clone(),
+            remux_to_mp4: file.remux_to_mp4,
+        })
+        .collect::<Vec<_>>();
+    std::fs::write(&queue_path, serde_json::to_string(&queued)?)
+        .with_context(|| format!("Could not write to file {:?}", queue_path))?;
+    Ok(())
+}
+
+/// Keep only the queued files this run didn't download, for the next --resume-queue.
+/// Matched by id and URL, as a download may be saved under another name than queued.
+pub fn prune_queue(files: &[File], options: &ProcessOptions) -> Result<()> {
+    let downloaded = options
+        .downloaded_ids
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let remaining = files
+        .iter()
+        .filter(|file| !downloaded.contains(&(file.id, file.url.clone())))
+        .cloned()
+        .collect::<Vec<_>>();
+    save_queue(&remaining, &options.base_path)
+}
+
+/// The files queued by the last run, or None if there is no queue or it is older than
+/// `max_age`, as the download links in it may have expired by then
+pub fn load_queue(base_path: &Path, max_age: Duration) -> Result<Option<Vec<File>>> {
+    let queue_path = base_path.join(QUEUE_FILE_NAME);
+    let Ok(metadata) = std::fs::metadata(&queue_path) else {
+        status!(
+            "No saved download queue in {}, discovering files again",
+            base_path.display()
+        );
+        return Ok(None);
+    };
+    let age = metadata.modified()?.elapsed().unwrap_or_default();
+    if age > max_age {
+        status!(
+            "The saved download queue is {} hours old and its download links may have expired, discovering files again",
+            age.as_secs() / 3600
+        );
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(&queue_path)
+        .with_context(|| format!("Could not read {:?}", queue_path))?;
+    let queued = serde_json::from_str::<Vec<QueuedFile>>(&content)
+        .with_context(|| format!("Could not parse {:?}", queue_path))?;
+    let files = queued
+        .into_iter()
+        .map(|queued| File {
+            filepath: queued.filepath,
+            segments: queued.segments,
+            remux_to_mp4: queued.remux_to_mp4,
+            ..queued.file
+        })
+        .collect();
+    Ok(Some(files))
+}
@@ -0,0 +1,250 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use anyhow::{Context, Result};
+
+use crate::api::get_pages;
+use crate::canvas::{
+    ProcessOptions, Quiz, QuizAnswer, QuizQuestion, QuizQuestionResult, QuizResult,
+};
+use crate::html::{process_html_links, save_content};
+use crate::utils::{
+    create_folder_if_not_exist_or_ignored, flatten_path, get_raw_json_path, html_escape,
+    prettify_json, sanitize_path_component,
+};
+
+pub async fn process_quizzes(
+    (url, path): (String, PathBuf),
+    options: Arc<ProcessOptions>,
+) -> Result<()> {
+    let quizzes_url = format!("{}quizzes", url);
+    let pages = get_pages(quizzes_url, &options).await?;
+
+    let mut has_quizzes = false;
+    let mut quizzes_folder_path = None;
+
+    for pg in pages {
+        let uri = pg.url().to_string();
+        let page_body = pg.text().await?;
+
+        let quiz_result = serde_json::from_str::<QuizResult>(&page_body);
+
+        match quiz_result {
+            Ok(QuizResult::Ok(quizzes)) => {
+                if !quizzes.is_empty() && !has_quizzes {
+                    // Create quizzes folder only when we have actual quizzes
+                    let folder_path = path.join("quizzes");
+                    if !create_folder_if_not_exist_or_ignored(&folder_path, &options)? {
+                        continue;
+                    }
+                    quizzes_folder_path = Some(folder_path.clone());
+                    has_quizzes = true;
+
+                    // Create quizzes.json file
+                    if let Some(quizzes_json_path) = get_raw_json_path(
+                        &path,
+                        "quizzes.json",
+                        &options.base_path,
+                        options.save_json,
+                    )? {
+                        let mut quizzes_json_file =
+                            std::fs::File::create(quizzes_json_path.clone()).with_context(
+                                || format!("Unable to create file for {:?}", quizzes_json_path),
+                            )?;
+                        let pretty_json = prettify_json(&page_body).unwrap_or(page_body.clone());
+                        quizzes_json_file
+                            .write_all(pretty_json.as_bytes())
+                            .with_context(|| {
+                                format!("Unable to write to file for {:?}", quizzes_json_path)
+                            })?;
+                    }
+                }
+
+                for quiz in quizzes {
+                    if let Some(ref folder_path) = quizzes_folder_path {
+                        fork!(
+                            process_quiz_questions,
+                            (url.clone(), quiz.clone(), folder_path.clone()),
+                            (String, Quiz, PathBuf),
+                            options.clone()
+                        );
+                        fork!(
+                            process_quiz_description,
+                            (quiz, folder_path.clone()),
+                            (Quiz, PathBuf),
+                            options.clone()
+                        );
+                    }
+                }
+            }
+            Ok(QuizResult::Err { status }) => {
+                tracing::debug!("No quizzes found for url {} (status: {})", uri, status);
+            }
+            Err(e) => {
+                tracing::debug!("No quizzes found for url {} (error: {})", uri, e);
+            }
+        }
+    }
+
+    if has_quizzes {
+        tracing::debug!(
+            "❓ Quizzes synced for {}",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        );
+        options.n_quizzes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    Ok(())
+}
+
+async fn process_quiz_description(
+    (quiz, path): (Quiz, PathBuf),
+    options: Arc<ProcessOptions>,
+) -> Result<()> {
+    let Some(description) = quiz.description.filter(|d| !d.trim().is_empty()) else {
+        return Ok(());
+    };
+
+    let quiz_name = sanitize_path_component(&quiz.title, options.filename_mode);
+    let quiz_html = format!(
+        "<html><head><title>{}</title></head><body>{}</body></html>",
+        quiz.title, description
+    );
+
+    let quiz_html_path = flatten_path(&path.join(format!("{}.html", quiz_name)), &options);
+    save_content(&quiz_html_path, &quiz_html, &options).await?;
+
+    fork!(
+        process_html_links,
+        (description, path, quiz_name),
+        (String, PathBuf, String),
+        options.clone()
+    );
+    Ok(())
+}
+
+/// Save the questions of a quiz as "<quiz> - questions.html" for studying. Canvas only
+/// shows them to students for some quizzes, the others are skipped.
+async fn process_quiz_questions(
+    (url, quiz, path): (String, Quiz, PathBuf),
+    options: Arc<ProcessOptions>,
+) -> Result<()> {
+    let questions_url = format!("{}quizzes/{}/questions", url, quiz.id);
+    let pages = get_pages(questions_url, &options).await?;
+
+    let mut questions = Vec::new();
+    for pg in pages {
+        let uri = pg.url().to_string();
+        let page_body = pg.text().await?;
+        match serde_json::from_str::<QuizQuestionResult>(&page_body) {
+            Ok(QuizQuestionResult::Ok(page_questions)) => questions.extend(page_questions),
+            Ok(QuizQuestionResult::Err { status }) => {
+                tracing::debug!("No access to quiz questions at {uri} (status: {status})");
+                return Ok(());
+            }
+            Err(e) => {
+                tracing::debug!("No quiz questions found for url {uri} (error: {e})");
+                return Ok(());
+            }
+        }
+    }
+    if questions.is_empty() {
+        return Ok(());
+    }
+    questions.sort_by_key(|question| question.position);
+
+    let mut body = String::new();
+    for (i, question) in questions.iter().enumerate() {
+        body.push_str(&format_question(i + 1, question));
+    }
+    let quiz_name = format!(
+        "{} - questions",
+        sanitize_path_component(&quiz.title, options.filename_mode)
+    );
+    let questions_html = format!(
+        "<html><head><title>{}</title></head><body><h1>{}</h1>{}</body></html>",
+        html_escape(&quiz.title),
+        html_escape(&quiz.title),
+        body
+    );
+    let questions_html_path = flatten_path(&path.join(format!("{}.html", quiz_name)), &options);
+    save_content(&questions_html_path, &questions_html, &options).await?;
+
+    fork!(
+        process_html_links,
+        (body, path, quiz_name),
+        (String, PathBuf, String),
+        options.clone()
+    );
+    Ok(())
+}
+
+fn format_question(number: usize, question: &QuizQuestion) -> String {
+    let mut html = format!("<h2>Question {number}</h2>");
+    if let Some(points) = question.points_possible {
+        html.push_str(&format!("<p><em>{points} pts</em></p>"));
+    }
+    html.push_str(&format!("<div>{}</div>", question.question_text));
+
+    match question.question_type.as_str() {
+        "multiple_choice_question" | "true_false_question" | "multiple_answers_question" => {
+            html.push_str("<ul>");
+            for answer in &question.answers {
+                let correct = if answer.weight.is_some_and(|weight| weight > 0.0) {
+                    " <strong>(correct)</strong>"
+                } else {
+                    ""
+                };
+                html.push_str(&format!("<li>{}{correct}</li>", answer_html(answer)));
+            }
+            html.push_str("</ul>");
+        }
+        "short_answer_question" | "numerical_question" => {
+            // The answers are the accepted ones, only sent when the user may see them
+            let accepted = question
+                .answers
+                .iter()
+                .map(|answer| numerical_answer(answer).unwrap_or_else(|| answer_html(answer)))
+                .filter(|answer| !answer.is_empty())
+                .collect::<Vec<_>>();
+            if !accepted.is_empty() {
+                html.push_str(&format!("<p>Accepted answers: {}</p>", accepted.join(", ")));
+            }
+        }
+        _ => {}
+    }
+    html
+}
+
+fn answer_html(answer: &QuizAnswer) -> String {
+    match (&answer.html, &answer.text) {
+        (Some(html), _) if !html.trim().is_empty() => html.clone(),
+        (_, Some(text)) => html_escape(text),
+        _ => String::new(),
+    }
+}
+
+/// Accepted value of a numerical question's answer, which has no text: "3 ± 0.5",
+/// "between 1 and 2" or "3.14 (3 significant digits)"
+fn numerical_answer(answer: &QuizAnswer) -> Option<String> {
+    match answer.numerical_answer_type.as_deref()? {
+        "exact_answer" => {
+            let exact = answer.exact?;
+            Some(match answer.margin {
+                Some(margin) if margin != 0.0 => format!("{exact} ± {margin}"),
+                _ => exact.to_string(),
+            })
+        }
+        "range_answer" => Some(format!("between {} and {}", answer.start?, answer.end?)),
+        "precision_answer" => {
+            let approximate = answer.approximate?;
+            Some(match answer.precision {
+                Some(precision) => format!("{approximate} ({precision} significant digits)"),
+                None => approximate.to_string(),
+            })
+        }
+        _ => None,
+    }
+}
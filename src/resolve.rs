@@ -0,0 +1,115 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use lazy_regex::regex_captures;
+
+use crate::api::get_canvas_api;
+use crate::assignments::process_assignment;
+use crate::canvas::{File, ProcessOptions};
+use crate::discussions::process_discussion_topic;
+use crate::files::filter_files;
+use crate::pages::process_page;
+use crate::utils::create_folder_if_not_exist_or_ignored;
+
+/// A single course item a Canvas web link points at, see --url
+#[derive(Clone, Debug)]
+pub enum CanvasItem {
+    Page(String), // the page's name in links, e.g. "week-1"
+    Assignment(u32),
+    File(u32),
+    Discussion(u32),
+    Announcement(u32),
+}
+
+/// Course id and item of a link like https://canvas.example.edu/courses/123/pages/week-1,
+/// or None if the link is on another Canvas instance than `canvas_url`
+pub fn resolve_url(url: &str, canvas_url: &str) -> Result<Option<(u32, CanvasItem)>> {
+    let link = reqwest::Url::parse(url).with_context(|| format!("Invalid URL {url}"))?;
+    let canvas = reqwest::Url::parse(canvas_url)?;
+    if link.host_str() != canvas.host_str() {
+        return Ok(None);
+    }
+    // Canvas hosted under a path, like https://host/canvas, has it before /courses
+    let path = link
+        .path()
+        .strip_prefix(canvas.path().trim_end_matches('/'))
+        .unwrap_or(link.path());
+    let Some((_, course_id, kind, item)) = regex_captures!(
+        r"^/courses/(\d+)/(pages|assignments|files|discussion_topics|announcements)/([^/]+)",
+        path
+    ) else {
+        anyhow::bail!(
+            "Can't tell what {url} links to, expected a course page, assignment, file, discussion or announcement"
+        );
+    };
+    let course_id = course_id.parse()?;
+    let item = if kind == "pages" {
+        CanvasItem::Page(item.to_string())
+    } else {
+        let id = item
+            .parse()
+            .with_context(|| format!("Expected an item id in {url}, found {item}"))?;
+        match kind {
+            "assignments" => CanvasItem::Assignment(id),
+            "files" => CanvasItem::File(id),
+            "announcements" => CanvasItem::Announcement(id),
+            _ => CanvasItem::Discussion(id),
+        }
+    };
+    Ok(Some((course_id, item)))
+}
+
+/// Download one item into the course folder at `path`, like a full sync would save it.
+/// `url` is the course's API link.
+pub async fn process_item(
+    (url, item, path): (String, CanvasItem, PathBuf),
+    options: Arc<ProcessOptions>,
+) -> Result<()> {
+    match item {
+        CanvasItem::Page(slug) => fork!(
+            process_page,
+            (url, slug, path),
+            (String, String, PathBuf),
+            options.clone()
+        ),
+        CanvasItem::Assignment(id) => fork!(
+            process_assignment,
+            (url, id, path),
+            (String, u32, PathBuf),
+            options.clone()
+        ),
+        CanvasItem::Discussion(id) => fork!(
+            process_discussion_topic,
+            (url, id, false, path),
+            (String, u32, bool, PathBuf),
+            options.clone()
+        ),
+        CanvasItem::Announcement(id) => fork!(
+            process_discussion_topic,
+            (url, id, true, path),
+            (String, u32, bool, PathBuf),
+            options.clone()
+        ),
+        CanvasItem::File(id) => {
+            let file_url = format!("{url}files/{id}");
+            let file = get_canvas_api(file_url.clone(), &options)
+                .await?
+                .json::<File>()
+                .await
+                .with_context(|| format!("Error when getting file info at link:{file_url}"))?;
+            // Its folder isn't looked up, so it goes right into files/
+            let files_path = path.join("files");
+            if !create_folder_if_not_exist_or_ignored(&files_path, &options)? {
+                return Ok(());
+            }
+            let mut filtered_files = filter_files(&options, &files_path, vec![file]);
+            options
+                .files_to_download
+                .lock()
+                .await
+                .append(&mut filtered_files);
+        }
+    }
+    Ok(())
+}
@@ -7,6 +7,9 @@ use anyhow::{Context, Result};
 
 use crate::api::get_canvas_api;
 use crate::canvas::{ProcessOptions, Syllabus};
+use crate::html::{process_html_links, save_content};
+#[cfg(feature = "pdf")]
+use crate::pdf::{CourseSection, add_to_course_pdf};
 use crate::utils::{get_raw_json_path, prettify_json};
 
 pub async fn process_syllabus(
@@ -16,8 +19,7 @@ pub async fn process_syllabus(
     // Get syllabus from Canvas API
     let syllabus_url = format!(
         "{}/api/v1/courses/{}?include[]=syllabus_body",
-        options.canvas_url.trim_end_matches('/'),
-        course_id
+        options.canvas_url, course_id
     );
 
     let syllabus_resp = get_canvas_api(syllabus_url, &options).await?;
@@ -58,16 +60,22 @@ pub async fn process_syllabus(
                     );
 
                     let syllabus_html_path = path.join("syllabus.html");
-                    let mut html_file = std::fs::File::create(syllabus_html_path.clone())
-                        .with_context(|| {
-                            format!("Unable to create file for {:?}", syllabus_html_path)
-                        })?;
+                    save_content(&syllabus_html_path, &syllabus_html, &options).await?;
+                    #[cfg(feature = "pdf")]
+                    add_to_course_pdf(
+                        CourseSection::Syllabus,
+                        &syllabus_html_path,
+                        &syllabus_html,
+                        &options,
+                    );
 
-                    html_file
-                        .write_all(syllabus_html.as_bytes())
-                        .with_context(|| {
-                            format!("Could not write to file {:?}", syllabus_html_path)
-                        })?;
+                    // Instructors often link the actual syllabus PDF and schedule in the body
+                    fork!(
+                        process_html_links,
+                        (body.clone(), path.clone(), "syllabus_files".to_string()),
+                        (String, PathBuf, String),
+                        options.clone()
+                    );
 
                     tracing::debug!("📜 Syllabus synced for {}", syllabus.course_code);
                     options.n_syllabi.fetch_add(1, Ordering::Relaxed);
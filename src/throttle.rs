@@ -0,0 +1,49 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Token bucket shared by all downloads to cap the aggregate transfer rate
+pub struct RateLimiter {
+    bytes_per_sec: f64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64, // may go negative, which is debt paid off by sleeping
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        RateLimiter {
+            bytes_per_sec: bytes_per_sec as f64,
+            bucket: Mutex::new(Bucket {
+                tokens: bytes_per_sec as f64,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Reserve `bytes` and wait until the rate limit allows them
+    pub async fn acquire(&self, bytes: u64) {
+        let wait_time = {
+            let mut bucket = self
+                .bucket
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+            // Allow bursts of at most one second worth of bytes
+            bucket.tokens = (bucket.tokens + elapsed * self.bytes_per_sec).min(self.bytes_per_sec);
+            bucket.last_refill = now;
+            bucket.tokens -= bytes as f64;
+            if bucket.tokens < 0.0 {
+                Duration::from_secs_f64(-bucket.tokens / self.bytes_per_sec)
+            } else {
+                Duration::ZERO
+            }
+        };
+        if !wait_time.is_zero() {
+            tokio::time::sleep(wait_time).await;
+        }
+    }
+}
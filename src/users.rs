@@ -1,4 +1,3 @@
-use std::io::Write;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
@@ -7,7 +6,11 @@ use anyhow::{Context, Result};
 
 use crate::api::get_pages;
 use crate::canvas::ProcessOptions;
-use crate::utils::{get_raw_json_path, prettify_json};
+use crate::files::{filter_files, prepare_link_for_download};
+use crate::utils::{get_raw_json_path, write_json_array};
+
+/// Path of the placeholder image Canvas reports for users without an avatar
+const DEFAULT_AVATAR_PATH: &str = "/images/messages/avatar-";
 
 pub async fn process_users(
     (url, parent_path): (String, PathBuf),
@@ -29,17 +32,48 @@ pub async fn process_users(
         &options.base_path,
         options.save_json,
     )? {
-        let users_path_str = users_path.to_string_lossy();
-        let mut users_file = std::fs::File::create(users_path.clone())
-            .with_context(|| format!("Unable to create file for {:?}", users_path_str))?;
+        let mut users = Vec::new();
+        let mut avatars = std::collections::BTreeMap::new(); // avatar url -> user id
 
         for pg in pages {
+            let uri = pg.url().to_string();
             let page_body = pg.text().await?;
+            let page_users = match serde_json::from_str::<Vec<serde_json::Value>>(&page_body) {
+                Ok(page_users) => page_users,
+                Err(e) => {
+                    // e.g. {"status": "unauthorized"} when the roster is hidden from students
+                    tracing::warn!("Could not get users at link:{uri}\n{e:?}");
+                    continue;
+                }
+            };
+
+            if options.download_avatars {
+                avatars.extend(page_users.iter().filter_map(|user| {
+                    let id = user.get("id")?.as_u64()?;
+                    let avatar_url = user.get("avatar_url")?.as_str()?;
+                    Some((avatar_url.to_string(), id))
+                }));
+            }
+            users.extend(page_users);
+        }
+        write_json_array(&users_path, &users)?;
 
-            let pretty_json = prettify_json(&page_body).unwrap_or(page_body.clone());
-            users_file
-                .write_all(pretty_json.as_bytes())
-                .with_context(|| format!("Unable to write to file for {:?}", users_path_str))?;
+        // Avatars go into avatars/ next to users.json, skipping Canvas' default placeholder
+        if !avatars.is_empty() {
+            let avatars_path = users_path.with_file_name("avatars");
+            std::fs::create_dir_all(&avatars_path)
+                .with_context(|| format!("Failed to create directory: {:?}", avatars_path))?;
+            for (avatar_url, user_id) in avatars {
+                if avatar_url.contains(DEFAULT_AVATAR_PATH) {
+                    continue;
+                }
+                fork!(
+                    process_avatar,
+                    (avatar_url, user_id, avatars_path.clone()),
+                    (String, u64, PathBuf),
+                    options.clone()
+                );
+            }
         }
 
         tracing::debug!(
@@ -54,3 +88,23 @@ pub async fn process_users(
 
     Ok(())
 }
+
+/// Queue one avatar as avatars/<user id>.<ext>
+async fn process_avatar(
+    (avatar_url, user_id, avatars_path): (String, u64, PathBuf),
+    options: Arc<ProcessOptions>,
+) -> Result<()> {
+    let mut file =
+        prepare_link_for_download((avatar_url, avatars_path.clone()), options.clone()).await?;
+    file.display_name = match std::path::Path::new(&file.display_name).extension() {
+        Some(ext) => format!("{user_id}.{}", ext.to_string_lossy()),
+        None => user_id.to_string(),
+    };
+    let mut filtered_files = filter_files(&options, &avatars_path, vec![file]);
+    options
+        .files_to_download
+        .lock()
+        .await
+        .append(&mut filtered_files);
+    Ok(())
+}
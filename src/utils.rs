@@ -1,8 +1,70 @@
-use crate::canvas::{Course, ProcessOptions};
+use crate::canvas::{Course, CourseStats, FilenameMode, ProcessOptions};
 use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use percent_encoding::{AsciiSet, CONTROLS};
+use serde::Deserialize;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+/// Full path of an executable in PATH
+pub fn find_in_path(name: &str) -> Option<PathBuf> {
+    let exe_name = format!("{name}{}", std::env::consts::EXE_SUFFIX);
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(&exe_name))
+        .find(|path| path.is_file())
+}
+
+/// Canvas URL without a trailing slash or API path, keeping the path of installs hosted
+/// under one: "https://host/canvas/" and "https://host/canvas/api/v1" give "https://host/canvas"
+pub fn normalize_canvas_url(canvas_url: &str) -> Result<String> {
+    let mut url = reqwest::Url::parse(canvas_url.trim())
+        .with_context(|| format!("Invalid Canvas URL: {canvas_url}"))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        anyhow::bail!("Canvas URL must start with https:// or http://: {canvas_url}");
+    }
+    url.set_query(None);
+    url.set_fragment(None);
+    let url = url.as_str().trim_end_matches('/');
+    let url = url.strip_suffix("/api/v1").unwrap_or(url);
+    Ok(url.trim_end_matches('/').to_string())
+}
+
+/// Set by --json, see the status! macro
+pub static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/// Set on the first Ctrl-C; downloads that haven't started yet are skipped
+pub static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+tokio::task_local! {
+    /// Course whose content the current task discovers; fork! passes it on to the tasks it
+    /// spawns, which then share the course's --course-concurrency limit
+    pub static COURSE: u32;
+}
+
+/// Characters in a file name that would end or change the meaning of a relative link
+pub const HREF_ESCAPES: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'%').add(b'?');
+
+/// Path of `to` as seen from the folder `from`, e.g. "../files/a.pdf"; both must share a base
+pub fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from = from.components().collect::<Vec<_>>();
+    let to = to.components().collect::<Vec<_>>();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    std::iter::repeat_n(std::path::Component::ParentDir, from.len() - common)
+        .chain(to[common..].iter().copied())
+        .collect()
+}
+
+/// Text escaped for use in HTML content or a quoted attribute
+pub fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
 
 pub fn print_all_courses_by_term(courses: &[Course]) {
     let mut grouped_courses: HashMap<u32, Vec<(&str, &str)>> = HashMap::new();
@@ -24,13 +86,13 @@ pub fn print_all_courses_by_term(courses: &[Course]) {
         .max(12); // At least 12 for "Course Code" header
 
     // Print header
-    println!(
+    status!(
         "{:<10} | {:<width$} | Course Name",
         "Term ID",
         "Course Code",
         width = max_code_width
     );
-    println!("{}", "-".repeat(10 + 3 + max_code_width + 3 + 40));
+    status!("{}", "-".repeat(10 + 3 + max_code_width + 3 + 40));
 
     // Sort by term ID for consistent output
     let mut term_ids: Vec<_> = grouped_courses.keys().collect();
@@ -40,7 +102,7 @@ pub fn print_all_courses_by_term(courses: &[Course]) {
         let courses_in_term = &grouped_courses[term_id];
         for (i, (code, name)) in courses_in_term.iter().enumerate() {
             if i == 0 {
-                println!(
+                status!(
                     "{:<10} | {:<width$} | {}",
                     term_id,
                     code,
@@ -48,7 +110,7 @@ pub fn print_all_courses_by_term(courses: &[Course]) {
                     width = max_code_width
                 );
             } else {
-                println!(
+                status!(
                     "{:<10} | {:<width$} | {}",
                     "",
                     code,
@@ -60,9 +122,245 @@ pub fn print_all_courses_by_term(courses: &[Course]) {
 
         // Add separator line between terms (but not after the last one)
         if term_idx < term_ids.len() - 1 {
-            println!("{}", "-".repeat(10 + 3 + max_code_width + 3 + 40));
+            status!("{}", "-".repeat(10 + 3 + max_code_width + 3 + 40));
+        }
+    }
+}
+
+/// List courses grouped by term, numbered, and ask which ones to download.
+/// Accepts numbers and ranges like "1 3 5-7"; an empty answer selects nothing.
+pub fn pick_courses_interactively(courses: &[Course]) -> Result<Vec<&Course>> {
+    let mut sorted_courses: Vec<&Course> = courses.iter().collect();
+    sorted_courses.sort_by_key(|course| course.enrollment_term_id);
+
+    let mut last_term_id = None;
+    for (i, course) in sorted_courses.iter().enumerate() {
+        if last_term_id != Some(course.enrollment_term_id) {
+            status!("Term {}:", course.enrollment_term_id);
+            last_term_id = Some(course.enrollment_term_id);
+        }
+        status!("  [{:>2}] {} - {}", i + 1, course.course_code, course.name);
+    }
+
+    loop {
+        status_prompt!("Select courses to download (e.g. 1 3 5-7, empty to cancel): ")?;
+        let mut input = String::new();
+        if std::io::stdin()
+            .read_line(&mut input)
+            .context("Failed to read selection")?
+            == 0
+        {
+            return Ok(vec![]);
+        }
+
+        match parse_selection(&input, sorted_courses.len()) {
+            Ok(selection) => {
+                return Ok(selection.into_iter().map(|i| sorted_courses[i]).collect());
+            }
+            Err(e) => status!("{e}"),
+        }
+    }
+}
+
+/// Parse "1 3 5-7" / "1,3,5-7" into sorted, deduplicated 0-based indices below `len`
+fn parse_selection(input: &str, len: usize) -> Result<Vec<usize>, String> {
+    let mut selection = Vec::new();
+    for part in input
+        .split([',', ' ', '\t', '\n', '\r'])
+        .filter(|p| !p.is_empty())
+    {
+        let (start, end) = part.split_once('-').unwrap_or((part, part));
+        let (Ok(start), Ok(end)) = (start.trim().parse::<usize>(), end.trim().parse::<usize>())
+        else {
+            return Err(format!("Invalid selection: {part:?}"));
+        };
+        if start == 0 || end > len || start > end {
+            return Err(format!("Selection out of range 1-{len}: {part:?}"));
+        }
+        selection.extend(start - 1..end);
+    }
+    selection.sort_unstable();
+    selection.dedup();
+    Ok(selection)
+}
+
+/// Client settings shared by the Canvas client and the per-session Panopto clients.
+/// `keepalive` is the TCP keep-alive interval, None to turn it off.
+pub fn client_builder(
+    proxy: Option<&reqwest::Proxy>,
+    http1_only: bool,
+    keepalive: Option<Duration>,
+) -> reqwest::ClientBuilder {
+    let user_agent = format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    let builder = reqwest::ClientBuilder::new()
+        .user_agent(user_agent)
+        .tcp_keepalive(keepalive);
+    let builder = if http1_only {
+        builder.http1_only()
+    } else {
+        builder.http2_keep_alive_interval(Some(Duration::from_secs(2)))
+    };
+    match proxy {
+        Some(proxy) => builder.proxy(proxy.clone()),
+        None => builder,
+    }
+}
+
+/// Placeholders accepted by --layout
+const LAYOUT_PLACEHOLDERS: [&str; 4] = ["term", "course_code", "course_name", "course_id"];
+
+/// Folder a course is downloaded into: its destination from --courses-file, the --layout
+/// template expanded under the destination folder, or just the course code
+pub fn course_folder(course: &Course, options: &ProcessOptions) -> PathBuf {
+    if let Some(destination) = options.course_destinations.get(&course.id) {
+        return options.base_path.join(destination);
+    }
+    let Some(ref layout) = options.course_layout else {
+        return options.base_path.join(course.course_code.replace('/', "_"));
+    };
+    let term = course
+        .term
+        .as_ref()
+        .map_or_else(|| course.enrollment_term_id.to_string(), |t| t.name.clone());
+    let mut path = options.base_path.clone();
+    for component in layout.split('/') {
+        let expanded = component
+            .replace("{term}", &term)
+            .replace("{course_code}", &course.course_code)
+            .replace("{course_name}", &course.name)
+            .replace("{course_id}", &course.id.to_string());
+        path.push(sanitize_path_component(&expanded, options.filename_mode));
+    }
+    path
+}
+
+/// Check a --layout template like "{term}/{course_code} - {course_name}"
+pub fn parse_layout(s: &str) -> Result<String, String> {
+    let layout = s.trim_matches('/');
+    if layout
+        .split('/')
+        .any(|component| component.trim().is_empty())
+    {
+        return Err(format!("Invalid layout {s:?}, it has an empty folder name"));
+    }
+    for placeholder in lazy_regex::regex!(r"\{([^}]*)\}").captures_iter(layout) {
+        let name = &placeholder[1];
+        if !LAYOUT_PLACEHOLDERS.contains(&name) {
+            return Err(format!(
+                "Unknown placeholder {{{name}}} in layout, expected one of {}",
+                LAYOUT_PLACEHOLDERS.map(|p| format!("{{{p}}}")).join(", ")
+            ));
         }
     }
+    Ok(layout.to_string())
+}
+
+/// Longest file or folder name we create, leaving room under Windows' 260-char MAX_PATH
+const MAX_COMPONENT_LEN: usize = 120;
+
+/// Device names Windows refuses as a basename, with or without an extension
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Turn a Canvas title into a single path component. In the default safe mode it is also
+/// valid on Windows: illegal characters are removed, reserved names get a trailing "_"
+/// ("CON" -> "CON_"). Overly long names are truncated with a hash suffix to keep them unique.
+pub fn sanitize_path_component(name: &str, mode: FilenameMode) -> String {
+    let name = match mode {
+        FilenameMode::Safe => sanitize_for_windows(name),
+        FilenameMode::Preserve => name
+            .chars()
+            .filter(|c| !c.is_control() && !matches!(c, '/' | '\\'))
+            .collect::<String>()
+            .trim()
+            .to_string(),
+        FilenameMode::Ascii => sanitize_for_windows(&deunicode::deunicode(name)),
+    };
+    if name.is_empty() || name == "." || name == ".." {
+        return "_".to_string();
+    }
+    shorten_path_component(&name)
+}
+
+fn sanitize_for_windows(name: &str) -> String {
+    let stem_len = name.find('.').unwrap_or(name.len());
+    let stem = name[..stem_len].trim_end();
+    let name = if WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        format!("{stem}_{}", &name[stem_len..])
+    } else {
+        name.to_string()
+    };
+    sanitize_filename::sanitize(name)
+}
+
+/// Truncate a name longer than MAX_COMPONENT_LEN, keeping its extension,
+/// e.g. "<long title>.pdf" -> "<truncated title>~1a2b3c4d.pdf"
+pub fn shorten_path_component(name: &str) -> String {
+    if name.len() <= MAX_COMPONENT_LEN {
+        return name.to_string();
+    }
+
+    let suffix = format!("~{:08x}", stable_hash(name));
+
+    let ext = match name.rfind('.') {
+        Some(idx) if idx > 0 && name.len() - idx <= 16 => &name[idx..],
+        _ => "",
+    };
+    let mut stem_len = MAX_COMPONENT_LEN - suffix.len() - ext.len();
+    while !name.is_char_boundary(stem_len) {
+        stem_len -= 1;
+    }
+    format!("{}{suffix}{ext}", name[..stem_len].trim_end())
+}
+
+/// FNV-1a, so names derived from it stay the same across runs and Rust versions
+pub fn stable_hash(s: &str) -> u32 {
+    s.bytes().fold(0x811c9dc5_u32, |hash, b| {
+        (hash ^ b as u32).wrapping_mul(0x01000193)
+    })
+}
+
+pub fn print_course_summary(courses: &[&Course], options: &ProcessOptions) {
+    let course_stats = options
+        .course_stats
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    let max_code_width = courses
+        .iter()
+        .map(|c| c.course_code.len())
+        .max()
+        .unwrap_or(12)
+        .max(12); // At least 12 for "Course Code" header
+
+    status!(
+        "{:<width$} | {:>6} | {:>10} | {:>6}",
+        "Course Code",
+        "Files",
+        "Size",
+        "Errors",
+        width = max_code_width
+    );
+    status!("{}", "-".repeat(max_code_width + 3 + 6 + 3 + 10 + 3 + 6));
+    for course in courses {
+        let default_stats = CourseStats::default();
+        let stats = course_stats
+            .get(&course_folder(course, options))
+            .unwrap_or(&default_stats);
+        status!(
+            "{:<width$} | {:>6} | {:>10} | {:>6}",
+            course.course_code,
+            stats.files_downloaded,
+            format_bytes(stats.bytes_downloaded),
+            stats.errors,
+            width = max_code_width
+        );
+    }
 }
 
 pub fn ignored(
@@ -86,6 +384,35 @@ pub fn ignored(
     ignored
 }
 
+/// Normalize user-supplied extensions: lowercase, no leading dot, empty entries dropped
+pub fn normalize_extensions(exts: &[String]) -> Vec<String> {
+    exts.iter()
+        .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+        .filter(|ext| !ext.is_empty())
+        .collect()
+}
+
+/// Check a file path against the --include-ext/--exclude-ext filters.
+/// Exclusion wins over inclusion; files without an extension are never dropped by --include-ext.
+pub fn extension_allowed(filepath: &Path, options: &ProcessOptions) -> bool {
+    let ext = match filepath.extension() {
+        Some(ext) => ext.to_string_lossy().to_lowercase(),
+        None => return true,
+    };
+    if options.exclude_ext.contains(&ext) {
+        tracing::debug!("Excluding by extension: {}", filepath.display());
+        return false;
+    }
+    let included = options
+        .include_ext
+        .as_ref()
+        .is_none_or(|include_ext| include_ext.contains(&ext));
+    if !included {
+        tracing::debug!("Not in included extensions: {}", filepath.display());
+    }
+    included
+}
+
 fn create_folder_if_not_exist(folder_path: &Path) -> Result<()> {
     std::fs::create_dir_all(folder_path).with_context(|| {
         format!(
@@ -96,6 +423,110 @@ fn create_folder_if_not_exist(folder_path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// With --flatten, map a path nested below a course's content folder onto a single name in
+/// that folder, e.g. "CS101/modules/Week 1/slides.pdf" -> "CS101/modules/Week 1__slides.pdf"
+pub fn flatten_path(path: &Path, options: &ProcessOptions) -> PathBuf {
+    if !options.flatten {
+        return path.to_path_buf();
+    }
+    // Course folders and content folders stay real folders
+    let Some(course_folder) = enclosing_course_folder(path, options) else {
+        return path.to_path_buf();
+    };
+    let Ok(relative_path) = path.strip_prefix(&course_folder) else {
+        return path.to_path_buf();
+    };
+    let components = relative_path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>();
+    if components.len() <= 2 {
+        return path.to_path_buf();
+    }
+
+    let (content_folder, nested) = components.split_at(1);
+    let mut flat_path = course_folder.join(content_folder[0].as_ref());
+    flat_path.push(shorten_path_component(&nested.join("__")));
+    flat_path
+}
+
+/// With --group-by-type, move a file below a course's content folders into the course's
+/// folder for its type, joining the folders it was in into its name like --flatten,
+/// e.g. "CS101/files/Week 1/intro.pptx" -> "CS101/slides/Week 1__intro.pptx"
+pub fn group_by_type_path(
+    path: &Path,
+    content_type: Option<&str>,
+    options: &ProcessOptions,
+) -> PathBuf {
+    if !options.group_by_type {
+        return path.to_path_buf();
+    }
+    let Some(course_folder) = enclosing_course_folder(path, options) else {
+        return path.to_path_buf();
+    };
+    let Ok(relative_path) = path.strip_prefix(&course_folder) else {
+        return path.to_path_buf();
+    };
+    let components = relative_path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>();
+    if components.len() < 2 {
+        return path.to_path_buf();
+    }
+
+    // The content folder (files, modules, ...) is replaced by the type folder
+    let mut grouped_path = course_folder.join(type_folder(path, content_type));
+    grouped_path.push(shorten_path_component(&components[1..].join("__")));
+    grouped_path
+}
+
+/// Type folder for --group-by-type, by extension or else by MIME type
+fn type_folder(path: &Path, content_type: Option<&str>) -> &'static str {
+    let ext = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "pdf" | "doc" | "docx" | "odt" | "rtf" | "txt" | "md" | "tex" | "epub" | "pages" => {
+            return "documents";
+        }
+        "ppt" | "pptx" | "pps" | "ppsx" | "odp" | "key" => return "slides",
+        "xls" | "xlsx" | "ods" | "csv" | "numbers" => return "spreadsheets",
+        // Not "ts", which is as likely TypeScript as an MPEG stream; the MIME type decides
+        "mp4" | "m4v" | "mov" | "mkv" | "avi" | "webm" | "wmv" => return "videos",
+        "mp3" | "m4a" | "wav" | "flac" | "ogg" | "aac" => return "audio",
+        "png" | "jpg" | "jpeg" | "gif" | "svg" | "webp" | "bmp" | "tif" | "tiff" | "heic" => {
+            return "images";
+        }
+        "zip" | "rar" | "7z" | "tar" | "gz" | "tgz" | "bz2" | "xz" => return "archives",
+        "py" | "ipynb" | "java" | "c" | "cpp" | "h" | "hpp" | "rs" | "js" | "r" | "m" | "sql" => {
+            return "code";
+        }
+        _ => {}
+    }
+    match content_type.unwrap_or_default() {
+        "application/pdf" => "documents",
+        mime if mime.starts_with("video/") => "videos",
+        mime if mime.starts_with("audio/") => "audio",
+        mime if mime.starts_with("image/") => "images",
+        mime if mime.starts_with("text/") => "documents",
+        _ => "other",
+    }
+}
+
+/// Course folder a path lies in; main() registers every course folder in course_stats
+/// before discovery
+pub fn enclosing_course_folder(path: &Path, options: &ProcessOptions) -> Option<PathBuf> {
+    let course_stats = options
+        .course_stats
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    path.ancestors()
+        .find(|folder| course_stats.contains_key(*folder))
+        .map(Path::to_path_buf)
+}
+
 // return Ok(true) if folder created or already exists, Ok(false) if ignored
 pub fn create_folder_if_not_exist_or_ignored(
     folder_path: &Path,
@@ -110,6 +541,17 @@ pub fn create_folder_if_not_exist_or_ignored(
         return Ok(false);
     }
 
+    // Folders below content folders only exist as name prefixes when flattening
+    if options.flatten
+        && enclosing_course_folder(folder_path, options).is_some_and(|course_folder| {
+            folder_path
+                .strip_prefix(course_folder)
+                .is_ok_and(|relative_path| relative_path.components().count() > 1)
+        })
+    {
+        return Ok(true);
+    }
+
     create_folder_if_not_exist(folder_path)?;
     Ok(true)
 }
@@ -119,6 +561,13 @@ pub fn prettify_json(json_str: &str) -> Result<String> {
     Ok(serde_json::to_string_pretty(&value)?)
 }
 
+/// Write the items of all pages of a paginated response as a single JSON array
+pub fn write_json_array(json_path: &Path, items: &[Value]) -> Result<()> {
+    let pretty_json = serde_json::to_string_pretty(items)?;
+    std::fs::write(json_path, pretty_json)
+        .with_context(|| format!("Unable to write to file for {:?}", json_path))
+}
+
 /// Get the path for a raw JSON file in a parallel "raw" folder structure
 /// Returns None if save_json is false
 ///
@@ -168,3 +617,168 @@ pub fn format_bytes(bytes: u64) -> String {
         format!("{:.2} {}", size, unit)
     }
 }
+
+/// Parse a human-readable size like "500M" or "2G" (1024-based) into bytes
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid size: {s:?}, expected e.g. 500M or 2G"))?;
+    let exponent = match unit
+        .trim()
+        .trim_end_matches(['B', 'b'])
+        .trim_end_matches('i')
+        .to_uppercase()
+        .as_str()
+    {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        _ => {
+            return Err(format!(
+                "Invalid size unit in {s:?}, expected one of K, M, G, T"
+            ));
+        }
+    };
+    Ok((number * 1024_f64.powi(exponent)) as u64)
+}
+
+/// A course listed in a --courses-file, optionally with its own folder
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum CourseEntry {
+    Id(u32),
+    WithDestination {
+        id: u32,
+        destination: Option<PathBuf>,
+    },
+}
+
+impl CourseEntry {
+    pub fn id(&self) -> u32 {
+        match *self {
+            CourseEntry::Id(id) | CourseEntry::WithDestination { id, .. } => id,
+        }
+    }
+
+    pub fn destination(&self) -> Option<&Path> {
+        match self {
+            CourseEntry::Id(_) => None,
+            CourseEntry::WithDestination { destination, .. } => destination.as_deref(),
+        }
+    }
+}
+
+/// Read a --courses-file: a JSON array of course ids or {"id", "destination"} objects, or one
+/// course id per line, optionally followed by a destination. Empty lines and # comments are
+/// skipped. Destinations are folders relative to the download location.
+pub fn load_courses_file(path: &Path) -> Result<Vec<CourseEntry>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read courses file {}", path.display()))?;
+
+    let entries = if content.trim_start().starts_with('[') {
+        serde_json::from_str(&content)
+            .with_context(|| format!("Courses file {} is not valid JSON", path.display()))?
+    } else {
+        let mut entries = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (id, destination) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let id = id.parse().with_context(|| {
+                format!(
+                    "Invalid course id {id:?} on line {} of {}",
+                    i + 1,
+                    path.display()
+                )
+            })?;
+            let destination = destination.trim();
+            entries.push(if destination.is_empty() {
+                CourseEntry::Id(id)
+            } else {
+                CourseEntry::WithDestination {
+                    id,
+                    destination: Some(PathBuf::from(destination)),
+                }
+            });
+        }
+        entries
+    };
+
+    for entry in &entries {
+        if let Some(destination) = entry.destination()
+            && !destination
+                .components()
+                .all(|c| matches!(c, std::path::Component::Normal(_)))
+        {
+            anyhow::bail!(
+                "Destination {} of course {} must be a folder inside the download location",
+                destination.display(),
+                entry.id()
+            );
+        }
+    }
+    Ok(entries)
+}
+
+/// Parse a --since date: RFC 3339, or YYYY-MM-DD meaning local midnight of that day
+pub fn parse_date(s: &str) -> Result<DateTime<Utc>, String> {
+    let s = s.trim();
+    if let Ok(date) = DateTime::parse_from_rfc3339(s) {
+        return Ok(date.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .and_then(|date| date.and_local_timezone(Local).earliest())
+        .map(|date| date.with_timezone(&Utc))
+        .ok_or_else(|| {
+            format!("Invalid date: {s:?}, expected e.g. 2025-09-01 or 2025-09-01T08:00:00Z")
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_canvas_url_keeps_install_path() {
+        assert_eq!(
+            normalize_canvas_url("https://host/canvas/").expect("valid url"),
+            "https://host/canvas"
+        );
+    }
+
+    #[test]
+    fn normalize_canvas_url_strips_trailing_slash() {
+        assert_eq!(
+            normalize_canvas_url("https://canvas.example.com/").expect("valid url"),
+            "https://canvas.example.com"
+        );
+    }
+
+    #[test]
+    fn normalize_canvas_url_strips_api_path() {
+        assert_eq!(
+            normalize_canvas_url("https://host/canvas/api/v1/").expect("valid url"),
+            "https://host/canvas"
+        );
+        assert_eq!(
+            normalize_canvas_url("https://canvas.example.com/api/v1").expect("valid url"),
+            "https://canvas.example.com"
+        );
+    }
+
+    #[test]
+    fn normalize_canvas_url_rejects_other_schemes() {
+        assert!(normalize_canvas_url("ftp://canvas.example.com").is_err());
+    }
+}
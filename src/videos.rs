@@ -1,59 +1,124 @@
-use std::ffi::OsStr;
+use std::collections::BTreeMap;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use anyhow::{Result, anyhow};
-use chrono::{TimeZone, Utc};
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Local, TimeZone, Utc};
+use futures::future::BoxFuture;
 use lazy_regex::regex;
-use m3u8_rs::Playlist;
+use m3u8_rs::{Playlist, VariantStream};
+use percent_encoding::utf8_percent_encode;
 use reqwest::{Url, header};
 use select::document::Document;
 use select::predicate::Name;
 use serde_json::json;
 
 use crate::api::get_canvas_api;
-use crate::canvas::{File, PanoptoDeliveryInfo, PanoptoSessionInfo, ProcessOptions, Session};
+use crate::canvas::{
+    File, PanoptoDeliveryInfo, PanoptoResult, PanoptoSessionInfo, ProcessOptions, Session,
+    VideoQuality,
+};
 use crate::files::filter_files;
-use crate::utils::{create_folder_if_not_exist_or_ignored, get_raw_json_path, prettify_json};
+use crate::list::TreeNode;
+use crate::utils::{
+    HREF_ESCAPES, client_builder, create_folder_if_not_exist_or_ignored, flatten_path,
+    get_raw_json_path, group_by_type_path, html_escape, prettify_json, sanitize_path_component,
+    write_json_array,
+};
 
 pub async fn process_videos(
     (url, id, path): (String, u32, PathBuf),
     options: Arc<ProcessOptions>,
 ) -> Result<()> {
+    let Some((panopto_host, panopto_folder_id, client)) = panopto_login(&url, id, &options).await?
+    else {
+        return Ok(());
+    };
+
+    let video_folder_path = path.join("videos");
+    if !create_folder_if_not_exist_or_ignored(&video_folder_path, &options)? {
+        return Ok(());
+    }
+    tracing::debug!(
+        "🎬 Videos synced for {}",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    );
+    options.n_videos.fetch_add(1, Ordering::Relaxed);
+
+    process_video_folder(
+        (
+            panopto_host,
+            panopto_folder_id,
+            client.clone(),
+            video_folder_path,
+        ),
+        options,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Whether the missing terms acceptance was reported, it is the same for every course
+static TERMS_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Log in to Panopto through the course's external tool. Returns the Panopto host, the
+/// course's folder ID and a client holding the session cookies, or None without Panopto.
+async fn panopto_login(
+    url: &str,
+    id: u32,
+    options: &ProcessOptions,
+) -> Result<Option<(String, String, reqwest::Client)>> {
     let session = get_canvas_api(
         format!(
             "{}/login/session_token?return_to={}/courses/{}/external_tools/128",
             url, url, id
         ),
-        &options,
+        options,
     )
     .await?;
     let session_result = session.json::<Session>().await?;
+    // The session would land on the terms page instead of the Panopto tool
+    if session_result.requires_terms_acceptance {
+        if !TERMS_WARNED.swap(true, Ordering::Relaxed) {
+            tracing::warn!(
+                "Skipping Panopto videos: Canvas asks you to accept its terms of use first. Log in to {url} in a browser once, accept them, then run again."
+            );
+        }
+        return Ok(None);
+    }
 
     // Need a new client for each session for the cookie store
-    let user_agent = format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
-    let client = reqwest::ClientBuilder::new()
-        .user_agent(user_agent)
-        .cookie_store(true)
-        .build()?;
+    let client = client_builder(
+        options.proxy.as_ref(),
+        options.http1_only,
+        options.keepalive,
+    )
+    .cookie_store(true)
+    .build()?;
     let videos = client.get(session_result.session_url).send().await?;
 
     // Parse the form that contains the parameters needed to request
     let video_html = videos.text().await?;
     let (action, params) = {
         let panopto_document = Document::from_read(video_html.as_bytes())?;
-        let panopto_form = panopto_document
-            .find(Name("form"))
-            .find(|n| n.attr("data-tool-id") == Some("mediaweb.ap.panopto.com"));
+        // Panopto tenants differ by region and school, e.g. mediaweb.ap.panopto.com
+        let panopto_form = panopto_document.find(Name("form")).find(|n| {
+            n.attr("data-tool-id")
+                .is_some_and(|tool_id| match options.panopto_host {
+                    Some(ref host) => tool_id == host,
+                    None => tool_id.ends_with("panopto.com"),
+                })
+        });
 
         // If no Panopto form found, course doesn't use Panopto
         let panopto_form = match panopto_form {
             Some(form) => form,
             None => {
                 tracing::debug!("No Panopto videos found for course");
-                return Ok(());
+                return Ok(None);
             }
         };
 
@@ -73,7 +138,7 @@ pub async fn process_videos(
     // set origin and referral headers
     let panopto_response = client
         .post(action)
-        .header("Origin", &url)
+        .header("Origin", url)
         .header("Referer", format!("{}/", url))
         .form(&params)
         .send()
@@ -98,36 +163,101 @@ pub async fn process_videos(
         .host_str()
         .ok_or(anyhow!("Could not get Panopto Host"))?
         .to_string();
+    tracing::debug!("Panopto host of course {id}: {panopto_host}");
+    Ok(Some((panopto_host, panopto_folder_id, client)))
+}
 
-    let video_folder_path = path.join("videos");
-    if !create_folder_if_not_exist_or_ignored(&video_folder_path, &options)? {
-        return Ok(());
-    }
-    tracing::debug!(
-        "🎬 Videos synced for {}",
-        path.file_name().unwrap_or_default().to_string_lossy()
-    );
-    options.n_videos.fetch_add(1, Ordering::Relaxed);
+/// One page of the sessions and subfolders of a Panopto folder, along with the raw
+/// response data for sessions.json
+async fn get_sessions_page(
+    host: &str,
+    id: &str,
+    client: &reqwest::Client,
+    page: u32,
+) -> Result<(serde_json::Value, PanoptoSessionInfo)> {
+    let sessions_result = client
+        .post(format!(
+            "https://{}/Panopto/Services/Data.svc/GetSessions",
+            host
+        ))
+        .json(&json!({
+            "queryParameters":
+            {
+                "query":null,
+                "sortColumn":1,
+                "sortAscending":false,
+                "maxResults":100,
+                "page":page,
+                "startDate":null,
+                "endDate":null,
+                "folderID":id,
+                "bookmarked":false,
+                "getFolderData":true,
+                "isSharedWithMe":false,
+                "isSubscriptionsPage":false,
+                "includeArchived":true,
+                "includeArchivedStateCount":true,
+                "sessionListOnlyArchived":false,
+                "includePlaylists":true
+            }
+        }))
+        .send()
+        .await?;
 
-    process_video_folder(
-        (
-            panopto_host,
-            panopto_folder_id,
-            client.clone(),
-            video_folder_path,
-        ),
-        options,
-    )
-    .await?;
+    let sessions_text = sessions_result.text().await?;
+    let mut folder_sessions = serde_json::from_str::<serde_json::Value>(&sessions_text)?;
+    let folder_sessions_results = folder_sessions
+        .get_mut("d")
+        .map(serde_json::Value::take)
+        .ok_or(anyhow!("Could not get Panopto Folder Sessions"))?;
+    let sessions = serde_json::from_value::<PanoptoSessionInfo>(folder_sessions_results.clone())?;
+    Ok((folder_sessions_results, sessions))
+}
 
-    Ok(())
+/// Sessions of a course's Panopto folder and its subfolders, for --list
+pub async fn list_videos(url: &str, id: u32, options: &ProcessOptions) -> Result<Vec<TreeNode>> {
+    let Some((host, folder_id, client)) = panopto_login(url, id, options).await? else {
+        return Ok(Vec::new());
+    };
+    list_video_folder(&host, folder_id, &client).await
+}
+
+// async recursion needs boxing
+fn list_video_folder<'a>(
+    host: &'a str,
+    id: String,
+    client: &'a reqwest::Client,
+) -> BoxFuture<'a, Result<Vec<TreeNode>>> {
+    Box::pin(async move {
+        let mut nodes = Vec::new();
+        for page in 0.. {
+            let (_, sessions) = get_sessions_page(host, &id, client, page).await?;
+            // Subfolders are the same on every page
+            if page == 0 {
+                for subfolder in sessions.Subfolders {
+                    let children = list_video_folder(host, subfolder.ID, client).await?;
+                    nodes.push(TreeNode::new(format!("{}/", subfolder.Name), children));
+                }
+            }
+            if sessions.Results.is_empty() {
+                break;
+            }
+            nodes.extend(
+                sessions
+                    .Results
+                    .into_iter()
+                    .map(|result| TreeNode::leaf(result.SessionName)),
+            );
+        }
+        Ok(nodes)
+    })
 }
 
 async fn process_video_folder(
     (host, id, client, path): (String, String, reqwest::Client, PathBuf),
     options: Arc<ProcessOptions>,
 ) -> Result<()> {
-    // POST json folderID: to https://mediaweb.ap.panopto.com/Panopto/Services/Data.svc/GetFolderInfo
+    // POST json folderID: to https://{host}/Panopto/Services/Data.svc/GetFolderInfo
     let folderinfo_result = client
         .post(format!(
             "https://{}/Panopto/Services/Data.svc/GetFolderInfo",
@@ -148,61 +278,19 @@ async fn process_video_folder(
         file.write_all(pretty_json.as_bytes())?;
     }
 
-    // write into sessions.json
-    let sessions_json = get_raw_json_path(
-        &path,
-        "sessions.json",
-        &options.base_path,
-        options.save_json,
-    )?;
-    let mut sessions_file = sessions_json
-        .as_ref()
-        .and_then(|p| std::fs::File::create(p.clone()).ok());
+    // sessions of all pages, written into sessions.json
+    let mut all_sessions = Vec::new();
 
     for i in 0.. {
-        let sessions_result = client
-            .post(format!(
-                "https://{}/Panopto/Services/Data.svc/GetSessions",
-                host
-            ))
-            .json(&json!({
-                "queryParameters":
-                {
-                    "query":null,
-                    "sortColumn":1,
-                    "sortAscending":false,
-                    "maxResults":100,
-                    "page":i,
-                    "startDate":null,
-                    "endDate":null,
-                    "folderID":id,
-                    "bookmarked":false,
-                    "getFolderData":true,
-                    "isSharedWithMe":false,
-                    "isSubscriptionsPage":false,
-                    "includeArchived":true,
-                    "includeArchivedStateCount":true,
-                    "sessionListOnlyArchived":false,
-                    "includePlaylists":true
-                }
-            }))
-            .send()
-            .await?;
-
-        let sessions_text = sessions_result.text().await?;
-        if let Some(ref mut file) = sessions_file {
-            let pretty_json = prettify_json(&sessions_text).unwrap_or(sessions_text.clone());
-            file.write_all(pretty_json.as_bytes())?;
+        let (mut folder_sessions_results, sessions) =
+            get_sessions_page(&host, &id, &client, i).await?;
+        if let Some(serde_json::Value::Array(page_sessions)) = folder_sessions_results
+            .get_mut("Results")
+            .map(serde_json::Value::take)
+        {
+            all_sessions.extend(page_sessions);
         }
 
-        let folder_sessions = serde_json::from_str::<serde_json::Value>(&sessions_text)?;
-        let folder_sessions_results = folder_sessions
-            .get("d")
-            .ok_or(anyhow!("Could not get Panopto Folder Sessions"))?;
-
-        let sessions =
-            serde_json::from_value::<PanoptoSessionInfo>(folder_sessions_results.clone())?;
-
         // Subfolders are the same, so process only the first request
         if i == 0 {
             for subfolder in sessions.Subfolders {
@@ -223,6 +311,13 @@ async fn process_video_folder(
             break;
         }
         for result in sessions.Results {
+            if options.video_index {
+                options
+                    .video_sessions
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .push((path.clone(), result.clone()));
+            }
             fork!(
                 process_session,
                 (host.clone(), result, client.clone(), path.clone()),
@@ -236,6 +331,15 @@ async fn process_video_folder(
             )
         }
     }
+
+    if let Some(sessions_path) = get_raw_json_path(
+        &path,
+        "sessions.json",
+        &options.base_path,
+        options.save_json,
+    )? {
+        write_json_array(&sessions_path, &all_sessions)?;
+    }
     Ok(())
 }
 
@@ -248,7 +352,7 @@ async fn process_session(
     ),
     options: Arc<ProcessOptions>,
 ) -> Result<()> {
-    // POST deliveryID: to https://mediaweb.ap.panopto.com/Panopto/Pages/Viewer/DeliveryInfo.aspx
+    // POST deliveryID: to https://{host}/Panopto/Pages/Viewer/DeliveryInfo.aspx
     let resp = client
         .post(format!(
             "https://{}/Panopto/Pages/Viewer/DeliveryInfo.aspx",
@@ -270,11 +374,58 @@ async fn process_session(
 
     let delivery_info = resp.json::<PanoptoDeliveryInfo>().await?;
 
+    let start_time = parse_start_time(&result.StartTime)?.to_rfc3339();
+
+    // Captions are optional, failing to queue them shouldn't stop the video download
+    if let Err(e) = queue_captions((&host, &result, &client, &path, &start_time), &options).await {
+        tracing::debug!("Could not get captions for {}: {e:?}", result.SessionName);
+    }
+
+    // A direct MP4 ("podcast") download is a single file and much more robust than HLS.
+    // It only comes in one quality, so use it when the highest quality is asked for.
+    let podcast_mp4 = delivery_info
+        .Delivery
+        .iter()
+        .flat_map(|delivery| &delivery.PodcastStreams)
+        .filter_map(|stream| stream.StreamUrl.as_deref())
+        .find(|url| Url::parse(url).is_ok_and(|url| url.path().ends_with(".mp4")));
+    let podcast_mp4 = match (podcast_mp4, options.video_quality) {
+        (Some(url), VideoQuality::Highest) if podcast_available(&client, url).await => Some(url),
+        (Some(_), VideoQuality::Highest) => {
+            tracing::debug!(
+                "Podcast MP4 of {} is not downloadable, using the HLS stream",
+                result.SessionName
+            );
+            None
+        }
+        _ => None,
+    };
+    if let Some(podcast_mp4) = podcast_mp4 {
+        let file = File {
+            display_name: format!("{}.mp4", result.SessionName),
+            folder_id: None,
+            id: 0,
+            size: 0,
+            url: podcast_mp4.to_string(),
+            locked_for_user: false,
+            lock_explanation: None,
+            unlock_at: None,
+            content_type: None,
+            updated_at: start_time,
+            filepath: path.clone(),
+            segments: Vec::new(),
+            remux_to_mp4: false,
+            client: Some(client.clone()),
+        };
+        let mut lock = options.files_to_download.lock().await;
+        let mut filtered_files = filter_files(&options, &path, [file].to_vec());
+        lock.append(&mut filtered_files);
+        return Ok(());
+    }
+
     let viewer_file_id = delivery_info.ViewerFileId;
     let panopto_url = Url::parse(&result.IosVideoUrl)?;
-    let panopto_cdn_host = panopto_url
-        .host_str()
-        .unwrap_or("s-cloudfront.cdn.ap.panopto.com");
+    let panopto_cdn_host = panopto_url.host_str().unwrap_or(&options.panopto_cdn_host);
     let panopto_master_m3u8 = format!(
         "https://{}/sessions/{}/{}-{}.hls/master.m3u8",
         panopto_cdn_host, result.SessionID, result.DeliveryID, viewer_file_id
@@ -284,12 +435,8 @@ async fn process_session(
     let m3u8_parser = m3u8_rs::parse_playlist_res(m3u8_text.as_bytes());
     match m3u8_parser {
         Ok(Playlist::MasterPlaylist(pl)) => {
-            // get the highest bandwidth
-            let download_variant = pl
-                .variants
-                .iter()
-                .max_by_key(|v| v.bandwidth)
-                .expect("No variants found");
+            let download_variant = select_variant(&pl.variants, options.video_quality)
+                .ok_or(anyhow!("No variants found for {}", result.SessionName))?;
 
             let panopto_index_m3u8 = format!(
                 "https://{}/sessions/{}/{}-{}.hls/{}",
@@ -300,7 +447,7 @@ async fn process_session(
                 download_variant.uri
             );
 
-            let index_m3u8_resp = client.get(panopto_index_m3u8).send().await?;
+            let index_m3u8_resp = client.get(&panopto_index_m3u8).send().await?;
             let index_m3u8_text = index_m3u8_resp.text().await?;
             let index_m3u8_parser = m3u8_rs::parse_playlist_res(index_m3u8_text.as_bytes());
             match index_m3u8_parser {
@@ -311,63 +458,264 @@ async fn process_session(
                         .split("/")
                         .next()
                         .ok_or(anyhow!("Could not get URI ID"))?;
-                    let file_uri = index_pl.segments[0].uri.clone();
-                    let file_uri_ext = Path::new(&file_uri)
-                        .extension()
-                        .unwrap_or(OsStr::new(""))
-                        .to_str()
-                        .unwrap_or("");
-                    let panopto_mp4_file = format!(
-                        "https://{}/sessions/{}/{}-{}.hls/{}/{}",
+                    let segment_base = format!(
+                        "https://{}/sessions/{}/{}-{}.hls/{}",
                         panopto_cdn_host,
                         result.SessionID,
                         result.DeliveryID,
                         viewer_file_id,
-                        uri_id,
-                        file_uri
+                        uri_id
                     );
-                    let download_file_name = if file_uri_ext.is_empty() {
-                        result.SessionName
+
+                    // Collect the distinct segment files in playback order. Byte-range playlists
+                    // point every segment at the same file, which then only needs one download.
+                    let mut segment_uris: Vec<&str> = Vec::new();
+                    if let Some(map) = index_pl.segments.first().and_then(|s| s.map.as_ref()) {
+                        segment_uris.push(&map.uri);
+                    }
+                    for segment in &index_pl.segments {
+                        if segment_uris.last() != Some(&segment.uri.as_str()) {
+                            segment_uris.push(&segment.uri);
+                        }
+                    }
+                    if index_pl.segments.is_empty() {
+                        return Err(anyhow!("No segments found for {}", result.SessionName));
+                    }
+                    // fMP4 segments come with an init section and already form an MP4,
+                    // MPEG-TS segments stay a transport stream unless they can be remuxed
+                    let is_fmp4 = index_pl.segments.iter().any(|s| s.map.is_some());
+                    let remux_to_mp4 = !is_fmp4 && options.ffmpeg.is_some();
+                    let extension = if is_fmp4 || remux_to_mp4 { "mp4" } else { "ts" };
+                    let mut segments = segment_uris
+                        .iter()
+                        .map(|uri| format!("{segment_base}/{uri}"))
+                        .collect::<Vec<String>>();
+                    // A single file downloads like any other, which also allows resuming it
+                    let panopto_video_url = if segments.len() == 1 {
+                        segments.remove(0)
                     } else {
-                        format!("{}.{}", result.SessionName, file_uri_ext)
+                        panopto_index_m3u8
                     };
-
-                    let date_match_rfc3339 = regex!(r"/Date\((\d+)\)/")
-                        .captures(&result.StartTime)
-                        .and_then(|x| x.get(1))
-                        .map(|x| x.as_str())
-                        .ok_or(anyhow!("Parse error for StartTime"))
-                        .and_then(|x| {
-                            x.parse::<i64>()
-                                .map_err(|e| anyhow!("Conversion error for StartTime: {}", e))
-                        })
-                        .and_then(|x| {
-                            Utc.timestamp_millis_opt(x)
-                                .earliest()
-                                .ok_or(anyhow!("Timestamp parse error for StartTime"))
-                        })
-                        .map(|x| x.to_rfc3339())?;
+                    let download_file_name = format!("{}.{extension}", result.SessionName);
 
                     let file = File {
                         display_name: download_file_name,
                         folder_id: None,
                         id: 0,
                         size: 0,
-                        url: panopto_mp4_file,
+                        url: panopto_video_url,
                         locked_for_user: false,
-                        updated_at: date_match_rfc3339,
+                        lock_explanation: None,
+                        unlock_at: None,
+                        content_type: None,
+                        updated_at: start_time,
                         filepath: path.clone(),
+                        segments,
+                        remux_to_mp4,
+                        client: Some(client.clone()),
                     };
                     let mut lock = options.files_to_download.lock().await;
                     let mut filtered_files = filter_files(&options, &path, [file].to_vec());
                     lock.append(&mut filtered_files);
                 }
-                Err(e) => tracing::error!("{e:?}"),
+                Err(e) => {
+                    options.record_error(anyhow!(
+                        "Could not parse the segment playlist of {}: {e:?}",
+                        result.SessionName
+                    ));
+                }
             }
         }
         Ok(Playlist::MediaPlaylist(_pl)) => {}
-        Err(e) => tracing::error!("{e:?}"),
+        Err(e) => {
+            options.record_error(anyhow!(
+                "Could not parse the playlist of {}: {e:?}",
+                result.SessionName
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a podcast MP4 can be downloaded with the session cookies. Without access Panopto
+/// answers with its login page, so the session falls back to the HLS stream then.
+async fn podcast_available(client: &reqwest::Client, url: &str) -> bool {
+    client.head(url).send().await.is_ok_and(|resp| {
+        resp.status().is_success()
+            && !resp
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|content_type| content_type.to_str().ok())
+                .is_some_and(|content_type| content_type.starts_with("text/html"))
+    })
+}
+
+/// Queue the session's captions as <session name>.srt. They need the Panopto session cookies,
+/// so they are fetched with `client`. Sessions without captions give an empty response or an
+/// HTML error page, which the download skips quietly.
+async fn queue_captions(
+    (host, result, client, path, start_time): (&str, &PanoptoResult, &reqwest::Client, &Path, &str),
+    options: &ProcessOptions,
+) -> Result<()> {
+    let url = Url::parse_with_params(
+        &format!("https://{host}/Panopto/Pages/Transcription/GenerateSRT.ashx"),
+        &[("id", result.DeliveryID.as_str()), ("language", "0")],
+    )?;
+    let file = File {
+        display_name: format!("{}.srt", result.SessionName),
+        folder_id: None,
+        id: 0,
+        size: 0,
+        url: url.to_string(),
+        locked_for_user: false,
+        lock_explanation: None,
+        unlock_at: None,
+        content_type: None,
+        updated_at: start_time.to_string(),
+        filepath: path.to_path_buf(),
+        segments: Vec::new(),
+        remux_to_mp4: false,
+        client: Some(client.clone()),
+    };
+    let mut filtered_files = filter_files(options, path, vec![file]);
+    options
+        .files_to_download
+        .lock()
+        .await
+        .append(&mut filtered_files);
+    Ok(())
+}
+
+/// Panopto gives times as "/Date(<milliseconds since epoch>)/"
+fn parse_start_time(start_time: &str) -> Result<DateTime<Utc>> {
+    regex!(r"/Date\((\d+)\)/")
+        .captures(start_time)
+        .and_then(|x| x.get(1))
+        .map(|x| x.as_str())
+        .ok_or(anyhow!("Parse error for StartTime"))
+        .and_then(|x| {
+            x.parse::<i64>()
+                .map_err(|e| anyhow!("Conversion error for StartTime: {}", e))
+        })
+        .and_then(|x| {
+            Utc.timestamp_millis_opt(x)
+                .earliest()
+                .ok_or(anyhow!("Timestamp parse error for StartTime"))
+        })
+}
+
+/// Write an index.html into each Panopto folder listing its sessions, when they started
+/// and whether they were downloaded. Runs after the downloads so the status is final.
+pub fn write_video_indexes(options: &ProcessOptions) -> Result<()> {
+    let video_sessions = options
+        .video_sessions
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let partial_videos = options
+        .partial_videos
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    let mut sessions_by_folder: BTreeMap<&Path, Vec<&PanoptoResult>> = BTreeMap::new();
+    for (folder, session) in video_sessions.iter() {
+        sessions_by_folder.entry(folder).or_default().push(session);
     }
 
+    for (folder, sessions) in sessions_by_folder {
+        let index_path = flatten_path(&folder.join("index.html"), options);
+        let mut rows = Vec::new();
+        for session in sessions {
+            // Sessions are saved as mp4 or, without remuxing, as a transport stream
+            let video_path = ["mp4", "ts"]
+                .iter()
+                .map(|extension| {
+                    let file_name = format!("{}.{extension}", session.SessionName);
+                    group_by_type_path(
+                        &flatten_path(
+                            &folder
+                                .join(sanitize_path_component(&file_name, options.filename_mode)),
+                            options,
+                        ),
+                        None,
+                        options,
+                    )
+                })
+                .find(|path| path.exists());
+            let status = match video_path {
+                Some(ref path) if partial_videos.iter().any(|(partial, _)| partial == path) => {
+                    "Downloaded with gaps"
+                }
+                Some(_) => "Downloaded",
+                None => "Not downloaded",
+            };
+            // Only link videos next to the index, flattened ones may live elsewhere
+            let name = match video_path {
+                Some(ref path) if path.parent() == index_path.parent() => format!(
+                    "<a href=\"{}\">{}</a>",
+                    html_escape(
+                        &utf8_percent_encode(
+                            &path.file_name().unwrap_or_default().to_string_lossy(),
+                            HREF_ESCAPES
+                        )
+                        .to_string()
+                    ),
+                    html_escape(&session.SessionName)
+                ),
+                _ => html_escape(&session.SessionName),
+            };
+            let start_time = parse_start_time(&session.StartTime)
+                .map(|time| {
+                    time.with_timezone(&Local)
+                        .format("%Y-%m-%d %H:%M")
+                        .to_string()
+                })
+                .unwrap_or_default();
+            rows.push(format!(
+                "        <tr><td>{name}</td><td>{start_time}</td><td>{status}</td></tr>\n"
+            ));
+        }
+
+        let mut html = String::new();
+        html.push_str("<!DOCTYPE html>\n<html>\n<head>\n");
+        html.push_str("    <meta charset=\"UTF-8\">\n");
+        html.push_str("    <title>Videos</title>\n");
+        html.push_str(r#"    <style>
+        body { font-family: system-ui, -apple-system, "Segoe UI", Arial, sans-serif; font-size: 16px; line-height: 1.5; max-width: 900px; margin: 20px auto; padding: 0 20px; }
+        table { border-collapse: collapse; width: 100%; }
+        th, td { text-align: left; padding: 6px 10px; border-bottom: 1px solid #ddd; }
+        th { background: #f9f9f9; }
+    </style>
+"#);
+        html.push_str("</head>\n<body>\n");
+        html.push_str("    <h1>Videos</h1>\n");
+        html.push_str("    <table>\n");
+        html.push_str("        <tr><th>Session</th><th>Start time</th><th>Status</th></tr>\n");
+        for row in rows {
+            html.push_str(&row);
+        }
+        html.push_str("    </table>\n</body>\n</html>");
+        std::fs::write(&index_path, html)
+            .with_context(|| format!("Could not write to file {:?}", index_path))?;
+    }
     Ok(())
 }
+
+fn select_variant(variants: &[VariantStream], quality: VideoQuality) -> Option<&VariantStream> {
+    match quality {
+        VideoQuality::Highest => variants.iter().max_by_key(|v| v.bandwidth),
+        VideoQuality::Lowest => variants.iter().min_by_key(|v| v.bandwidth),
+        VideoQuality::Height(height) => variants
+            .iter()
+            .filter_map(|v| {
+                v.resolution
+                    .as_ref()
+                    .map(|r| (v, r.height.abs_diff(height)))
+            })
+            // closest height, prefer higher bandwidth among equally close ones
+            .min_by_key(|(v, diff)| (*diff, std::cmp::Reverse(v.bandwidth)))
+            .map(|(v, _)| v)
+            // without resolution info, fall back to the best variant
+            .or_else(|| variants.iter().max_by_key(|v| v.bandwidth)),
+    }
+}